@@ -0,0 +1,65 @@
+use bevy::prelude::*;
+
+use crate::ui::ShowHelp;
+use crate::{GameState, KeyBindings, GAME_STATES};
+
+// How long the menu sits untouched before the demo starts.
+const ATTRACT_SECONDS: f32 = 8.;
+
+// Left alone on the menu for a while, the computer plays a run behind it
+// until it crashes. Any key but Esc starts a real run instead.
+pub(crate) struct AttractPlugin;
+impl Plugin for AttractPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(IdleTimer(Timer::from_seconds(ATTRACT_SECONDS, false)))
+            .on_state_enter(GAME_STATES, GameState::Menu, idle_reset.system())
+            .add_system(menu_idle.system())
+            .add_system(demo_input.system());
+    }
+}
+
+struct IdleTimer(Timer);
+
+fn idle_reset(mut idle: ResMut<IdleTimer>) {
+    idle.0.reset();
+}
+
+#[allow(clippy::too_many_arguments)]
+fn menu_idle(
+    mut gamestate: ResMut<State<GameState>>,
+    time: Res<Time>,
+    keys: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    buttons: Res<Input<GamepadButton>>,
+    show_help: Res<ShowHelp>,
+    cursor_events: Res<Events<CursorMoved>>,
+    mut cursor_reader: Local<EventReader<CursorMoved>>,
+    mut idle: ResMut<IdleTimer>,
+) {
+    // Drained every frame, so moves made elsewhere don't count later.
+    let moved = cursor_reader.iter(&cursor_events).next().is_some();
+    if *gamestate.current() != GameState::Menu {
+        return;
+    }
+    // Reading the controls counts as doing something.
+    let busy = keys.get_pressed().next().is_some()
+        || mouse.get_pressed().next().is_some()
+        || buttons.get_pressed().next().is_some()
+        || moved
+        || show_help.0;
+    if busy {
+        idle.0.reset();
+        return;
+    }
+    idle.0.tick(time.delta_seconds());
+    if idle.0.just_finished() {
+        gamestate.set_next(GameState::Demo).ok();
+    }
+}
+
+// Esc is left to go back to the menu.
+fn demo_input(keys: Res<Input<KeyCode>>, bindings: Res<KeyBindings>, mut gamestate: ResMut<State<GameState>>) {
+    if *gamestate.current() == GameState::Demo && keys.get_just_pressed().any(|key| *key != bindings.back) {
+        gamestate.set_next(GameState::Countdown).ok();
+    }
+}
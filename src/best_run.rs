@@ -0,0 +1,183 @@
+use bevy::prelude::*;
+
+use crate::snake::{snake_length, Player, SnakeSegment};
+use crate::ui::despawn_all;
+use crate::{
+    add_tick_system, arg_value, GameMode, GameState, Level, Position, Practice, Recorder, Size, Tile, DEFAULT_LEVEL,
+    GAME_STATES,
+};
+
+// Solo runs race a faint ghost of the best run so far on the same arena and
+// difficulty, moving tick for tick along the path that run's head took. It's
+// only drawn: nothing collides with it.
+pub(crate) struct BestRunPlugin;
+impl Plugin for BestRunPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(BestRun::default())
+            .add_resource(CurrentRun::default())
+            .init_resource::<GhostMaterial>()
+            .on_state_enter(GAME_STATES, GameState::Countdown, best_run_setup.system())
+            .on_state_enter(GAME_STATES, GameState::Countdown, despawn_all::<RunGhost>.system())
+            .on_state_enter(GAME_STATES, GameState::Lost, save_best_run.system())
+            // A replay starts from the menu, and has no ghost.
+            .on_state_enter(GAME_STATES, GameState::Menu, best_run_clear.system())
+            .on_state_enter(GAME_STATES, GameState::Menu, despawn_all::<RunGhost>.system());
+        add_tick_system(app, || ghost_movement.system());
+    }
+}
+
+// A run as the ghost needs it: where the head was after each tick, and how
+// long the snake was then.
+#[derive(Default)]
+struct RunPath {
+    score: u32,
+    path: Vec<(Position, u32)>,
+}
+impl RunPath {
+    // A `score <n>` line followed by one `<x> <y> <length>` line per tick.
+    fn serialize(&self) -> String {
+        let mut out = format!("score {}\n", self.score);
+        for (pos, length) in &self.path {
+            out += &format!("{} {} {}\n", pos.x, pos.y, length);
+        }
+        out
+    }
+
+    fn deserialize(data: &str) -> Option<Self> {
+        let mut lines = data.lines();
+        let score = lines.next()?.strip_prefix("score ")?.parse().ok()?;
+        let mut path = Vec::new();
+        for line in lines.filter(|l| !l.trim().is_empty()) {
+            let mut parts = line.split_whitespace();
+            let x = parts.next()?.parse().ok()?;
+            let y = parts.next()?.parse().ok()?;
+            let length = parts.next()?.parse().ok()?;
+            path.push((Position { x, y }, length));
+        }
+        Some(Self { score, path })
+    }
+}
+
+// The best run to race in the current one, if there is any to race.
+#[derive(Default)]
+struct BestRun(Option<RunPath>);
+
+// The current run, recorded in case it beats the best. Cleared when a saved
+// game is loaded in place of the run.
+#[derive(Default)]
+pub(crate) struct CurrentRun(RunPath);
+
+struct GhostMaterial(Handle<ColorMaterial>);
+impl FromResources for GhostMaterial {
+    fn from_resources(resources: &Resources) -> Self {
+        let mut materials = resources.get_mut::<Assets<ColorMaterial>>().unwrap();
+        Self(materials.add(Color::rgba(1., 1., 1., 0.2).into()))
+    }
+}
+
+struct RunGhost;
+
+// One file per arena and difficulty: the level, its size (it can be changed
+// from the command line) and the difficulty all go in the name.
+fn best_run_path(level: &Level, recorder: &Recorder) -> String {
+    format!(
+        "best-{}-{}x{}-{}.run",
+        arg_value("--level").unwrap_or_else(|| DEFAULT_LEVEL.to_string()),
+        level.width,
+        level.height,
+        recorder.difficulty.name()
+    )
+}
+
+// Runs after `game_setup`, so the `Recorder` is this run's.
+fn best_run_setup(
+    level: Res<Level>,
+    recorder: Res<Recorder>,
+    mut best: ResMut<BestRun>,
+    mut current: ResMut<CurrentRun>,
+) {
+    current.0 = RunPath::default();
+    best.0 = if recorder.mode == GameMode::Solo {
+        std::fs::read_to_string(best_run_path(&level, &recorder))
+            .ok()
+            .and_then(|data| RunPath::deserialize(&data))
+    } else {
+        None
+    };
+}
+
+fn best_run_clear(mut best: ResMut<BestRun>) {
+    best.0 = None;
+}
+
+// Runs before `game_cleanup`, so the player is still around.
+fn save_best_run(
+    level: Res<Level>,
+    recorder: Res<Recorder>,
+    practice: Res<Practice>,
+    best: Res<BestRun>,
+    mut current: ResMut<CurrentRun>,
+    players: Query<&Player>,
+) {
+    // Crashes don't end a practice run, so it's no run to race, and a loaded
+    // run's path doesn't start where the ghost would.
+    if recorder.mode != GameMode::Solo || practice.0 || recorder.loaded {
+        return;
+    }
+    let score = match players.iter().next() {
+        Some(player) => player.food,
+        None => return,
+    };
+    let best_score = best.0.as_ref().map_or(0, |best| best.score);
+    if score <= best_score {
+        return;
+    }
+    current.0.score = score;
+    let path = best_run_path(&level, &recorder);
+    if let Err(err) = std::fs::write(&path, current.0.serialize()) {
+        warn!("could not save best run to {}: {}", path, err);
+    }
+}
+
+// Added after `SnakePlugin`, so it sees the head where it ended up.
+fn ghost_movement(
+    commands: &mut Commands,
+    best: Res<BestRun>,
+    material: Res<GhostMaterial>,
+    mut current: ResMut<CurrentRun>,
+    players: Query<(Entity, &Player, &Position)>,
+    segments: Query<&SnakeSegment>,
+    ghosts: Query<Entity, With<RunGhost>>,
+) {
+    if let Some((head, _, &pos)) = players.iter().find(|(_, player, _)| player.index == 0) {
+        let length = snake_length(head, &segments) as u32;
+        current.0.path.push((pos, length));
+    }
+    for ghost in ghosts.iter() {
+        commands.despawn(ghost);
+    }
+    let best = match &best.0 {
+        Some(best) => best,
+        None => return,
+    };
+    // Once the best run has crashed, so has its ghost.
+    let tick = current.0.path.len();
+    if tick == 0 || tick > best.path.len() {
+        return;
+    }
+    // The body trails the head along its own path.
+    let (_, length) = best.path[tick - 1];
+    for &(pos, _) in best.path[..tick].iter().rev().take(length as usize) {
+        commands
+            .spawn(SpriteBundle {
+                material: material.0.clone(),
+                // Under the snakes and the food.
+                transform: Transform::from_translation(Vec3::new(0., 0., 0.9)),
+                ..Default::default()
+            })
+            .with(RunGhost)
+            .with(Tile)
+            .with(pos)
+            .with(Size::square(0.65));
+    }
+}
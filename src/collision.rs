@@ -0,0 +1,411 @@
+use bevy::prelude::*;
+use rand::Rng;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use crate::food::Food;
+use crate::leaderboard::Leaderboard;
+use crate::poison::Poison;
+use crate::snake::{body_of, snake_body, GhostEffect, Player, RenderPosition, Snake, SnakeHead, SnakeSegment};
+use crate::stress::StressSnake;
+use crate::{add_tick_system, GameState, MainCamera, Materials, MoveTimer, Position, Practice, Size, Wall, GAME_STATES};
+#[cfg(feature = "audio")]
+use crate::{AudioSettings, SoundEffects};
+
+// Turns head collisions into eat and bump events, and ends the round on a crash.
+pub(crate) struct CollisionPlugin;
+impl Plugin for CollisionPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_event::<EatEvent>()
+            .add_event::<BumpEvent>()
+            .add_event::<GameOverEvent>()
+            .add_resource(RoundResult::default())
+            .add_resource(ScreenShake::default())
+            .add_system(screen_shake.system())
+            .on_state_enter(GAME_STATES, GameState::Dying, death_setup.system())
+            .on_state_update(GAME_STATES, GameState::Dying, death_animation.system());
+        add_tick_system(app, || collision_solver.system());
+        add_tick_system(app, || bump_events_solver.system());
+        add_tick_system(app, || game_over.system());
+    }
+}
+
+// Indices of the players whose snakes crashed, set when a round ends, and
+// how each of them crashed.
+#[derive(Default)]
+pub(crate) struct RoundResult {
+    pub(crate) losers: Vec<usize>,
+    pub(crate) reasons: Vec<(usize, DeathReason)>,
+}
+
+struct ScreenShake {
+    trauma: f32,
+    intensity: f32,
+    timer: Timer,
+}
+impl Default for ScreenShake {
+    fn default() -> Self {
+        Self {
+            trauma: 0.,
+            intensity: 12.,
+            timer: Timer::new(Duration::from_millis(400), false),
+        }
+    }
+}
+
+const DEATH_FLASH_SECONDS: f32 = 0.2;
+const DEATH_FALL_SECONDS: f32 = 0.5;
+// In pixels, by the end of the fall.
+const DEATH_FALL_DISTANCE: f32 = 40.;
+
+// The crashed snakes turn red, then shrink and fall away. Each part keeps the
+// size it started from.
+struct DeathAnim {
+    timer: Timer,
+    parts: Vec<(Entity, f32, f32)>,
+}
+
+pub(crate) struct EatEvent {
+    pub(crate) eater: Entity,
+    pub(crate) eaten: Entity,
+}
+pub(crate) struct BumpEvent {
+    pub(crate) head: Entity,
+    // Whatever the head ran into; `None` for an arena edge that doesn't wrap.
+    pub(crate) wall: Option<Entity>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum DeathReason {
+    WallBump,
+    // Crossed an arena edge that doesn't wrap.
+    Edge,
+    SelfBite,
+    SnakeBite,
+    HeadOn,
+    Poisoned,
+}
+impl DeathReason {
+    pub(crate) fn description(self) -> &'static str {
+        match self {
+            Self::WallBump => "Ran into a wall",
+            Self::Edge => "Ran off the edge",
+            Self::SelfBite => "Bit its own body",
+            Self::SnakeBite => "Ran into the other snake",
+            Self::HeadOn => "Crashed head-on",
+            Self::Poisoned => "Ran over poison",
+        }
+    }
+}
+
+// Sent once for each snake that crashed, for anything that wants to react to
+// it; `game_over` is the one that ends the round.
+pub(crate) struct GameOverEvent {
+    pub(crate) player: usize,
+    pub(crate) reason: DeathReason,
+    // The crashed player's food.
+    pub(crate) score: u32,
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn collision_solver(
+    heads_positions: Query<(Entity, &Position), With<SnakeHead>>,
+    body_positions: Query<(Entity, &Position), (With<Snake>, Without<SnakeHead>)>,
+    food_positions: Query<(Entity, &Position), With<Food>>,
+    wall_positions: Query<(Entity, &Position), With<Wall>>,
+    segments: Query<&SnakeSegment>,
+    positions: Query<&Position>,
+    move_timer: Res<MoveTimer>,
+    mut ghost: ResMut<GhostEffect>,
+    mut eat_events: ResMut<Events<EatEvent>>,
+    mut bump_events: ResMut<Events<BumpEvent>>,
+) {
+    // Hash every tile once, so each head is a lookup instead of a scan.
+    // Several entities can share a tile (segments stack up while growing),
+    // and each of them still gets its own event.
+    let foods = tile_map(food_positions.iter());
+    let blockers = tile_map(body_positions.iter().chain(wall_positions.iter()).chain(heads_positions.iter()));
+    // A ghost snake's own body doesn't block it; walls and other snakes still
+    // do. Only another snake's part on one of the ghost's own tiles gets let
+    // through along with it.
+    let ghost_body: HashSet<Position> = ghost
+        .owner
+        .map(|head| snake_body(head, &segments, &positions).into_iter().skip(1).collect())
+        .unwrap_or_default();
+    for (e1, p1) in heads_positions.iter() {
+        for &e2 in foods.get(p1).into_iter().flatten() {
+            eat_events.send(EatEvent {
+                eater: e1,
+                eaten: e2,
+            });
+        }
+        // Two heads meeting on a tile both crash, each into the other.
+        let passes_through = |e2: &Entity| {
+            *e2 == e1 || (ghost.owner == Some(e1) && ghost_body.contains(p1) && body_positions.get(*e2).is_ok())
+        };
+        for &e2 in blockers.get(p1).into_iter().flatten().filter(|e2| !passes_through(e2)) {
+            bump_events.send(BumpEvent {
+                head: e1,
+                wall: Some(e2),
+            });
+        }
+    }
+    // Counted down after the checks, so the last ghost tick is still covered.
+    if ghost.owner.is_some() {
+        ghost.timer.tick(move_timer.interval());
+        if ghost.timer.finished() {
+            ghost.owner = None;
+        }
+    }
+}
+
+fn tile_map<'a>(entities: impl Iterator<Item = (Entity, &'a Position)>) -> HashMap<Position, Vec<Entity>> {
+    let mut map: HashMap<Position, Vec<Entity>> = HashMap::new();
+    for (entity, pos) in entities {
+        map.entry(*pos).or_default().push(entity);
+    }
+    map
+}
+
+#[allow(clippy::too_many_arguments)]
+fn bump_events_solver(
+    commands: &mut Commands,
+    practice: Res<Practice>,
+    bump_events: Res<Events<BumpEvent>>,
+    mut bump_reader: Local<EventReader<BumpEvent>>,
+    players: Query<&Player>,
+    // What a head can run into, besides another snake's body.
+    obstacles: Query<(Option<&Wall>, Option<&SnakeHead>, Option<&Poison>)>,
+    segments: Query<&SnakeSegment>,
+    positions: Query<&Position>,
+    stress_snakes: Query<&StressSnake>,
+    mut shake: ResMut<ScreenShake>,
+    mut game_over_events: ResMut<Events<GameOverEvent>>,
+    #[cfg(feature = "audio")] audio: Res<Audio>,
+    #[cfg(feature = "audio")] sounds: Res<SoundEffects>,
+    #[cfg(feature = "audio")] audio_settings: Res<AudioSettings>,
+) {
+    // Marked as read all the same, so they don't pile up for later.
+    let bumps = bump_reader.iter(&bump_events);
+    if practice.0 {
+        return;
+    }
+    let mut crashed = Vec::new();
+    let mut cleared = Vec::new();
+    for BumpEvent { head, wall } in bumps {
+        // A snake can run into several things at once; the first one counts.
+        if crashed.contains(head) || cleared.contains(head) {
+            continue;
+        }
+        // The stress test's snakes are nobody's, and are just cleared off
+        // the board.
+        if stress_snakes.get(*head).is_ok() {
+            for part in std::iter::once(*head).chain(body_of(*head, &segments)) {
+                commands.despawn(part);
+            }
+            cleared.push(*head);
+            continue;
+        }
+        let player = match players.get(*head) {
+            Ok(player) => player,
+            Err(_) => continue,
+        };
+        crashed.push(*head);
+        // The head is on the tile of whatever it ran into, so it bit itself
+        // if that's one of its own body's tiles.
+        let bit_itself = || {
+            let head_pos = positions.get(*head).ok();
+            snake_body(*head, &segments, &positions).iter().skip(1).any(|pos| Some(pos) == head_pos)
+        };
+        let reason = match wall.map(|wall| obstacles.get(wall)) {
+            None => DeathReason::Edge,
+            Some(Ok((Some(_), _, _))) => DeathReason::WallBump,
+            Some(_) if bit_itself() => DeathReason::SelfBite,
+            Some(Ok((_, Some(_), _))) => DeathReason::HeadOn,
+            Some(Ok((_, _, Some(_)))) => DeathReason::Poisoned,
+            Some(_) => DeathReason::SnakeBite,
+        };
+        game_over_events.send(GameOverEvent {
+            player: player.index,
+            reason,
+            score: player.food,
+        });
+    }
+    if !crashed.is_empty() {
+        #[cfg(feature = "audio")]
+        if !audio_settings.muted {
+            audio.play(sounds.crash.clone());
+        }
+        shake.trauma = 1.;
+        shake.timer.reset();
+    }
+}
+
+fn game_over(
+    mut gamestate: ResMut<State<GameState>>,
+    game_over_events: Res<Events<GameOverEvent>>,
+    mut game_over_reader: Local<EventReader<GameOverEvent>>,
+    mut result: ResMut<RoundResult>,
+) {
+    // The round ends as soon as any snake crashes; if both crash on the same
+    // tick (including head to head) it's a draw.
+    let mut reasons: Vec<(usize, DeathReason)> =
+        game_over_reader.iter(&game_over_events).map(|e| (e.player, e.reason)).collect();
+    reasons.sort_by_key(|(player, _)| *player);
+    reasons.dedup_by_key(|(player, _)| *player);
+    if reasons.is_empty() {
+        return;
+    }
+    result.losers = reasons.iter().map(|(player, _)| *player).collect();
+    result.reasons = reasons;
+    // A replay, or the demo, has nothing left to show once the snake crashes.
+    let next = if matches!(gamestate.current(), GameState::Replay | GameState::Demo) {
+        GameState::Menu
+    } else {
+        GameState::Dying
+    };
+    gamestate.set_next(next).ok();
+}
+
+fn death_setup(
+    commands: &mut Commands,
+    materials: Res<Materials>,
+    result: Res<RoundResult>,
+    players: Query<(Entity, &Player)>,
+    segments: Query<&SnakeSegment>,
+    mut parts: Query<(&Size, &mut Handle<ColorMaterial>)>,
+) {
+    let mut dying = Vec::new();
+    for (head, player) in players.iter().filter(|(_, player)| result.losers.contains(&player.index)) {
+        for part in std::iter::once(head).chain(body_of(head, &segments)) {
+            if let Ok((size, mut material)) = parts.get_mut(part) {
+                *material = materials.death_material.clone();
+                dying.push((part, size.width, size.height));
+            }
+        }
+    }
+    commands.insert_resource(DeathAnim {
+        timer: Timer::from_seconds(DEATH_FLASH_SECONDS + DEATH_FALL_SECONDS, false),
+        parts: dying,
+    });
+}
+
+fn death_animation(
+    time: Res<Time>,
+    leaderboard: Res<Leaderboard>,
+    mut gamestate: ResMut<State<GameState>>,
+    mut anim: ResMut<DeathAnim>,
+    mut parts: Query<(&mut Size, &mut RenderPosition)>,
+) {
+    anim.timer.tick(time.delta_seconds());
+    let fall = ((anim.timer.elapsed() - DEATH_FLASH_SECONDS) / DEATH_FALL_SECONDS).max(0.).min(1.);
+    for &(part, width, height) in anim.parts.iter() {
+        if let Ok((mut size, mut render_pos)) = parts.get_mut(part) {
+            size.width = width * (1. - fall);
+            size.height = height * (1. - fall);
+            // Speeding up as it goes, like something dropped.
+            render_pos.offset = Vec2::new(0., -DEATH_FALL_DISTANCE * fall * fall);
+        }
+    }
+    if anim.timer.finished() {
+        // A score good enough for the leaderboard is signed first.
+        let next = if leaderboard.signing() {
+            GameState::Leaderboard
+        } else {
+            GameState::Lost
+        };
+        gamestate.set_next(next).ok();
+    }
+}
+
+fn screen_shake(
+    time: Res<Time>,
+    mut shake: ResMut<ScreenShake>,
+    mut cameras: Query<&mut Transform, With<MainCamera>>,
+) {
+    if shake.trauma <= 0. {
+        return;
+    }
+    shake.timer.tick(time.delta_seconds());
+    shake.trauma = if shake.timer.finished() { 0. } else { shake.timer.percent_left() };
+    // Squaring the trauma makes the shake die off quickly instead of linearly.
+    let amount = shake.intensity * shake.trauma * shake.trauma;
+    let mut rng = rand::thread_rng();
+    for mut transform in cameras.iter_mut() {
+        // position_translation assumes a centered camera, so the offset is
+        // applied around the origin and goes back to zero once settled.
+        transform.translation.x = amount * rng.gen_range(-1.0..1.0);
+        transform.translation.y = amount * rng.gen_range(-1.0..1.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::{headless_app, run_ticks, spawn_player};
+    use crate::{Direction, Level};
+    use std::time::Instant;
+
+    const BENCH_LENGTH: i32 = 200;
+    const BENCH_TICKS: u32 = 1000;
+
+    // Timings only, so it's left out of the usual run:
+    // `cargo test collision_benchmark -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn collision_benchmark() {
+        let level = Level {
+            width: 30,
+            height: 30,
+            ..Default::default()
+        };
+        // Back and forth along the rows, so every part is next to the one ahead.
+        let layout: Vec<Position> = (0..BENCH_LENGTH)
+            .map(|i| {
+                let (row, col) = (i / 30, i % 30);
+                let x = if row % 2 == 0 { 29 - col } else { col };
+                Position { x, y: row }
+            })
+            .collect();
+        let mut app = headless_app(level);
+        // Runs into itself right away, and carries on.
+        app.resources.insert(Practice(true));
+        spawn_player(&mut app, 0, Direction::Up, &layout);
+        let start = Instant::now();
+        run_ticks(&mut app, BENCH_TICKS);
+        let tick_time = start.elapsed();
+
+        // The tile lookup alone, against the scan over every part it replaced.
+        let parts: Vec<(Entity, Position)> = app
+            .world
+            .query_filtered::<(Entity, &Position), With<Snake>>()
+            .map(|(part, pos)| (part, *pos))
+            .collect();
+        let heads: Vec<Position> = app.world.query_filtered::<&Position, With<SnakeHead>>().copied().collect();
+        let start = Instant::now();
+        let mut hashed = 0;
+        for _ in 0..BENCH_TICKS {
+            let tiles = tile_map(parts.iter().map(|(part, pos)| (*part, pos)));
+            hashed += heads.iter().filter_map(|head| tiles.get(head)).map(Vec::len).sum::<usize>();
+        }
+        let hash_time = start.elapsed();
+        let start = Instant::now();
+        let mut scanned = 0;
+        for _ in 0..BENCH_TICKS {
+            scanned += heads
+                .iter()
+                .map(|head| parts.iter().filter(|(_, pos)| pos == head).count())
+                .sum::<usize>();
+        }
+        let scan_time = start.elapsed();
+        assert_eq!(hashed, scanned);
+        println!(
+            "length {}, {} ticks: {:?} per tick; tile map {:?}, scan {:?}",
+            BENCH_LENGTH,
+            BENCH_TICKS,
+            tick_time / BENCH_TICKS,
+            hash_time / BENCH_TICKS,
+            scan_time / BENCH_TICKS
+        );
+    }
+}
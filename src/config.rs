@@ -0,0 +1,67 @@
+use bevy::prelude::*;
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+
+use crate::leaderboard::LeaderboardEntry;
+use crate::settings::SavedSettings;
+use crate::stats::LifetimeStats;
+
+const CONFIG_PATH: &str = "config.ron";
+
+// Everything kept from one launch to the next, in one file. It's loaded
+// before the plugins, which each take their part of it, and written back
+// whole whenever one of those parts changes.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Config {
+    pub(crate) settings: SavedSettings,
+    pub(crate) stats: LifetimeStats,
+    pub(crate) leaderboard: Vec<LeaderboardEntry>,
+}
+impl Config {
+    // Missing fields keep their defaults, and a file that can't be read at
+    // all is started over.
+    pub(crate) fn load() -> Self {
+        let data = match std::fs::read_to_string(CONFIG_PATH) {
+            Ok(data) => data,
+            Err(_) => return Self::default(),
+        };
+        ron::from_str(&data).unwrap_or_else(|err| {
+            warn!("could not read {}: {}, using the defaults", CONFIG_PATH, err);
+            Self::default()
+        })
+    }
+
+    pub(crate) fn save(&self) {
+        let result = ron::ser::to_string_pretty(self, PrettyConfig::default())
+            .map_err(|err| err.to_string())
+            .and_then(|data| std::fs::write(CONFIG_PATH, data).map_err(|err| err.to_string()));
+        if let Err(err) = result {
+            warn!("could not save {}: {}", CONFIG_PATH, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_fields_keep_their_defaults() {
+        let config: Config = ron::from_str("(stats: (runs: 3, daily_best: {7: 12}))").unwrap();
+        assert_eq!(config.stats.runs, 3);
+        assert_eq!(config.stats.foods, 0);
+        assert_eq!(config.stats.daily_best.get(&7), Some(&12));
+    }
+
+    #[test]
+    fn config_round_trips() {
+        let mut config = Config::default();
+        config.stats.ticks = 1234;
+        config.stats.daily_best.insert(3, 40);
+        let data = ron::ser::to_string_pretty(&config, PrettyConfig::default()).unwrap();
+        let loaded: Config = ron::from_str(&data).unwrap();
+        assert_eq!(loaded.stats.ticks, 1234);
+        assert_eq!(loaded.stats.daily_best.get(&3), Some(&40));
+    }
+}
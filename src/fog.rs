@@ -0,0 +1,110 @@
+use bevy::prelude::*;
+use bevy::render::draw::Visible;
+
+use crate::food::Food;
+use crate::snake::{AiController, Player};
+use crate::{parsed_arg, Level, Position, Size, Tile, BOARD_SETUP};
+
+// How many tiles past the radius the fog takes to thicken, one shade each.
+const FOG_SHADES: u32 = 3;
+const FOG_DARKNESS: f32 = 0.85;
+
+// Limited visibility around the players' heads, on when `--fog` is given.
+// It only covers things up: collisions and the AI still see everything.
+pub(crate) struct FogPlugin;
+impl Plugin for FogPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(Fog {
+            radius: fog_radius_from_args(),
+        })
+        .init_resource::<FogMaterials>()
+        .add_startup_system_to_stage(BOARD_SETUP, fog_setup.system())
+        .add_system(update_fog.system());
+    }
+}
+
+struct Fog {
+    radius: Option<u32>,
+}
+
+// One overlay over each tile.
+struct FogTile;
+
+// From the thinnest shade to the thickest.
+struct FogMaterials(Vec<Handle<ColorMaterial>>);
+impl FromResources for FogMaterials {
+    fn from_resources(resources: &Resources) -> Self {
+        let mut materials = resources.get_mut::<Assets<ColorMaterial>>().unwrap();
+        Self(
+            (1..=FOG_SHADES)
+                .map(|shade| {
+                    let alpha = FOG_DARKNESS * shade as f32 / FOG_SHADES as f32;
+                    materials.add(Color::rgba(0., 0., 0., alpha).into())
+                })
+                .collect(),
+        )
+    }
+}
+
+// `--fog <radius>` on the command line, in tiles around the head.
+fn fog_radius_from_args() -> Option<u32> {
+    parsed_arg("--fog", |_| true)
+}
+
+fn fog_setup(commands: &mut Commands, fog: Res<Fog>, fog_materials: Res<FogMaterials>, level: Res<Level>) {
+    if fog.radius.is_none() {
+        return;
+    }
+    for x in 0..level.width as i32 {
+        for y in 0..level.height as i32 {
+            commands
+                .spawn(SpriteBundle {
+                    material: fog_materials.0[0].clone(),
+                    // Over everything on the board.
+                    transform: Transform::from_translation(Vec3::new(0., 0., 3.)),
+                    visible: Visible {
+                        is_visible: false,
+                        is_transparent: true,
+                    },
+                    ..Default::default()
+                })
+                .with(FogTile)
+                // Like the board under it, it doesn't take up the tile.
+                .with(Tile)
+                .with(Position { x, y })
+                .with(Size::square(1.));
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn update_fog(
+    fog: Res<Fog>,
+    fog_materials: Res<FogMaterials>,
+    heads: Query<&Position, (With<Player>, Without<AiController>)>,
+    mut tiles: Query<(&Position, &mut Handle<ColorMaterial>, &mut Visible), With<FogTile>>,
+    mut foods: Query<(&Position, &mut Visible), (With<Food>, Without<FogTile>)>,
+) {
+    let radius = match fog.radius {
+        Some(radius) => radius,
+        None => return,
+    };
+    let heads: Vec<Position> = heads.iter().cloned().collect();
+    // Outside of a run there's no head to see from, and the board is clear.
+    let distance = |pos: &Position| {
+        heads
+            .iter()
+            .map(|head| ((head.x - pos.x).abs() + (head.y - pos.y).abs()) as u32)
+            .min()
+    };
+    for (pos, mut material, mut visible) in tiles.iter_mut() {
+        let shade = distance(pos).map_or(0, |d| d.saturating_sub(radius).min(FOG_SHADES));
+        visible.is_visible = shade > 0;
+        if shade > 0 {
+            *material = fog_materials.0[shade as usize - 1].clone();
+        }
+    }
+    for (pos, mut visible) in foods.iter_mut() {
+        visible.is_visible = distance(pos).map_or(true, |d| d <= radius);
+    }
+}
@@ -0,0 +1,767 @@
+use bevy::prelude::*;
+use rand::seq::IteratorRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::Duration;
+
+use crate::collision::EatEvent;
+use crate::frenzy::FrenzyActive;
+use crate::magnet::MagnetEffect;
+use crate::shrink::ShrinkTimer;
+use crate::stats::RunStats;
+use crate::snake::{grow_snake, shrink_snake, step, ControlsInverted, GhostEffect, Player, SnakeSegment, WrapAxes};
+use crate::{
+    add_tick_system, arg_value, combo_window_from_args, parsed_arg, ArenaTiles, Direction, GameRng, GameState,
+    Level, Materials, MoveTimer, Position, Size, Tile, MAX_PLAYERS, USAGE,
+};
+#[cfg(feature = "audio")]
+use crate::{AudioSettings, SoundEffects};
+
+// Food spawning and eating, and the particles it leaves behind.
+pub(crate) struct FoodPlugin;
+impl Plugin for FoodPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(FoodSpawnTimer::default())
+            .add_resource(Pairs(pairs_from_args()))
+            .add_resource(food_points_from_args())
+            .add_resource(PendingColor::default())
+            .add_resource(PreyTimer::default())
+            .add_event::<ScoreEvent>()
+            .add_resource(Combo::new(combo_window_from_args()))
+            .add_system(particle_system.system())
+            .add_system(bonus_pulse.system())
+            .add_system(spawn_scale.system());
+        // Systems touching the same data run in the order they're added, so
+        // the spawner comes after everything that moves on a tick: the snakes
+        // (`SnakePlugin` is added first) and the prey. Segments grown on this
+        // tick are still commands when it runs, but they spawn on the tail's
+        // tile, which the tail already occupies.
+        add_tick_system(app, || prey_movement.system());
+        add_tick_system(app, || food_spawner.system());
+        add_tick_system(app, || combo_expiry.system());
+        add_tick_system(app, || power_up_eaten.system());
+        add_tick_system(app, || eat_events_solver.system());
+        add_tick_system(app, || apply_score.system());
+        // After eating, so a bonus reached on its last tick is still eaten.
+        add_tick_system(app, || bonus_expiry.system());
+    }
+}
+
+pub(crate) struct Food;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub(crate) enum FoodKind {
+    Normal,
+    Golden,
+    Rotten,
+    Ghost,
+    // Runs around the arena until it's caught.
+    Moving,
+    // Swaps the eater's controls around for a while.
+    Confusion,
+    // Pulls nearby food in to the eater's head for a while.
+    Magnet,
+    // Takes the place of normal food when pairs are on. Scores only when
+    // eaten right after another of the same color.
+    Paired(usize),
+}
+
+// Gone after its timer runs out, ticked with the game so a replay loses it
+// on the same tick. The pulse only looks alive, so it keeps to the frames.
+struct Bonus {
+    timer: Timer,
+    pulse: f32,
+}
+impl Default for Bonus {
+    fn default() -> Self {
+        Self {
+            timer: Timer::new(Duration::from_millis(5000), false),
+            pulse: 0.,
+        }
+    }
+}
+
+// Food pops in, growing from `SPAWN_SCALE` to full size. Only the
+// `Transform` is scaled, while `size_scaling` keeps setting the sprite's size
+// from `Size`, so the two don't get in each other's way.
+const SPAWN_SCALE: f32 = 0.2;
+
+struct SpawnScale {
+    timer: Timer,
+}
+impl Default for SpawnScale {
+    fn default() -> Self {
+        Self {
+            timer: Timer::new(Duration::from_millis(200), false),
+        }
+    }
+}
+
+const PARTICLES_PER_BURST: usize = 6;
+const MAX_PARTICLES: usize = 48;
+
+struct Particle {
+    velocity: Vec2,
+}
+
+struct ParticleLifetime(Timer);
+impl Default for ParticleLifetime {
+    fn default() -> Self {
+        Self(Timer::new(Duration::from_millis(400), false))
+    }
+}
+
+pub(crate) const COMBO_WINDOW: f32 = 2.;
+
+// Eating again within `timer` of the last food bumps the multiplier applied
+// to the score; letting it run out drops back to 1x. Each player keeps their
+// own streak, so the other player eating in between starts a new one. Like
+// the food spawner it counts movement ticks, so replays score the same.
+pub(crate) struct Combo {
+    pub(crate) multiplier: u32,
+    player: Option<usize>,
+    timer: Timer,
+}
+impl Combo {
+    pub(crate) fn new(window: f32) -> Self {
+        Self {
+            multiplier: 1,
+            player: None,
+            timer: Timer::from_seconds(window, false),
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.multiplier = 1;
+        self.player = None;
+        self.timer.reset();
+    }
+
+    fn eat(&mut self, player: usize) -> u32 {
+        self.multiplier = if self.player == Some(player) { self.multiplier + 1 } else { 1 };
+        self.player = Some(player);
+        self.timer.reset();
+        self.multiplier
+    }
+}
+impl Default for Combo {
+    fn default() -> Self {
+        Self::new(COMBO_WINDOW)
+    }
+}
+
+// Points won, or lost if negative, by the player whose head is `snake`, for
+// food eaten at `at` with the combo at `multiplier`.
+pub(crate) struct ScoreEvent {
+    pub(crate) snake: Entity,
+    pub(crate) points: i32,
+    pub(crate) multiplier: u32,
+    pub(crate) at: Option<Vec3>,
+}
+
+// What each kind of food scores, before the combo multiplier. Bonus food
+// scores `bonus` whatever its kind, and paired food only scores `pair` for
+// the second of a pair.
+struct FoodPoints {
+    normal: u32,
+    golden: u32,
+    rotten: u32,
+    ghost: u32,
+    moving: u32,
+    confusion: u32,
+    magnet: u32,
+    pair: u32,
+    bonus: u32,
+}
+impl Default for FoodPoints {
+    fn default() -> Self {
+        Self {
+            normal: 1,
+            golden: 5,
+            rotten: 0,
+            ghost: 1,
+            moving: 3,
+            confusion: 1,
+            magnet: 1,
+            pair: 3,
+            bonus: 5,
+        }
+    }
+}
+impl FoodPoints {
+    fn get_mut(&mut self, name: &str) -> Option<&mut u32> {
+        match name {
+            "normal" => Some(&mut self.normal),
+            "golden" => Some(&mut self.golden),
+            "rotten" => Some(&mut self.rotten),
+            "ghost" => Some(&mut self.ghost),
+            "moving" => Some(&mut self.moving),
+            "confusion" => Some(&mut self.confusion),
+            "magnet" => Some(&mut self.magnet),
+            "pair" => Some(&mut self.pair),
+            "bonus" => Some(&mut self.bonus),
+            _ => None,
+        }
+    }
+}
+
+// `--food-points <kind>=<points>,...`, e.g. `golden=10,moving=2`. Kinds left
+// out keep their points, as do any that don't parse.
+fn food_points_from_args() -> FoodPoints {
+    let mut points = FoodPoints::default();
+    let value = match arg_value("--food-points") {
+        Some(value) => value,
+        None => return points,
+    };
+    for entry in value.split(',') {
+        let mut parts = entry.splitn(2, '=');
+        let (name, value) = (parts.next().unwrap_or(""), parts.next().and_then(|value| value.parse().ok()));
+        match (points.get_mut(name), value) {
+            (Some(points), Some(value)) => *points = value,
+            _ => eprintln!("invalid value {:?} for --food-points\n{}", entry, USAGE),
+        }
+    }
+    points
+}
+
+// The colors paired food comes in, in the default palette.
+pub(crate) const PAIR_COLORS: [(&str, Color); 4] = [
+    ("Red", Color::rgb(0.9, 0.2, 0.2)),
+    ("Teal", Color::rgb(0.1, 0.8, 0.8)),
+    ("White", Color::rgb(1.0, 1.0, 1.0)),
+    ("Purple", Color::rgb(0.6, 0.3, 0.9)),
+];
+
+// How many of `PAIR_COLORS` are in play, when pairs are on.
+struct Pairs(Option<usize>);
+
+// `--pairs <colors>` on the command line turns pairs on.
+fn pairs_from_args() -> Option<usize> {
+    parsed_arg("--pairs", |colors: &usize| (2..=PAIR_COLORS.len()).contains(colors))
+}
+
+// The color each player has eaten half a pair of. Reset by `game_setup` at
+// the start of every run.
+#[derive(Default)]
+pub(crate) struct PendingColor(pub(crate) [Option<usize>; MAX_PLAYERS]);
+impl PendingColor {
+    // Whether `player` eating paired food of `color` completes a pair. A
+    // match does; anything else breaks the chain, and the food that broke it
+    // is lost.
+    fn eat(&mut self, player: usize, color: usize) -> bool {
+        match self.0[player].take() {
+            Some(pending) if pending == color => true,
+            Some(_) => false,
+            None => {
+                self.0[player] = Some(color);
+                false
+            }
+        }
+    }
+}
+
+// How many of every `KIND_ROLLS` foods spawned are of each special kind, on
+// average. The rest, at least 80 of them, are normal or paired food.
+const KIND_ROLLS: u32 = 100;
+const GOLDEN_WEIGHT: u32 = 4;
+const ROTTEN_WEIGHT: u32 = 3;
+const GHOST_WEIGHT: u32 = 3;
+const MOVING_WEIGHT: u32 = 4;
+const CONFUSION_WEIGHT: u32 = 3;
+const MAGNET_WEIGHT: u32 = 3;
+const SPECIAL_FOODS: [(FoodKind, u32); 6] = [
+    (FoodKind::Golden, GOLDEN_WEIGHT),
+    (FoodKind::Rotten, ROTTEN_WEIGHT),
+    (FoodKind::Ghost, GHOST_WEIGHT),
+    (FoodKind::Moving, MOVING_WEIGHT),
+    (FoodKind::Confusion, CONFUSION_WEIGHT),
+    (FoodKind::Magnet, MAGNET_WEIGHT),
+];
+
+pub(crate) struct FoodSpawnTimer(pub(crate) Timer);
+impl Default for FoodSpawnTimer {
+    fn default() -> Self {
+        Self(Timer::new(Duration::from_millis(1000), true))
+    }
+}
+
+// How often each moving food takes a step. Counted in ticks, like the
+// spawner, so a replay sees it run the same way.
+pub(crate) struct PreyTimer(Timer);
+impl Default for PreyTimer {
+    fn default() -> Self {
+        Self(Timer::new(Duration::from_millis(450), true))
+    }
+}
+
+pub(crate) fn spawn_food(commands: &mut Commands, materials: &Materials, kind: FoodKind, position: Position) {
+    let material = match kind {
+        FoodKind::Normal => materials.food_material.clone(),
+        FoodKind::Golden => materials.golden_food_material.clone(),
+        FoodKind::Rotten => materials.rotten_food_material.clone(),
+        FoodKind::Ghost => materials.ghost_food_material.clone(),
+        FoodKind::Moving => materials.moving_food_material.clone(),
+        FoodKind::Confusion => materials.confusion_food_material.clone(),
+        FoodKind::Magnet => materials.magnet_food_material.clone(),
+        // The headless app's `Materials` has none.
+        FoodKind::Paired(color) => materials.pair_materials.get(color).cloned().unwrap_or_default(),
+    };
+    commands
+        .spawn(SpriteBundle {
+            material,
+            transform: Transform {
+                translation: Vec3::new(0., 0., 1.),
+                scale: Vec3::splat(SPAWN_SCALE),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .with(Food)
+        .with(kind)
+        .with(position)
+        .with(Size::square(0.4))
+        .with(SpawnScale::default());
+}
+
+fn spawn_particles(commands: &mut Commands, materials: &Materials, origin: Vec3, count: usize) {
+    for i in 0..count {
+        let angle = i as f32 / count as f32 * 2. * std::f32::consts::PI;
+        commands
+            .spawn(SpriteBundle {
+                material: materials.food_material.clone(),
+                sprite: Sprite::new(Vec2::new(6., 6.)),
+                transform: Transform::from_translation(Vec3::new(origin.x, origin.y, 2.)),
+                ..Default::default()
+            })
+            .with(Particle {
+                velocity: Vec2::new(angle.cos(), angle.sin()) * 120.,
+            })
+            .with(ParticleLifetime::default());
+    }
+}
+
+fn particle_system(
+    commands: &mut Commands,
+    time: Res<Time>,
+    mut particles: Query<(Entity, &Particle, &mut ParticleLifetime, &mut Transform)>,
+) {
+    for (entity, particle, mut lifetime, mut transform) in particles.iter_mut() {
+        lifetime.0.tick(time.delta_seconds());
+        if lifetime.0.finished() {
+            commands.despawn(entity);
+            continue;
+        }
+        transform.translation += particle.velocity.extend(0.) * time.delta_seconds();
+        transform.scale = Vec3::splat(lifetime.0.percent_left());
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn food_spawner(
+    commands: &mut Commands,
+    occupied: Query<&Position, Without<Tile>>,
+    materials: Res<Materials>,
+    tiles: Res<ArenaTiles>,
+    level: Res<Level>,
+    shrink: Res<ShrinkTimer>,
+    mut rng: ResMut<GameRng>,
+    move_timer: Res<MoveTimer>,
+    pairs: Res<Pairs>,
+    frenzy: Res<FrenzyActive>,
+    mut timer: ResMut<FoodSpawnTimer>,
+) {
+    // This runs once per movement tick, so it advances by exactly one tick
+    // interval: food keeps a steady cadence whatever the frame rate, and
+    // stays put while paused. A frenzy runs it twice as fast.
+    let speed = if frenzy.on() { 2. } else { 1. };
+    timer.0.tick(move_timer.interval() * speed);
+    if !timer.0.finished() {
+        return;
+    }
+    let mut occupied: HashSet<Position> = occupied.iter().cloned().collect();
+    // The walls of a ring shrunk on this same tick aren't spawned yet.
+    occupied.extend(tiles.0.iter().filter(|pos| shrink.shrunk(&level, **pos)));
+    // A full board just gets no new food.
+    if let Some(pos) = tiles.pick_free_tile(&occupied, &mut *rng) {
+        let rng = &mut rng.0;
+        let mut roll = rng.gen_range(0..KIND_ROLLS);
+        let special = SPECIAL_FOODS.iter().find_map(|&(kind, weight)| {
+            if roll < weight {
+                Some(kind)
+            } else {
+                roll -= weight;
+                None
+            }
+        });
+        let kind = special.unwrap_or_else(|| match pairs.0 {
+            Some(colors) => FoodKind::Paired(rng.gen_range(0..colors)),
+            None => FoodKind::Normal,
+        });
+        spawn_food(commands, &materials, kind, pos);
+        if kind == FoodKind::Normal && rng.gen_range(0..20) == 0 {
+            commands.with(Bonus::default());
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn prey_movement(
+    level: Res<Level>,
+    wrap: Res<WrapAxes>,
+    move_timer: Res<MoveTimer>,
+    mut timer: ResMut<PreyTimer>,
+    mut rng: ResMut<GameRng>,
+    mut q: QuerySet<(
+        Query<&Position, Without<Tile>>,
+        Query<(&FoodKind, &mut Position)>,
+    )>,
+) {
+    timer.0.tick(move_timer.interval());
+    if !timer.0.just_finished() {
+        return;
+    }
+    // Snakes, walls, portals and other food all stand in the way.
+    let mut occupied: HashSet<Position> = q.q0().iter().cloned().collect();
+    let directions = [Direction::Up, Direction::Left, Direction::Down, Direction::Right];
+    for (kind, mut pos) in q.q1_mut().iter_mut() {
+        if *kind != FoodKind::Moving {
+            continue;
+        }
+        let next = directions
+            .iter()
+            .filter(|direction| !wrap.blocks(*pos, **direction, &level))
+            .map(|direction| step(*pos, *direction, &level))
+            .filter(|next| !occupied.contains(next))
+            .choose(&mut rng.0);
+        if let Some(next) = next {
+            occupied.remove(&*pos);
+            occupied.insert(next);
+            *pos = next;
+        }
+    }
+}
+
+fn bonus_expiry(commands: &mut Commands, move_timer: Res<MoveTimer>, mut bonuses: Query<(Entity, &mut Bonus)>) {
+    for (entity, mut bonus) in bonuses.iter_mut() {
+        bonus.timer.tick(move_timer.interval());
+        if bonus.timer.finished() {
+            commands.despawn(entity);
+        }
+    }
+}
+
+fn bonus_pulse(
+    gamestate: Res<State<GameState>>,
+    time: Res<Time>,
+    mut bonuses: Query<(&mut Bonus, &mut Transform, Option<&SpawnScale>)>,
+) {
+    if !matches!(gamestate.current(), GameState::Playing | GameState::Replay | GameState::Demo) {
+        return;
+    }
+    for (mut bonus, mut transform, spawning) in bonuses.iter_mut() {
+        bonus.pulse += time.delta_seconds();
+        // It only starts pulsing once it's done popping in.
+        if spawning.is_some() {
+            continue;
+        }
+        let pulse = (bonus.pulse * 4. * std::f32::consts::PI).sin();
+        transform.scale = Vec3::splat(1. + 0.3 * pulse);
+    }
+}
+
+fn spawn_scale(
+    commands: &mut Commands,
+    time: Res<Time>,
+    mut foods: Query<(Entity, &mut SpawnScale, &mut Transform)>,
+) {
+    for (entity, mut spawn, mut transform) in foods.iter_mut() {
+        spawn.timer.tick(time.delta_seconds());
+        if spawn.timer.finished() {
+            transform.scale = Vec3::one();
+            commands.remove_one::<SpawnScale>(entity);
+            continue;
+        }
+        // Eased out, so it slows down as it reaches full size.
+        let t = 1. - (1. - spawn.timer.percent()).powi(2);
+        transform.scale = Vec3::splat(SPAWN_SCALE + (1. - SPAWN_SCALE) * t);
+    }
+}
+
+// Before `eat_events_solver`, which despawns the food. Like there, only the
+// first of two heads reaching it on the same tick gets it.
+fn power_up_eaten(
+    eat_events: Res<Events<EatEvent>>,
+    mut eat_reader: Local<EventReader<EatEvent>>,
+    kinds: Query<&FoodKind>,
+    mut ghost: ResMut<GhostEffect>,
+    mut inverted: ResMut<ControlsInverted>,
+    mut magnet: ResMut<MagnetEffect>,
+) {
+    let mut eaten_foods = HashSet::new();
+    for EatEvent { eater, eaten } in eat_reader.iter(&eat_events) {
+        if !eaten_foods.insert(*eaten) {
+            continue;
+        }
+        match kinds.get(*eaten) {
+            Ok(FoodKind::Ghost) => {
+                ghost.owner = Some(*eater);
+                ghost.timer.reset();
+            }
+            Ok(FoodKind::Confusion) => {
+                inverted.owner = Some(*eater);
+                inverted.timer.reset();
+            }
+            Ok(FoodKind::Magnet) => {
+                magnet.owner = Some(*eater);
+                magnet.timer.reset();
+            }
+            _ => {}
+        }
+    }
+}
+
+// Before `eat_events_solver`, so a combo runs out before the next food.
+fn combo_expiry(move_timer: Res<MoveTimer>, mut combo: ResMut<Combo>) {
+    if combo.player.is_some() {
+        combo.timer.tick(move_timer.interval());
+        if combo.timer.finished() {
+            combo.reset();
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn eat_events_solver(
+    commands: &mut Commands,
+    mut segments: Query<(Entity, &mut SnakeSegment)>,
+    positions: Query<&Position, With<SnakeSegment>>,
+    foods: Query<(Option<&FoodKind>, Option<&Bonus>, &Transform), With<Food>>,
+    particles: Query<&Particle>,
+    eat_events: Res<Events<EatEvent>>,
+    mut eat_reader: Local<EventReader<EatEvent>>,
+    materials: Res<Materials>,
+    players: Query<&Player>,
+    mut pending: ResMut<PendingColor>,
+    mut combo: ResMut<Combo>,
+    food_points: Res<FoodPoints>,
+    mut score_events: ResMut<Events<ScoreEvent>>,
+    #[cfg(feature = "audio")] audio: Res<Audio>,
+    #[cfg(feature = "audio")] sounds: Res<SoundEffects>,
+    #[cfg(feature = "audio")] audio_settings: Res<AudioSettings>,
+) {
+    let mut spawned_particles = 0;
+    // Two heads reaching the same food on the same tick both send an event
+    // for it; only the first one gets to eat it.
+    let mut eaten_foods = HashSet::new();
+    // Only one chomp per tick, however many foods were eaten in it.
+    #[cfg(feature = "audio")]
+    let mut chomped = audio_settings.muted;
+    for EatEvent { eater, eaten } in eat_reader.iter(&eat_events) {
+        if !eaten_foods.insert(*eaten) {
+            continue;
+        }
+        #[cfg(feature = "audio")]
+        if !chomped {
+            audio.play(sounds.chomp.clone());
+            chomped = true;
+        }
+        let (kind, bonus, transform) = match foods.get(*eaten) {
+            Ok((kind, bonus, transform)) => (*kind.unwrap_or(&FoodKind::Normal), bonus.is_some(), Some(transform)),
+            Err(_) => (FoodKind::Normal, false, None),
+        };
+        let growth = match kind {
+            FoodKind::Golden => 3,
+            FoodKind::Rotten => 0,
+            _ => 1,
+        };
+        let score = match kind {
+            _ if bonus => food_points.bonus,
+            FoodKind::Normal => food_points.normal,
+            FoodKind::Golden => food_points.golden,
+            FoodKind::Rotten => food_points.rotten,
+            FoodKind::Ghost => food_points.ghost,
+            FoodKind::Moving => food_points.moving,
+            FoodKind::Confusion => food_points.confusion,
+            FoodKind::Magnet => food_points.magnet,
+            FoodKind::Paired(color) => match players.get(*eater) {
+                Ok(player) if pending.eat(player.index, color) => food_points.pair,
+                _ => 0,
+            },
+        };
+        if growth > 0 {
+            let body_material = match players.get(*eater) {
+                Ok(player) if player.index == 1 => materials.second_body_material.clone(),
+                _ => materials.body_material.clone(),
+            };
+            grow_snake(commands, &mut segments, &positions, body_material, *eater, growth);
+        } else if kind == FoodKind::Rotten {
+            shrink_snake(commands, &mut segments, *eater, 1);
+        }
+        if let Some(transform) = transform {
+            let room = MAX_PARTICLES.saturating_sub(particles.iter().count() + spawned_particles);
+            let count = PARTICLES_PER_BURST.min(room);
+            spawn_particles(commands, &materials, transform.translation, count);
+            spawned_particles += count;
+        }
+        commands.despawn(*eaten);
+        if let Ok(player) = players.get(*eater) {
+            let multiplier = combo.eat(player.index);
+            score_events.send(ScoreEvent {
+                snake: *eater,
+                points: (score * multiplier) as i32,
+                multiplier,
+                at: transform.map(|transform| transform.translation),
+            });
+        }
+    }
+}
+
+// Every food a player eats comes with a score event, so the run's stats are
+// counted here too.
+fn apply_score(
+    score_events: Res<Events<ScoreEvent>>,
+    mut score_reader: Local<EventReader<ScoreEvent>>,
+    mut stats: ResMut<RunStats>,
+    mut players: Query<&mut Player>,
+) {
+    for ScoreEvent { snake, points, multiplier, .. } in score_reader.iter(&score_events) {
+        if let Ok(mut player) = players.get_mut(*snake) {
+            player.food = (player.food as i32 + points).max(0) as u32;
+        }
+        stats.foods += 1;
+        stats.max_combo = stats.max_combo.max(*multiplier);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::{food_positions, headless_app, place_food, run_ticks, snake_positions, spawn_player, tiles};
+    use crate::snake::{AiController, Snake};
+    use crate::Practice;
+
+    // The first `count` foods to show up on an empty board, in order.
+    fn first_foods(seed: u64, count: usize) -> Vec<Position> {
+        let mut app = headless_app(Level::default());
+        app.resources.insert(GameRng::from_seed(seed));
+        let mut seen = HashSet::new();
+        let mut foods = Vec::new();
+        // Food comes every seven ticks or so; give up well after that.
+        for _ in 0..count * 10 {
+            run_ticks(&mut app, 1);
+            for (food, pos) in app.world.query_filtered::<(Entity, &Position), With<Food>>() {
+                if seen.insert(food) {
+                    foods.push(*pos);
+                }
+            }
+        }
+        foods.truncate(count);
+        foods
+    }
+
+    #[test]
+    fn same_seed_same_foods() {
+        let foods = first_foods(42, 3);
+        assert_eq!(foods.len(), 3);
+        assert_eq!(foods, first_foods(42, 3));
+        // The tiles come from the seed, not from going over the board in
+        // order: three foods landing the same with another seed is about a
+        // one in ten million chance.
+        assert_ne!(foods, first_foods(43, 3));
+    }
+
+    // With no snake on the board nothing gets eaten, so the food there is
+    // all the food spawned.
+    #[test]
+    fn food_spawns_once_a_second() {
+        let mut app = headless_app(Level::default());
+        // 6 ticks of 0.15s are 0.9s, the 7th goes past a second.
+        run_ticks(&mut app, 6);
+        assert_eq!(food_positions(&app).len(), 0);
+        run_ticks(&mut app, 1);
+        assert_eq!(food_positions(&app).len(), 1);
+        run_ticks(&mut app, 7);
+        assert_eq!(food_positions(&app).len(), 2);
+    }
+
+    // However long the frames take: the timer goes by ticks.
+    #[test]
+    fn bonus_food_expires_after_five_seconds_of_ticks() {
+        let mut app = headless_app(Level::default());
+        place_food(&mut app, FoodKind::Normal, Position { x: 10, y: 10 });
+        let food = app.world.query_filtered::<Entity, With<Food>>().next().unwrap();
+        app.world.insert_one(food, Bonus::default()).unwrap();
+        // 33 ticks of 0.15s are 4.95s, the 34th goes past 5.
+        run_ticks(&mut app, 33);
+        assert!(app.world.get::<Bonus>(food).is_ok());
+        run_ticks(&mut app, 1);
+        assert!(app.world.get::<Food>(food).is_err());
+    }
+
+    #[test]
+    fn food_eaten_twice_in_a_tick_counts_once() {
+        let mut app = headless_app(Level::default());
+        let snake = spawn_player(&mut app, 0, Direction::Right, &tiles(&[(5, 5), (4, 5), (3, 5)]));
+        // Out of the snake's way, so only the events below eat it.
+        place_food(&mut app, FoodKind::Normal, Position { x: 10, y: 10 });
+        let food = app.world.query_filtered::<Entity, With<Food>>().next().unwrap();
+        {
+            let mut eat_events = app.resources.get_mut::<Events<EatEvent>>().unwrap();
+            for _ in 0..2 {
+                eat_events.send(EatEvent {
+                    eater: snake,
+                    eaten: food,
+                });
+            }
+        }
+        run_ticks(&mut app, 1);
+        assert!(food_positions(&app).is_empty());
+        assert_eq!(app.world.get::<Player>(snake).unwrap().food, 1);
+        assert_eq!(snake_positions(&app, snake).len(), 4);
+    }
+
+    #[test]
+    fn food_never_spawns_on_a_snake() {
+        let mut app = headless_app(Level::default());
+        // The AI eats everything it can reach, and can't crash in practice,
+        // so it keeps growing and the board keeps filling up.
+        app.resources.insert(Practice(true));
+        let snake = spawn_player(&mut app, 0, Direction::Right, &tiles(&[(5, 5), (4, 5), (3, 5)]));
+        app.world.insert_one(snake, AiController).unwrap();
+        for tick in 0..400 {
+            run_ticks(&mut app, 1);
+            let snakes: HashSet<Position> = app.world.query_filtered::<&Position, With<Snake>>().copied().collect();
+            for food in food_positions(&app) {
+                assert!(!snakes.contains(&food), "food on the snake at {:?} on tick {}", food, tick);
+            }
+        }
+        assert!(app.resources.get::<RunStats>().unwrap().foods > 0);
+    }
+
+    // A snake heading right along `layout` eats a `kind` of food, placed out
+    // of its way. Its food and length after the tick.
+    fn after_eating(kind: FoodKind, layout: &[(i32, i32)]) -> (u32, usize) {
+        let mut app = headless_app(Level::default());
+        let snake = spawn_player(&mut app, 0, Direction::Right, &tiles(layout));
+        place_food(&mut app, kind, Position { x: 10, y: 10 });
+        let food = app.world.query_filtered::<Entity, With<Food>>().next().unwrap();
+        app.resources.get_mut::<Events<EatEvent>>().unwrap().send(EatEvent {
+            eater: snake,
+            eaten: food,
+        });
+        run_ticks(&mut app, 1);
+        (app.world.get::<Player>(snake).unwrap().food, snake_positions(&app, snake).len())
+    }
+
+    #[test]
+    fn golden_and_rotten_food() {
+        let layout = [(5, 5), (4, 5), (3, 5)];
+        // Grows by 3 and scores 5.
+        assert_eq!(after_eating(FoodKind::Golden, &layout), (5, 6));
+        // Shrinks by 1 and scores nothing.
+        assert_eq!(after_eating(FoodKind::Rotten, &layout), (0, 2));
+        // But never below the head.
+        assert_eq!(after_eating(FoodKind::Rotten, &[(5, 5)]), (0, 1));
+    }
+}
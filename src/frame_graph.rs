@@ -0,0 +1,197 @@
+use bevy::diagnostic::{Diagnostics, FrameTimeDiagnosticsPlugin};
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+use crate::{add_tick_system, Boosting, KeyBindings, MoveTimer};
+
+const GRAPH_SAMPLES: usize = 90;
+const BAR_WIDTH: f32 = 2.;
+const FRAME_GRAPH_HEIGHT: f32 = 60.;
+const TICK_GRAPH_HEIGHT: f32 = 30.;
+// A frame this long fills the frame graph.
+const FRAME_GRAPH_SECONDS: f64 = 0.1;
+// Two frames' worth at 60 FPS: anything longer is drawn as a hitch.
+const HITCH_SECONDS: f64 = 2. / 60.;
+// A tick coming this late or later, counted in ticks, is a hitch too.
+const TICK_HITCH: f64 = 1.5;
+
+// F7 shows a rolling graph of the last frame times, from the frame time
+// diagnostic, and under it of the time between movement ticks, against
+// what it should be. Nothing is recorded while it's hidden.
+pub(crate) struct FrameGraphPlugin;
+impl Plugin for FrameGraphPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(FrameGraph::default())
+            .init_resource::<GraphMaterials>()
+            .add_system(toggle_frame_graph.system())
+            .add_system(frame_samples.system())
+            .add_system(update_frame_graph.system());
+        add_tick_system(app, || tick_samples.system());
+    }
+}
+
+#[derive(Default)]
+struct FrameGraph {
+    shown: bool,
+    // Oldest first, in seconds.
+    frames: VecDeque<f64>,
+    // Oldest first, in ticks: 1 is a tick right on time.
+    ticks: VecDeque<f64>,
+    last_tick: Option<f64>,
+}
+
+struct GraphMaterials {
+    background: Handle<ColorMaterial>,
+    bar: Handle<ColorMaterial>,
+    hitch: Handle<ColorMaterial>,
+}
+impl FromResources for GraphMaterials {
+    fn from_resources(resources: &Resources) -> Self {
+        let mut materials = resources.get_mut::<Assets<ColorMaterial>>().unwrap();
+        Self {
+            background: materials.add(Color::rgba(0., 0., 0., 0.6).into()),
+            bar: materials.add(Color::rgb(0.3, 0.9, 0.3).into()),
+            hitch: materials.add(Color::rgb(0.9, 0.2, 0.2).into()),
+        }
+    }
+}
+
+struct FrameGraphUi;
+
+// The `index`th bar from the left, of the tick graph or the frame one.
+struct GraphBar {
+    ticks: bool,
+    index: usize,
+}
+
+fn push_sample(samples: &mut VecDeque<f64>, sample: f64) {
+    if samples.len() == GRAPH_SAMPLES {
+        samples.pop_front();
+    }
+    samples.push_back(sample);
+}
+
+// The sample under bar `index`, with the newest at the right edge.
+fn sample_at(samples: &VecDeque<f64>, index: usize) -> Option<f64> {
+    (index + samples.len()).checked_sub(GRAPH_SAMPLES).and_then(|i| samples.get(i)).copied()
+}
+
+fn toggle_frame_graph(
+    commands: &mut Commands,
+    keys: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    materials: Res<GraphMaterials>,
+    mut graph: ResMut<FrameGraph>,
+    shown: Query<Entity, With<FrameGraphUi>>,
+) {
+    if !keys.just_pressed(bindings.frame_graph) {
+        return;
+    }
+    graph.shown = !graph.shown;
+    if !graph.shown {
+        *graph = FrameGraph::default();
+        for entity in shown.iter() {
+            commands.despawn_recursive(entity);
+        }
+        return;
+    }
+    let strip = |height: f32| NodeBundle {
+        style: Style {
+            size: bevy::prelude::Size::new(Val::Px(GRAPH_SAMPLES as f32 * BAR_WIDTH), Val::Px(height)),
+            align_items: AlignItems::FlexEnd,
+            ..Default::default()
+        },
+        material: materials.background.clone(),
+        ..Default::default()
+    };
+    let bar = || NodeBundle {
+        style: Style {
+            size: bevy::prelude::Size::new(Val::Px(BAR_WIDTH), Val::Px(0.)),
+            ..Default::default()
+        },
+        material: materials.bar.clone(),
+        ..Default::default()
+    };
+    // In the bottom left corner, frames over ticks.
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    left: Val::Px(10.),
+                    bottom: Val::Px(10.),
+                    ..Default::default()
+                },
+                flex_direction: FlexDirection::ColumnReverse,
+                ..Default::default()
+            },
+            material: materials.background.clone(),
+            ..Default::default()
+        })
+        .with(FrameGraphUi)
+        .with_children(|parent| {
+            parent.spawn(strip(FRAME_GRAPH_HEIGHT)).with_children(|parent| {
+                for index in 0..GRAPH_SAMPLES {
+                    parent.spawn(bar()).with(GraphBar { ticks: false, index });
+                }
+            });
+            parent.spawn(strip(TICK_GRAPH_HEIGHT)).with_children(|parent| {
+                for index in 0..GRAPH_SAMPLES {
+                    parent.spawn(bar()).with(GraphBar { ticks: true, index });
+                }
+            });
+        });
+}
+
+fn frame_samples(diagnostics: Res<Diagnostics>, mut graph: ResMut<FrameGraph>) {
+    if !graph.shown {
+        return;
+    }
+    if let Some(frame_time) = diagnostics.get(FrameTimeDiagnosticsPlugin::FRAME_TIME).and_then(|d| d.value()) {
+        push_sample(&mut graph.frames, frame_time);
+    }
+}
+
+// Boosting makes ticks come twice as often, so that's what they're held to.
+fn tick_samples(
+    time: Res<Time>,
+    move_timer: Res<MoveTimer>,
+    boosting: Res<Boosting>,
+    mut graph: ResMut<FrameGraph>,
+) {
+    if !graph.shown {
+        return;
+    }
+    let now = time.seconds_since_startup();
+    if let Some(last) = graph.last_tick.replace(now) {
+        let speed = if boosting.0 { 2. } else { 1. };
+        let expected = move_timer.interval() as f64 / speed;
+        push_sample(&mut graph.ticks, (now - last) / expected);
+    }
+}
+
+fn update_frame_graph(
+    graph: Res<FrameGraph>,
+    materials: Res<GraphMaterials>,
+    mut bars: Query<(&GraphBar, &mut Style, &mut Handle<ColorMaterial>)>,
+) {
+    if !graph.shown {
+        return;
+    }
+    for (bar, mut style, mut material) in bars.iter_mut() {
+        // A tick on time fills half its graph.
+        let (fill, hitch, graph_height) = if bar.ticks {
+            let ticks = sample_at(&graph.ticks, bar.index).unwrap_or(0.);
+            (ticks / 2., ticks > TICK_HITCH, TICK_GRAPH_HEIGHT)
+        } else {
+            let frame_time = sample_at(&graph.frames, bar.index).unwrap_or(0.);
+            (frame_time / FRAME_GRAPH_SECONDS, frame_time > HITCH_SECONDS, FRAME_GRAPH_HEIGHT)
+        };
+        style.size.height = Val::Px(fill.min(1.) as f32 * graph_height);
+        *material = if hitch {
+            materials.hitch.clone()
+        } else {
+            materials.bar.clone()
+        };
+    }
+}
@@ -0,0 +1,80 @@
+use bevy::prelude::*;
+
+use crate::{add_tick_system, parsed_arg, GameState, MoveTimer, GAME_STATES};
+
+const DEFAULT_FRENZY_INTERVAL: f32 = 30.;
+const DEFAULT_FRENZY_SECONDS: f32 = 5.;
+
+// Every so often food comes twice as fast for a few seconds. Both are
+// counted in ticks like the food spawner, so a replay sees the same
+// frenzies, and nothing counts down in the menu's demo or while paused.
+pub(crate) struct FrenzyPlugin;
+impl Plugin for FrenzyPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        let settings = frenzy_settings_from_args();
+        app.add_resource(FrenzyTimer(Timer::from_seconds(settings.interval, false)))
+            .add_resource(settings)
+            .add_resource(FrenzyActive::default())
+            // Every run starts a whole interval away from the first one.
+            .on_state_enter(GAME_STATES, GameState::Countdown, frenzy_reset.system())
+            .on_state_enter(GAME_STATES, GameState::Menu, frenzy_reset.system());
+        add_tick_system(app, || frenzy_timer.system());
+    }
+}
+
+// `--frenzy-interval <seconds>` between frenzies, `--frenzy-length
+// <seconds>` for how long each one lasts.
+struct FrenzySettings {
+    interval: f32,
+    length: f32,
+}
+
+fn frenzy_settings_from_args() -> FrenzySettings {
+    FrenzySettings {
+        interval: parsed_arg("--frenzy-interval", |seconds: &f32| *seconds > 0.).unwrap_or(DEFAULT_FRENZY_INTERVAL),
+        length: parsed_arg("--frenzy-length", |seconds: &f32| *seconds > 0.).unwrap_or(DEFAULT_FRENZY_SECONDS),
+    }
+}
+
+// Time until the next frenzy.
+struct FrenzyTimer(Timer);
+
+// Time left in the frenzy going on, if there is one.
+#[derive(Default)]
+pub(crate) struct FrenzyActive {
+    timer: Option<Timer>,
+}
+impl FrenzyActive {
+    pub(crate) fn on(&self) -> bool {
+        self.timer.is_some()
+    }
+}
+
+fn frenzy_reset(settings: Res<FrenzySettings>, mut timer: ResMut<FrenzyTimer>, mut active: ResMut<FrenzyActive>) {
+    timer.0 = Timer::from_seconds(settings.interval, false);
+    active.timer = None;
+}
+
+fn frenzy_timer(
+    gamestate: Res<State<GameState>>,
+    settings: Res<FrenzySettings>,
+    move_timer: Res<MoveTimer>,
+    mut timer: ResMut<FrenzyTimer>,
+    mut active: ResMut<FrenzyActive>,
+) {
+    if *gamestate.current() == GameState::Demo {
+        return;
+    }
+    if let Some(frenzy) = &mut active.timer {
+        frenzy.tick(move_timer.interval());
+        if frenzy.finished() {
+            active.timer = None;
+            timer.0 = Timer::from_seconds(settings.interval, false);
+        }
+        return;
+    }
+    timer.0.tick(move_timer.interval());
+    if timer.0.finished() {
+        active.timer = Some(Timer::from_seconds(settings.length, false));
+    }
+}
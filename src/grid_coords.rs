@@ -0,0 +1,69 @@
+use bevy::prelude::*;
+use bevy::text::{HorizontalAlign, TextAlignment, VerticalAlign};
+
+use crate::{tile_size, ArenaTiles, KeyBindings, Level, Position, Tile};
+
+// A debug overlay with every tile's coordinates written on it, for placing
+// walls and portals in level files. The labels only exist while it's shown.
+pub(crate) struct GridCoordsPlugin;
+impl Plugin for GridCoordsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(ShowGridCoords::default())
+            .add_system(toggle_grid_coords.system())
+            .add_system(grid_coords.system());
+    }
+}
+
+#[derive(Default)]
+struct ShowGridCoords(bool);
+
+struct GridCoord;
+
+fn toggle_grid_coords(keys: Res<Input<KeyCode>>, bindings: Res<KeyBindings>, mut show: ResMut<ShowGridCoords>) {
+    if keys.just_pressed(bindings.grid_coords) {
+        show.0 = !show.0;
+    }
+}
+
+// Sized for the window as it is when turned on.
+fn grid_coords(
+    commands: &mut Commands,
+    show: ChangedRes<ShowGridCoords>,
+    level: Res<Level>,
+    tiles: Res<ArenaTiles>,
+    windows: Res<Windows>,
+    asset_server: Res<AssetServer>,
+    labels: Query<Entity, With<GridCoord>>,
+) {
+    for label in labels.iter() {
+        commands.despawn(label);
+    }
+    if !show.0 {
+        return;
+    }
+    let font_size = tile_size(windows.get_primary().unwrap(), &level) * 0.3;
+    let font = asset_server.load("fonts/DejaVuSans.ttf");
+    for &pos in tiles.0.iter() {
+        commands
+            .spawn(Text2dBundle {
+                text: Text {
+                    value: format!("{},{}", pos.x, pos.y),
+                    font: font.clone(),
+                    style: TextStyle {
+                        font_size,
+                        color: Color::rgba(1., 1., 1., 0.6),
+                        alignment: TextAlignment {
+                            vertical: VerticalAlign::Center,
+                            horizontal: HorizontalAlign::Center,
+                        },
+                    },
+                },
+                // Over the board, fog included.
+                transform: Transform::from_translation(Vec3::new(0., 0., 4.)),
+                ..Default::default()
+            })
+            .with(GridCoord)
+            .with(Tile)
+            .with(pos);
+    }
+}
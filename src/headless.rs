@@ -0,0 +1,203 @@
+use bevy::ecs::{Schedule, System};
+use bevy::prelude::*;
+
+use crate::collision::CollisionPlugin;
+use crate::food::{spawn_food, Food, FoodKind, FoodPlugin};
+use crate::frenzy::FrenzyActive;
+use crate::leaderboard::Leaderboard;
+use crate::magnet::MagnetPlugin;
+use crate::poison::PoisonPlugin;
+use crate::shrink::ShrinkPlugin;
+use crate::snake::{spawn_snake, LastInput, Player, SnakePlugin, SnakeSegment};
+use crate::stats::RunStats;
+use crate::{
+    step_stage, tick_move_timer, tick_stage, ArenaTiles, AudioSettings, Boosting, Direction, GameRng, GameState, Level,
+    Materials, MoveTimer, Position, Practice, Recorder, Replay, StepRequested, DEFAULT_SEED, GAME_STATES,
+};
+#[cfg(feature = "audio")]
+use crate::SoundEffects;
+
+// A windowless app running only the gameplay plugins, so movement, collisions
+// and eating can be driven tick by tick and checked from tests. It starts
+// straight in `Playing` with an empty board: spawn what the test needs with
+// `spawn_player` and `place_food`, queue turns with `push_direction`, then
+// call `run_ticks` and look at `Position`s and `Player`s in `app.world`.
+pub(crate) fn headless_app(level: Level) -> App {
+    let mut builder = App::build();
+    builder
+        .add_plugins(MinimalPlugins)
+        .add_resource(State::new(GameState::Playing))
+        .add_resource(ArenaTiles::new(&level))
+        .add_resource(level)
+        .add_resource(Materials::default())
+        .add_resource(MoveTimer::default())
+        .add_resource(GameRng::from_seed(DEFAULT_SEED))
+        .add_resource(Recorder::default())
+        .add_resource(Replay::default())
+        .add_resource(AudioSettings { muted: true })
+        .add_resource(Boosting::default())
+        .add_resource(Practice::default())
+        .add_resource(StepRequested::default())
+        .add_resource(RunStats::default())
+        // Never on, so food keeps its usual pace.
+        .add_resource(FrenzyActive::default())
+        // Read at the end of the death animation.
+        .add_resource(Leaderboard::default())
+        .add_system(tick_move_timer.system())
+        .add_stage_after(stage::UPDATE, GAME_STATES, StateStage::<GameState>::default()
+            .with_update_stage(GameState::Playing, tick_stage())
+            .with_update_stage(GameState::Paused, step_stage())
+        )
+        // No UiPlugin, nor the systems that lay sprites out in the window.
+        .add_plugin(SnakePlugin)
+        .add_plugin(ShrinkPlugin)
+        .add_plugin(MagnetPlugin)
+        .add_plugin(PoisonPlugin)
+        .add_plugin(CollisionPlugin)
+        .add_plugin(FoodPlugin);
+    #[cfg(feature = "audio")]
+    builder
+        .add_resource(Audio::<AudioSource>::default())
+        .add_resource(SoundEffects {
+            chomp: Handle::default(),
+            crash: Handle::default(),
+        });
+    std::mem::take(&mut builder.app)
+}
+
+// Spawns the snake for player `index` along `layout`, head first.
+pub(crate) fn spawn_player(app: &mut App, index: usize, direction: Direction, layout: &[Position]) -> Entity {
+    let mut commands = Commands::default();
+    commands.set_entity_reserver(app.world.get_entity_reserver());
+    let snake = {
+        let materials = app.resources.get::<Materials>().unwrap();
+        spawn_snake(&mut commands, &materials, materials.body_material.clone(), layout)
+    };
+    commands.insert(snake, (
+        Player {
+            index,
+            direction,
+            food: 0,
+        },
+        LastInput::default(),
+    ));
+    commands.apply(&mut app.world, &mut app.resources);
+    snake
+}
+
+pub(crate) fn place_food(app: &mut App, kind: FoodKind, position: Position) {
+    let mut commands = Commands::default();
+    commands.set_entity_reserver(app.world.get_entity_reserver());
+    {
+        let materials = app.resources.get::<Materials>().unwrap();
+        spawn_food(&mut commands, &materials, kind, position);
+    }
+    commands.apply(&mut app.world, &mut app.resources);
+}
+
+pub(crate) fn push_direction(app: &mut App, snake: Entity, direction: Direction) {
+    app.world.get_mut::<LastInput>(snake).unwrap().push(direction);
+}
+
+// Runs exactly `ticks` movement ticks, one per update. Time is real time
+// here, so instead of waiting out the interval each update starts with the
+// timer already run out.
+pub(crate) fn run_ticks(app: &mut App, ticks: u32) {
+    for _ in 0..ticks {
+        {
+            let mut timer = app.resources.get_mut::<MoveTimer>().unwrap();
+            let interval = timer.interval();
+            timer.0.reset();
+            timer.0.set_elapsed(interval);
+        }
+        app.update();
+    }
+}
+
+// Runs `system` once, commands and all, for calling the helpers that take
+// queries. Anything it needs to be told or to hand back goes through a
+// resource.
+pub(crate) fn run_system<S: System<In = (), Out = ()>>(app: &mut App, system: S) {
+    let mut schedule = Schedule::default();
+    schedule.add_stage("run", SystemStage::serial().with_system(system));
+    schedule.initialize_and_run(&mut app.world, &mut app.resources);
+}
+
+// `layout`s are easier to read as coordinates.
+pub(crate) fn tiles(coords: &[(i32, i32)]) -> Vec<Position> {
+    coords.iter().map(|&(x, y)| Position { x, y }).collect()
+}
+
+// Where each part of `head`'s snake is, head first, following the same links
+// as `snake_body`.
+pub(crate) fn snake_positions(app: &App, head: Entity) -> Vec<Position> {
+    let mut positions = Vec::new();
+    let mut next = Some(head);
+    while let Some(part) = next {
+        positions.push(*app.world.get::<Position>(part).unwrap());
+        next = app.world.get::<SnakeSegment>(part).unwrap().back;
+    }
+    positions
+}
+
+pub(crate) fn food_positions(app: &App) -> Vec<Position> {
+    app.world.query_filtered::<&Position, With<Food>>().copied().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collision::{DeathReason, GameOverEvent, RoundResult};
+
+    #[test]
+    fn snake_moves_a_tile_per_tick() {
+        let mut app = headless_app(Level::default());
+        let snake = spawn_player(&mut app, 0, Direction::Right, &tiles(&[(5, 5), (4, 5), (3, 5)]));
+        run_ticks(&mut app, 1);
+        assert_eq!(snake_positions(&app, snake), tiles(&[(6, 5), (5, 5), (4, 5)]));
+        run_ticks(&mut app, 2);
+        assert_eq!(snake_positions(&app, snake), tiles(&[(8, 5), (7, 5), (6, 5)]));
+    }
+
+    #[test]
+    fn pushed_direction_turns_on_the_next_tick() {
+        let mut app = headless_app(Level::default());
+        let snake = spawn_player(&mut app, 0, Direction::Right, &tiles(&[(5, 5), (4, 5), (3, 5)]));
+        push_direction(&mut app, snake, Direction::Up);
+        run_ticks(&mut app, 1);
+        assert_eq!(app.world.get::<Player>(snake).unwrap().direction, Direction::Up);
+        assert_eq!(snake_positions(&app, snake), tiles(&[(5, 6), (5, 5), (4, 5)]));
+    }
+
+    #[test]
+    fn eating_grows_and_scores() {
+        let mut app = headless_app(Level::default());
+        let snake = spawn_player(&mut app, 0, Direction::Right, &tiles(&[(5, 5), (4, 5), (3, 5)]));
+        place_food(&mut app, FoodKind::Normal, Position { x: 6, y: 5 });
+        run_ticks(&mut app, 1);
+        assert!(food_positions(&app).is_empty());
+        assert_eq!(app.world.get::<Player>(snake).unwrap().food, 1);
+        // The new segment sits on the tail until the snake moves on.
+        assert_eq!(snake_positions(&app, snake), tiles(&[(6, 5), (5, 5), (4, 5), (4, 5)]));
+        run_ticks(&mut app, 1);
+        assert_eq!(snake_positions(&app, snake), tiles(&[(7, 5), (6, 5), (5, 5), (4, 5)]));
+    }
+
+    #[test]
+    fn biting_its_own_body_ends_the_round() {
+        let mut app = headless_app(Level::default());
+        // Curled up, so heading down runs into the body.
+        let layout = tiles(&[(5, 5), (6, 5), (6, 4), (5, 4), (4, 4)]);
+        let snake = spawn_player(&mut app, 0, Direction::Down, &layout);
+        app.world.get_mut::<Player>(snake).unwrap().food = 7;
+        run_ticks(&mut app, 1);
+        assert!(*app.resources.get::<State<GameState>>().unwrap().current() == GameState::Dying);
+        let result = app.resources.get::<RoundResult>().unwrap();
+        assert_eq!(result.losers, vec![0]);
+        assert_eq!(result.reasons, vec![(0, DeathReason::SelfBite)]);
+        let events = app.resources.get::<Events<GameOverEvent>>().unwrap();
+        let sent: Vec<(usize, DeathReason, u32)> =
+            events.get_reader().iter(&events).map(|event| (event.player, event.reason, event.score)).collect();
+        assert_eq!(sent, vec![(0, DeathReason::SelfBite, 7)]);
+    }
+}
@@ -0,0 +1,71 @@
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+use crate::collision::RoundResult;
+use crate::snake::Player;
+use crate::ui::despawn_all;
+use crate::{add_tick_system, Direction, GameState, Position, Size, GAME_STATES};
+
+// How many ticks of history are kept, and how many of them the trail shows.
+const HISTORY_TICKS: usize = 30;
+const TRAIL_MOVES: usize = 8;
+
+// After a crash, the game over screen shows the last few moves of the
+// snakes that crashed as a fading trail over the board.
+pub(crate) struct KillCamPlugin;
+impl Plugin for KillCamPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(TickHistory::default())
+            .on_state_enter(GAME_STATES, GameState::Lost, trail_setup.system())
+            .on_state_exit(GAME_STATES, GameState::Lost, despawn_all::<Trail>.system());
+        add_tick_system(app, || record_history.system());
+    }
+}
+
+// Where each player's head was and where it was heading, oldest tick first.
+// Reset by `game_setup` at the start of every run.
+#[derive(Default)]
+pub(crate) struct TickHistory(VecDeque<Vec<(usize, Position, Direction)>>);
+
+struct Trail;
+
+// Added after `SnakePlugin`, so it sees the heads where they ended up.
+fn record_history(mut history: ResMut<TickHistory>, players: Query<(&Player, &Position)>) {
+    if history.0.len() == HISTORY_TICKS {
+        history.0.pop_front();
+    }
+    history
+        .0
+        .push_back(players.iter().map(|(player, pos)| (player.index, *pos, player.direction)).collect());
+}
+
+fn trail_setup(
+    commands: &mut Commands,
+    history: Res<TickHistory>,
+    result: Res<RoundResult>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let moves = history.0.iter().rev().take(TRAIL_MOVES).enumerate();
+    for (age, heads) in moves {
+        // The older the move, the fainter.
+        let alpha = 0.9 * (1. - age as f32 / TRAIL_MOVES as f32);
+        let material = materials.add(Color::rgba(1., 0.9, 0.2, alpha).into());
+        for &(_, pos, direction) in heads.iter().filter(|(index, _, _)| result.losers.contains(index)) {
+            // Stretched along the way the head was going.
+            let size = match direction {
+                Direction::Left | Direction::Right => Size { width: 0.6, height: 0.25 },
+                Direction::Up | Direction::Down => Size { width: 0.25, height: 0.6 },
+            };
+            commands
+                .spawn(SpriteBundle {
+                    material: material.clone(),
+                    // Over everything on the board, fog included.
+                    transform: Transform::from_translation(Vec3::new(0., 0., 3.5)),
+                    ..Default::default()
+                })
+                .with(Trail)
+                .with(pos)
+                .with(size);
+        }
+    }
+}
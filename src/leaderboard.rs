@@ -0,0 +1,348 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::collision::GameOverEvent;
+use crate::config::Config;
+use crate::ui::despawn_all;
+use crate::{any_just_pressed, GameMode, GameState, KeyBindings, Practice, Recorder, GAME_STATES};
+
+const LEADERBOARD_SIZE: usize = 10;
+
+// The ten best solo scores, signed with three initials the way arcade
+// machines do it. A run that makes it on is signed once its death animation
+// is over, on the way to the game over screen. L shows it from the menu.
+pub(crate) struct LeaderboardPlugin;
+impl Plugin for LeaderboardPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        let entries = app.resources().get::<Config>().map(|config| config.leaderboard.clone()).unwrap_or_default();
+        app.add_resource(Leaderboard::from_entries(entries))
+            .add_system(leaderboard_input.system())
+            .add_system(update_leaderboard.system())
+            .on_state_enter(GAME_STATES, GameState::Dying, leaderboard_check.system())
+            .on_state_enter(GAME_STATES, GameState::Leaderboard, leaderboard_setup.system())
+            .on_state_exit(GAME_STATES, GameState::Leaderboard, despawn_all::<LeaderboardUi>.system());
+    }
+}
+
+struct LeaderboardUi;
+
+// Kept in the config file.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct LeaderboardEntry {
+    initials: String,
+    score: u32,
+    // `YYYY-MM-DD`, or `-` where there's no clock to read.
+    date: String,
+}
+
+// The score being signed, and the initials picked for it so far.
+struct Signing {
+    score: u32,
+    initials: [u8; 3],
+    cursor: usize,
+}
+
+#[derive(Default)]
+pub(crate) struct Leaderboard {
+    // Best first. Equal scores stay in the order they got on.
+    entries: Vec<LeaderboardEntry>,
+    signing: Option<Signing>,
+    // The entry just signed, picked out on the screen.
+    signed: Option<usize>,
+    // Reached from a game over rather than from the menu, so it carries on
+    // to the game over screen.
+    after_run: bool,
+}
+impl Leaderboard {
+    // Entries without three initials are left out, and the rest sorted again
+    // in case the file was edited by hand.
+    fn from_entries(mut entries: Vec<LeaderboardEntry>) -> Self {
+        entries.retain(|entry| {
+            let valid = entry.initials.len() == 3;
+            if !valid {
+                warn!("ignoring leaderboard entry with initials {:?}", entry.initials);
+            }
+            valid
+        });
+        // A stable sort, so ties keep their order.
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.score));
+        entries.truncate(LEADERBOARD_SIZE);
+        Self {
+            entries,
+            ..Default::default()
+        }
+    }
+
+    // Whether the run that just ended gets signed before the game over screen.
+    pub(crate) fn signing(&self) -> bool {
+        self.signing.is_some()
+    }
+
+    // A tie goes under the scores already there, so tying the last one
+    // isn't enough.
+    fn qualifies(&self, score: u32) -> bool {
+        let full = self.entries.len() >= LEADERBOARD_SIZE;
+        score > 0 && (!full || self.entries.last().map_or(true, |last| score > last.score))
+    }
+
+    fn sign(&mut self) {
+        let signing = match self.signing.take() {
+            Some(signing) => signing,
+            None => return,
+        };
+        let rank = self
+            .entries
+            .iter()
+            .position(|entry| entry.score < signing.score)
+            .unwrap_or_else(|| self.entries.len());
+        self.entries.insert(rank, LeaderboardEntry {
+            initials: String::from_utf8_lossy(&signing.initials).into_owned(),
+            score: signing.score,
+            date: today().unwrap_or_else(|| "-".to_string()),
+        });
+        self.entries.truncate(LEADERBOARD_SIZE);
+        self.signed = Some(rank);
+    }
+}
+
+fn save_leaderboard(leaderboard: &Leaderboard, config: &mut Config) {
+    config.leaderboard = leaderboard.entries.clone();
+    config.save();
+}
+
+// The web build has no clock to read, so there it has no date.
+#[cfg(not(target_arch = "wasm32"))]
+fn today() -> Option<String> {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).ok()?;
+    // Days since the epoch to a civil date, from Howard Hinnant's
+    // `civil_from_days`.
+    let z = (now.as_secs() / (24 * 60 * 60)) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    Some(format!("{:04}-{:02}-{:02}", year, month, day))
+}
+#[cfg(target_arch = "wasm32")]
+fn today() -> Option<String> {
+    None
+}
+
+// Runs as the death animation starts, on the frame of the crash that
+// started it, so it knows where to go once it's over. Only solo runs get on,
+// and practice runs don't count.
+fn leaderboard_check(
+    recorder: Res<Recorder>,
+    practice: Res<Practice>,
+    mut leaderboard: ResMut<Leaderboard>,
+    game_over_events: Res<Events<GameOverEvent>>,
+    mut game_over_reader: Local<EventReader<GameOverEvent>>,
+) {
+    // Read first, so the reader keeps up whatever kind of run this was.
+    let score = game_over_reader.iter(&game_over_events).last().map(|event| event.score);
+    leaderboard.signed = None;
+    leaderboard.after_run = true;
+    leaderboard.signing = None;
+    if recorder.mode != GameMode::Solo || practice.0 {
+        return;
+    }
+    if let Some(score) = score.filter(|score| leaderboard.qualifies(*score)) {
+        leaderboard.signing = Some(Signing {
+            score,
+            initials: *b"AAA",
+            cursor: 0,
+        });
+    }
+}
+
+// Up and down go through the letters and left and right between them, and
+// Enter signs. Esc is handled here rather than by `back_input`, since where
+// it goes back to depends on how the leaderboard was reached.
+fn leaderboard_input(
+    keys: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    mut gamestate: ResMut<State<GameState>>,
+    mut leaderboard: ResMut<Leaderboard>,
+    mut config: ResMut<Config>,
+) {
+    match gamestate.current() {
+        GameState::Menu if keys.just_pressed(bindings.leaderboard) => {
+            leaderboard.signed = None;
+            leaderboard.after_run = false;
+            gamestate.set_next(GameState::Leaderboard).ok();
+        }
+        GameState::Leaderboard if leaderboard.signing.is_none() => {
+            let leave = [KeyCode::Return, bindings.back, bindings.leaderboard];
+            if any_just_pressed(&keys, &leave) {
+                let next = if leaderboard.after_run { GameState::Lost } else { GameState::Menu };
+                gamestate.set_next(next).ok();
+            }
+        }
+        GameState::Leaderboard => {
+            let keys_for = |codes: &[KeyCode]| any_just_pressed(&keys, codes);
+            let (up, down) = (keys_for(&bindings.solo.up), keys_for(&bindings.solo.down));
+            let (left, right) = (keys_for(&bindings.solo.left), keys_for(&bindings.solo.right));
+            if keys.just_pressed(bindings.back) {
+                // Left unsigned.
+                leaderboard.signing = None;
+                gamestate.set_next(GameState::Lost).ok();
+            } else if keys.just_pressed(KeyCode::Return) {
+                leaderboard.sign();
+                save_leaderboard(&leaderboard, &mut config);
+            } else if up || down || left || right {
+                // Only borrowed mutably on a key press, so the screen isn't
+                // rebuilt every frame.
+                let signing = leaderboard.signing.as_mut().unwrap();
+                let letter = &mut signing.initials[signing.cursor];
+                if up {
+                    *letter = if *letter == b'Z' { b'A' } else { *letter + 1 };
+                } else if down {
+                    *letter = if *letter == b'A' { b'Z' } else { *letter - 1 };
+                } else if left {
+                    signing.cursor = signing.cursor.saturating_sub(1);
+                } else {
+                    signing.cursor = (signing.cursor + 1).min(signing.initials.len() - 1);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn leaderboard_setup(
+    commands: &mut Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+    leaderboard: Res<Leaderboard>,
+) {
+    spawn_leaderboard(commands, &mut materials, &asset_server, &leaderboard);
+}
+
+// Rebuilt whenever a letter is picked or the score signed.
+fn update_leaderboard(
+    commands: &mut Commands,
+    leaderboard: ChangedRes<Leaderboard>,
+    gamestate: Res<State<GameState>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+    shown: Query<Entity, With<LeaderboardUi>>,
+) {
+    if *gamestate.current() != GameState::Leaderboard {
+        return;
+    }
+    for entity in shown.iter() {
+        commands.despawn_recursive(entity);
+    }
+    spawn_leaderboard(commands, &mut materials, &asset_server, &leaderboard);
+}
+
+fn spawn_leaderboard(
+    commands: &mut Commands,
+    materials: &mut Assets<ColorMaterial>,
+    asset_server: &AssetServer,
+    leaderboard: &Leaderboard,
+) {
+    let font = asset_server.load("fonts/DejaVuSans.ttf");
+    let mut lines = vec![("Leaderboard".to_string(), 60., Color::WHITE)];
+    if let Some(signing) = &leaderboard.signing {
+        lines.push((format!("New High Score: {}", signing.score), 30., Color::YELLOW));
+        let initials = signing
+            .initials
+            .iter()
+            .enumerate()
+            .map(|(index, &letter)| {
+                if index == signing.cursor {
+                    format!("[{}]", letter as char)
+                } else {
+                    format!(" {} ", letter as char)
+                }
+            })
+            .collect::<String>();
+        lines.push((initials, 40., Color::YELLOW));
+    }
+    if leaderboard.entries.is_empty() {
+        lines.push(("No Scores Yet".to_string(), 20., Color::WHITE));
+    }
+    for (rank, entry) in leaderboard.entries.iter().enumerate() {
+        let color = if leaderboard.signed == Some(rank) {
+            Color::YELLOW
+        } else {
+            Color::WHITE
+        };
+        let line = format!("{}. {}  {}  {}", rank + 1, entry.initials, entry.score, entry.date);
+        lines.push((line, 20., color));
+    }
+    let footer = if leaderboard.signing.is_some() {
+        "Up/Down to Pick a Letter, Left/Right to Move, Enter to Sign, Esc to Skip"
+    } else if leaderboard.after_run {
+        "Press Enter to Continue"
+    } else {
+        "Press Enter or Esc to Go Back"
+    };
+    lines.push((footer.to_string(), 20., Color::WHITE));
+
+    // Over the board, which is still there after a run.
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: bevy::prelude::Size::new(Val::Percent(100.), Val::Percent(100.)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: materials.add(Color::rgba(0., 0., 0., 0.6).into()),
+            ..Default::default()
+        })
+        .with(LeaderboardUi)
+        .with_children(|parent| {
+            for (value, font_size, color) in lines {
+                parent.spawn(TextBundle {
+                    text: Text {
+                        value,
+                        font: font.clone(),
+                        style: TextStyle {
+                            font_size,
+                            color,
+                            ..Default::default()
+                        },
+                    },
+                    ..Default::default()
+                });
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(initials: &str, score: u32) -> LeaderboardEntry {
+        LeaderboardEntry {
+            initials: initials.to_string(),
+            score,
+            date: "-".to_string(),
+        }
+    }
+
+    #[test]
+    fn loaded_entries_are_checked_and_sorted() {
+        let mut entries = vec![entry("ABC", 5), entry("TOOLONG", 50), entry("DEF", 9), entry("GHI", 5)];
+        entries.extend((0..10).map(|score| entry("XYZ", score)));
+        let leaderboard = Leaderboard::from_entries(entries);
+        let kept: Vec<(&str, u32)> = leaderboard
+            .entries
+            .iter()
+            .map(|entry| (entry.initials.as_str(), entry.score))
+            .collect();
+        assert_eq!(kept.len(), LEADERBOARD_SIZE);
+        assert_eq!(kept[..4], [("DEF", 9), ("XYZ", 9), ("XYZ", 8), ("XYZ", 7)]);
+        // Ties keep their order.
+        assert_eq!(kept[5..8], [("ABC", 5), ("GHI", 5), ("XYZ", 5)]);
+    }
+}
@@ -0,0 +1,83 @@
+use bevy::prelude::*;
+use std::collections::HashSet;
+use std::time::Duration;
+
+use crate::food::Food;
+use crate::{add_tick_system, MoveTimer, Position, Tile};
+
+// The magnet power-up: while it lasts, food near the owner's head is pulled
+// in, a tile per tick.
+pub(crate) struct MagnetPlugin;
+impl Plugin for MagnetPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(MagnetEffect::default());
+        add_tick_system(app, || magnet_pull.system());
+    }
+}
+
+pub(crate) struct MagnetEffect {
+    pub(crate) owner: Option<Entity>,
+    pub(crate) timer: Timer,
+    // In tiles from the head, counted along both axes.
+    pub(crate) radius: i32,
+}
+impl Default for MagnetEffect {
+    fn default() -> Self {
+        Self {
+            owner: None,
+            timer: Timer::new(Duration::from_millis(5000), false),
+            radius: 4,
+        }
+    }
+}
+
+// Runs after `snake_movement` and before `collision_solver`, so food pulled
+// onto the head's new tile is eaten on the same tick.
+#[allow(clippy::type_complexity)]
+fn magnet_pull(
+    move_timer: Res<MoveTimer>,
+    mut magnet: ResMut<MagnetEffect>,
+    mut q: QuerySet<(
+        Query<&Position, Without<Tile>>,
+        Query<&mut Position, With<Food>>,
+    )>,
+) {
+    let head = match magnet.owner {
+        Some(head) => head,
+        None => return,
+    };
+    let head_pos = match q.q0().get(head) {
+        Ok(pos) => *pos,
+        Err(_) => {
+            magnet.owner = None;
+            return;
+        }
+    };
+    // Walls, snakes, portals and other food all stand in the way; only the
+    // head's own tile is fair game.
+    let mut occupied: HashSet<Position> = q.q0().iter().cloned().collect();
+    occupied.remove(&head_pos);
+    for mut pos in q.q1_mut().iter_mut() {
+        let (dx, dy) = (head_pos.x - pos.x, head_pos.y - pos.y);
+        if dx.abs() + dy.abs() > magnet.radius || *pos == head_pos {
+            continue;
+        }
+        // Along the longer way to the head first, and the other if that's
+        // blocked.
+        let along_x = Position { x: pos.x + dx.signum(), y: pos.y };
+        let along_y = Position { x: pos.x, y: pos.y + dy.signum() };
+        let steps = if dx.abs() >= dy.abs() { [along_x, along_y] } else { [along_y, along_x] };
+        let next = steps.iter().find(|next| **next != *pos && !occupied.contains(next));
+        if let Some(&next) = next {
+            occupied.remove(&*pos);
+            if next != head_pos {
+                occupied.insert(next);
+            }
+            *pos = next;
+        }
+    }
+    magnet.timer.tick(move_timer.interval());
+    if magnet.timer.finished() {
+        magnet.owner = None;
+    }
+}
@@ -1,18 +1,124 @@
 use bevy::prelude::*;
-use bevy::core::FixedTimestep;
-use bevy::diagnostic::*;
+use bevy::ecs::{ShouldRun, System};
 use bevy::app::AppExit;
+use bevy::window::{WindowFocused, WindowId};
 use rand::seq::IteratorRandom;
-use std::time::Duration;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 
+mod best_run;
+mod attract;
+mod collision;
+mod config;
+mod fog;
+mod grid_coords;
+mod food;
+mod frame_graph;
+mod frenzy;
+#[cfg(test)]
+mod headless;
+mod killcam;
+mod leaderboard;
+mod magnet;
+mod minimap;
+mod objective;
+mod poison;
+mod savegame;
+mod score_popups;
+mod settings;
+mod shrink;
+mod snake;
+mod speed_lines;
+mod stats;
+mod stress;
+mod survival;
+mod ui;
+
+use attract::AttractPlugin;
+use best_run::BestRunPlugin;
+use collision::{CollisionPlugin, RoundResult};
+use config::Config;
+use fog::FogPlugin;
+use frame_graph::FrameGraphPlugin;
+use frenzy::FrenzyPlugin;
+use grid_coords::GridCoordsPlugin;
+use food::{spawn_food, Combo, Food, FoodKind, FoodPlugin, FoodSpawnTimer, PendingColor, PreyTimer, COMBO_WINDOW};
+use killcam::{KillCamPlugin, TickHistory};
+use leaderboard::LeaderboardPlugin;
+use magnet::{MagnetEffect, MagnetPlugin};
+use minimap::{MinimapPlugin, ShowMinimap};
+use objective::ObjectivePlugin;
+use poison::{Poison, PoisonPlugin, PoisonTrail};
+use savegame::SaveGamePlugin;
+use score_popups::ScorePopupsPlugin;
+use settings::{Palette, SettingsPlugin};
+use shrink::{ShrinkPlugin, ShrinkTimer};
+use snake::{
+    spawn_snake, start_direction, step, AiController, ControlsInverted, GhostEffect, LastInput, MovementStyle, Player,
+    RenderPosition, Snake, SnakePlugin, WrapAnimation, WrapAxes,
+};
+use speed_lines::SpeedLinesPlugin;
+use stats::{RunStats, StatsPlugin};
+use stress::StressPlugin;
+use survival::{GrowingWalls, SurvivalPlugin};
+use ui::{ElapsedTime, ShowFps, ShowHelp, UiPlugin};
+
 const ARENA_WIDTH: u32 = 15;
 const ARENA_HEIGHT: u32 = 15;
 const ARENA_MARGIN: f32 = 50.;
+// The bars along edges a snake passes through stay black, and the ones along
+// edges that are a crash turn dark red. Either way they're opaque, to hide
+// what slides past the board's edges.
+const WRAP_BAR_COLOR: Color = Color::rgb(0., 0., 0.);
+const SOLID_BAR_COLOR: Color = Color::rgb(0.25, 0.04, 0.04);
 
 const FIXED_TIMESTEP: f64 = 0.15;
 
-#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash)]
+const REPLAY_PATH: &str = "last.replay";
+const DEFAULT_LEVEL: &str = "default";
+
+const DEFAULT_SEED: u64 = 42;
+
+const MIN_ARENA_SIZE: u32 = 5;
+const MAX_ARENA_SIZE: u32 = 60;
+
+const ARGS: [&str; 21] = [
+    "--level",
+    "--width",
+    "--height",
+    "--speed",
+    "--seed",
+    "--combo-window",
+    "--shrink-interval",
+    "--wrap",
+    "--fog",
+    "--grow-walls",
+    "--movement",
+    "--start-length",
+    "--pairs",
+    "--poison",
+    "--wrap-anim",
+    "--stress",
+    "--stress-length",
+    "--frenzy-interval",
+    "--frenzy-length",
+    "--goal",
+    "--food-points",
+];
+const USAGE: &str = "usage: idle_snake [--level <name>] [--width <5-60>] [--height <5-60>] \
+[--speed <0.02-1 seconds per move>] [--seed <n>] [--combo-window <seconds>] \
+[--shrink-interval <seconds>] [--wrap <both|x|y|none>] [--fog <radius>] [--grow-walls <foods>] \
+[--movement <classic|rigid>] [--start-length <1-width>] [--pairs <2-4 colors>] [--poison <ticks>] \
+[--wrap-anim <slide|dip>] [--stress <snakes>] [--stress-length <length>] \
+[--frenzy-interval <seconds>] [--frenzy-length <seconds>] [--goal <length>] \
+[--food-points <kind>=<points>,...]";
+
+const GAME_STATES: &str = "game_states";
+const BOARD_SETUP: &str = "board_setup";
+
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 struct Position {
     x: i32,
     y: i32,
@@ -32,7 +138,7 @@ impl Size {
     }
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
 enum Direction {
     Left,
     Up,
@@ -48,546 +154,1824 @@ impl Direction {
             Self::Down => Self::Up,
         }
     }
-}
 
-struct Snake;
-struct SnakeHead;
-struct SnakeSegment {
-    front: Option<Entity>,
-    back: Option<Entity>,
+    fn name(self) -> &'static str {
+        match self {
+            Self::Left => "Left",
+            Self::Up => "Up",
+            Self::Right => "Right",
+            Self::Down => "Down",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Left" => Some(Self::Left),
+            "Up" => Some(Self::Up),
+            "Right" => Some(Self::Right),
+            "Down" => Some(Self::Down),
+            _ => None,
+        }
+    }
 }
 
+// Default handles draw nothing, which is all the headless app needs.
+#[derive(Default)]
 struct Materials {
     head_material: Handle<ColorMaterial>,
     body_material: Handle<ColorMaterial>,
+    second_body_material: Handle<ColorMaterial>,
     food_material: Handle<ColorMaterial>,
-    board_material: Handle<ColorMaterial>,
+    golden_food_material: Handle<ColorMaterial>,
+    rotten_food_material: Handle<ColorMaterial>,
+    ghost_food_material: Handle<ColorMaterial>,
+    moving_food_material: Handle<ColorMaterial>,
+    confusion_food_material: Handle<ColorMaterial>,
+    magnet_food_material: Handle<ColorMaterial>,
+    // One for each of the palette's pair colors.
+    pair_materials: Vec<Handle<ColorMaterial>>,
+    // Worn by a snake while it crashes.
+    death_material: Handle<ColorMaterial>,
+    tile_light_material: Handle<ColorMaterial>,
+    tile_dark_material: Handle<ColorMaterial>,
+    wall_material: Handle<ColorMaterial>,
+    portal_material: Handle<ColorMaterial>,
+    poison_material: Handle<ColorMaterial>,
 }
 
-struct Player {
-    snake: Entity,
-    direction: Direction,
-    food: u32,
+#[cfg(feature = "audio")]
+struct SoundEffects {
+    chomp: Handle<AudioSource>,
+    crash: Handle<AudioSource>,
 }
 
-struct Food;
+// Bevy's audio can't loop or stop a sound once it started, so the music is a
+// short track that gets queued again every time it ends. Pausing or muting
+// simply stops queueing it, which silences it within one loop.
+#[cfg(feature = "audio")]
+const MUSIC_LOOP_SECONDS: f32 = 4.;
 
-struct FoodSpawnTimer(Timer);
-impl Default for FoodSpawnTimer {
-    fn default() -> Self {
-        Self(Timer::new(Duration::from_millis(1000), true))
-    }
+#[cfg(feature = "audio")]
+struct Music {
+    track: Handle<AudioSource>,
+    timer: Timer,
 }
 
-struct PauseTimer(Timer);
-impl Default for PauseTimer {
-    fn default() -> Self {
-        Self(Timer::new(Duration::from_millis(500), false))
-    }
+#[derive(Default)]
+struct AudioSettings {
+    muted: bool,
 }
 
-struct EatEvent {
-    eater: Entity,
-    eaten: Entity,
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+enum GameMode {
+    Solo,
+    Versus,
+    // Player two is steered by the computer.
+    VersusAi,
 }
-struct BumpEvent {
-    head: Entity,
-    wall: Entity,
+impl Default for GameMode {
+    fn default() -> Self {
+        Self::Solo
+    }
 }
+impl GameMode {
+    fn players(self) -> usize {
+        match self {
+            Self::Solo => 1,
+            Self::Versus | Self::VersusAi => 2,
+        }
+    }
 
-struct LastInput {
-    direction: Direction,
-}
+    fn player_name(self, index: usize) -> String {
+        if self == Self::VersusAi && index == 1 {
+            "Computer".to_string()
+        } else {
+            format!("Player {}", index + 1)
+        }
+    }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
-enum GameState {
-    Playing,
-    Paused,
-    Lost,
+    fn name(self) -> &'static str {
+        match self {
+            Self::Solo => "Solo",
+            Self::Versus => "Versus",
+            Self::VersusAi => "VersusAi",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Solo" => Some(Self::Solo),
+            "Versus" => Some(Self::Versus),
+            "VersusAi" => Some(Self::VersusAi),
+            _ => None,
+        }
+    }
 }
 
-struct FpsText;
-struct FoodText;
+struct Tile;
 
-fn setup(commands: &mut Commands, mut materials: ResMut<Assets<ColorMaterial>>, asset_server: Res<AssetServer>) {
-    commands.spawn(Camera2dBundle::default());
-    commands.spawn(CameraUiBundle::default());
-    commands.insert_resource(Materials {
-        head_material: materials
-            .add(ColorMaterial {
-                color: Color::rgb(0.7, 0.7, 0.7),
-                texture: None,
-            })
-            .into(),
-        body_material: materials
-            .add(ColorMaterial {
-                color: Color::rgb(0.3, 0.3, 0.3),
-                texture: None,
-            })
-            .into(),
-        food_material: materials
-            .add(ColorMaterial {
-                color: Color::rgb(1.0, 0.0, 1.0),
-                texture: None,
-            })
-            .into(),
-        board_material: materials
-            .add(ColorMaterial {
-                color: Color::rgb(1.0, 1.0, 1.0),
-                texture: None,
-            })
-            .into(),
-    });
-    commands.spawn(TextBundle {
-            style: Style {
-                align_self: AlignSelf::FlexEnd,
-                position_type: PositionType::Absolute,
-                position: Rect {
-                    bottom: Val::Px(10.),
-                    right: Val::Px(10.),
-                    ..Default::default()
-                },
-                ..Default::default()
-            },
-            text: Text {
-                value: "FPS:".to_string(),
-                font: asset_server.load("fonts/DejaVuSans.ttf"),
-                style: TextStyle {
-                    font_size: 20.0,
-                    color: Color::WHITE,
-                    ..Default::default()
-                },
-            },
-            ..Default::default()
-        })
-        .with(FpsText);
-    commands.spawn(TextBundle {
-            style: Style {
-                align_self: AlignSelf::FlexEnd,
-                position_type: PositionType::Absolute,
-                position: Rect {
-                    top: Val::Px(10.),
-                    left: Val::Px(10.),
-                    ..Default::default()
-                },
-                ..Default::default()
-            },
-            text: Text {
-                value: "Food:".to_string(),
-                font: asset_server.load("fonts/DejaVuSans.ttf"),
-                style: TextStyle {
-                    font_size: 20.0,
-                    color: Color::WHITE,
-                    ..Default::default()
-                },
-            },
-            ..Default::default()
-        })
-        .with(FoodText);
-    commands.spawn(NodeBundle {
-        style: Style {
-            align_self: AlignSelf::FlexEnd,
-            position_type: PositionType::Absolute,
-            position: Rect {
-                top: Val::Px(0.),
-                left: Val::Px(0.),
-                ..Default::default()
-            },
-            size: bevy::prelude::Size {
-                width: Val::Percent(100.),
-                height: Val::Px(ARENA_MARGIN),
-            },
-            ..Default::default()
-        },
-        material: materials.add(ColorMaterial {
-            color: Color::rgb(0., 0., 0.),
-            texture: None,
-        }),
-        ..Default::default()
-    });
-    commands.spawn(NodeBundle {
-        style: Style {
-            align_self: AlignSelf::FlexEnd,
-            position_type: PositionType::Absolute,
-            position: Rect {
-                top: Val::Px(0.),
-                left: Val::Px(0.),
-                ..Default::default()
-            },
-            size: bevy::prelude::Size {
-                width: Val::Px(ARENA_MARGIN),
-                height: Val::Percent(100.),
-            },
-            ..Default::default()
-        },
-        material: materials.add(ColorMaterial {
-            color: Color::rgb(0., 0., 0.),
-            texture: None,
-        }),
-        ..Default::default()
-    });
-    commands.spawn(NodeBundle {
-        style: Style {
-            align_self: AlignSelf::FlexEnd,
-            position_type: PositionType::Absolute,
-            position: Rect {
-                bottom: Val::Px(0.),
-                right: Val::Px(0.),
-                ..Default::default()
-            },
-            size: bevy::prelude::Size {
-                width: Val::Percent(100.),
-                height: Val::Px(ARENA_MARGIN),
-            },
-            ..Default::default()
-        },
-        material: materials.add(ColorMaterial {
-            color: Color::rgb(0., 0., 0.),
-            texture: None,
-        }),
-        ..Default::default()
-    });
-    commands.spawn(NodeBundle {
-        style: Style {
-            align_self: AlignSelf::FlexEnd,
-            position_type: PositionType::Absolute,
-            position: Rect {
-                bottom: Val::Px(0.),
-                right: Val::Px(0.),
-                ..Default::default()
-            },
-            size: bevy::prelude::Size {
-                width: Val::Px(ARENA_MARGIN),
-                height: Val::Percent(100.),
-            },
-            ..Default::default()
-        },
-        material: materials.add(ColorMaterial {
-            color: Color::rgb(0., 0., 0.),
-            texture: None,
-        }),
-        ..Default::default()
-    });
+struct Wall;
 
+// Entering a portal's tile moves the head to its partner's tile.
+struct Portal {
+    partner: Entity,
 }
 
-fn game_setup(commands: &mut Commands, materials: Res<Materials>) {
-    let snake = spawn_snake(commands, &materials, Position { x: 0, y: 0 });
-    commands.insert_resource(Player {
-        snake,
-        direction: Direction::Up,
-        food: 0,
-    });
+// Read from `assets/levels/<name>.ron`. Missing fields keep their defaults.
+#[derive(Deserialize)]
+#[serde(default)]
+struct Level {
+    width: u32,
+    height: u32,
+    walls: Vec<Position>,
+    portals: Vec<(Position, Position)>,
+    start: Position,
+    // Head included.
+    start_length: u32,
+    initial_food: u32,
 }
-
-fn size_scaling(windows: Res<Windows>, mut q: Query<(&Size, &mut Sprite)>) {
-    let window = windows.get_primary().unwrap();
-    for (sprite_size, mut sprite) in q.iter_mut() {
-        sprite.size = Vec2::new(
-            sprite_size.width / ARENA_WIDTH as f32 * window.width() as f32,
-            sprite_size.height / ARENA_HEIGHT as f32 * window.height() as f32,
-        );
+impl Default for Level {
+    fn default() -> Self {
+        Self {
+            width: ARENA_WIDTH,
+            height: ARENA_HEIGHT,
+            walls: Vec::new(),
+            portals: Vec::new(),
+            start: Position { x: 0, y: 0 },
+            start_length: 4,
+            initial_food: 0,
+        }
     }
 }
-
-fn position_translation(windows: Res<Windows>, mut q: Query<(&Position, &mut Transform)>) {
-    fn convert(pos: f32, bound_window: f32, bound_game: f32) -> f32 {
-        let bound_window_margin = bound_window - 2.*ARENA_MARGIN;
-        let tile_size = bound_window_margin / bound_game;
-        pos / bound_game * bound_window_margin - (bound_window / 2.) + (tile_size / 2.) + ARENA_MARGIN
+impl Level {
+    fn load(name: &str) -> Self {
+        let path = format!("assets/levels/{}.ron", name);
+        let level = std::fs::read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|data| ron::from_str::<Level>(&data).map_err(|err| err.to_string()));
+        match level {
+            Ok(mut level) if level.width > 0 && level.height > 0 => {
+                level.drop_invalid_portals();
+                level
+            }
+            Ok(_) => {
+                warn!("level {} has an empty arena, using the default one", path);
+                Self::default()
+            }
+            Err(err) => {
+                warn!("could not load level {}: {}, using the default one", path, err);
+                Self::default()
+            }
+        }
     }
-    let window = windows.get_primary().unwrap();
-    for (pos, mut transform) in q.iter_mut() {
-        let z = transform.translation.z;
-        transform.translation = Vec3::new(
-            convert(pos.x as f32, window.width() as f32, ARENA_WIDTH as f32),
-            convert(pos.y as f32, window.height() as f32, ARENA_HEIGHT as f32),
-            z,
-        );
+
+    // Walls and portals left outside the new arena, or on a starting snake,
+    // are dropped.
+    fn resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        let inside = |pos: &Position| pos.x >= 0 && pos.y >= 0 && pos.x < width as i32 && pos.y < height as i32;
+        if !inside(&self.start) {
+            self.start = Position { x: 0, y: 0 };
+        }
+        let taken: HashSet<Position> = (0..MAX_PLAYERS).flat_map(|player| self.snake_layout(player)).collect();
+        self.walls.retain(|pos| inside(pos) && !taken.contains(pos));
+        self.portals.retain(|(a, b)| inside(a) && inside(b));
+        self.drop_invalid_portals();
     }
-}
 
-fn input_events_sender(
-    keys: Res<Input<KeyCode>>,
-    mut last_input: ResMut<LastInput>,
-    mut app_exit_events: ResMut<Events<AppExit>>,
-    mut gamestate: ResMut<State<GameState>>,
-    time: Res<Time>,
-    mut pause_timer: Local<PauseTimer>,
-) {
-    if keys.pressed(KeyCode::Escape) {
-        app_exit_events.send(AppExit);
-    }
-    pause_timer.0.tick(time.delta_seconds());
-    if pause_timer.0.finished() {
-        if keys.pressed(KeyCode::Space) {
-            if *gamestate.current() == GameState::Paused {
-                gamestate.set_next(GameState::Playing).unwrap();
-            } else if *gamestate.current() == GameState::Playing {
-                gamestate.set_next(GameState::Paused).unwrap();
+    // Portals may not sit on a wall, the starting snake, or another portal.
+    fn drop_invalid_portals(&mut self) {
+        let mut taken: HashSet<Position> = self.walls.iter().cloned().collect();
+        taken.extend((0..MAX_PLAYERS).flat_map(|player| self.snake_layout(player)));
+        let portals = std::mem::take(&mut self.portals);
+        for (a, b) in portals {
+            if a != b && !taken.contains(&a) && !taken.contains(&b) {
+                taken.insert(a);
+                taken.insert(b);
+                self.portals.push((a, b));
+            } else {
+                warn!("dropping portal pair {:?} <-> {:?}: tile already taken", a, b);
             }
-            pause_timer.0.reset();
         }
     }
-    let direction = if keys.pressed(KeyCode::Left) {
-        Direction::Left
-    } else if keys.pressed(KeyCode::Right) {
-        Direction::Right
-    } else if keys.pressed(KeyCode::Down) {
-        Direction::Down
-    } else if keys.pressed(KeyCode::Up) {
-        Direction::Up
-    } else {
-        return;
-    };
-    last_input.direction = direction;
+
+    // The body trails away from the head, wrapping around the arena.
+    // Player two starts in the opposite corner, trailing to the left.
+    fn snake_layout(&self, player: usize) -> Vec<Position> {
+        let (start, step) = if player == 0 {
+            (self.start, 1)
+        } else {
+            let start = Position {
+                x: self.width as i32 - 1 - self.start.x,
+                y: self.height as i32 - 1 - self.start.y,
+            };
+            (start, -1)
+        };
+        (0..self.start_length.max(1) as i32)
+            .map(|i| Position {
+                x: (start.x + step * i).rem_euclid(self.width as i32),
+                y: start.y,
+            })
+            .collect()
+    }
 }
 
-fn snake_movement(
-    last_input: Res<LastInput>,
-    mut player: ResMut<Player>,
-    mut head_positions: Query<&mut Position, With<SnakeHead>>,
-) {
-    if last_input.direction != player.direction.opposite() {
-        player.direction = last_input.direction;
+// Every tile of the level's arena, built once when the level is loaded.
+struct ArenaTiles(Vec<Position>);
+impl ArenaTiles {
+    fn new(level: &Level) -> Self {
+        let height = level.height as i32;
+        Self(
+            (0..level.width as i32)
+                .flat_map(|x| (0..height).map(move |y| Position { x, y }))
+                .collect(),
+        )
     }
 
-    let mut player_head_pos = head_positions.get_mut(player.snake).unwrap();
-    match player.direction {
-        Direction::Left => {
-            player_head_pos.x -= 1;
-        }
-        Direction::Right => {
-            player_head_pos.x += 1;
-        }
-        Direction::Down => {
-            player_head_pos.y -= 1;
-        }
-        Direction::Up => {
-            player_head_pos.y += 1;
-        }
+    // Every tile not in `occupied`, walked in a fixed order (unlike a
+    // HashSet) so the same seed always picks the same tile.
+    fn free<'a>(&'a self, occupied: &'a HashSet<Position>) -> impl Iterator<Item = Position> + 'a {
+        self.0.iter().copied().filter(move |pos| !occupied.contains(pos))
+    }
+
+    // A random tile not in `occupied`, or `None` when the board is full.
+    // Everything placed on a single tile goes through here.
+    fn pick_free_tile(&self, occupied: &HashSet<Position>, rng: &mut GameRng) -> Option<Position> {
+        self.free(occupied).choose(&mut rng.0)
     }
-    if player_head_pos.x < 0 {
-        player_head_pos.x = ARENA_WIDTH as i32 - 1;
-    } else if player_head_pos.x >= ARENA_WIDTH as i32 {
-        player_head_pos.x = 0;
+}
+
+struct MainCamera;
+
+// The bars along the window's edges, around the board.
+enum Letterbox {
+    Horizontal,
+    Vertical,
+}
+
+// Drives the movement tick. Unlike `FixedTimestep`, its duration can be
+// changed at runtime, and it only advances while a run is on screen.
+struct MoveTimer(Timer);
+impl MoveTimer {
+    fn new(interval: f32) -> Self {
+        Self(Timer::from_seconds(interval, true))
+    }
+
+    fn interval(&self) -> f32 {
+        self.0.duration()
+    }
+
+    // How far into the current tick we are, from 0 to 1.
+    fn progress(&self) -> f32 {
+        self.0.percent()
     }
-    if player_head_pos.y < 0 {
-        player_head_pos.y = ARENA_HEIGHT as i32 - 1;
-    } else if player_head_pos.y >= ARENA_HEIGHT as i32 {
-        player_head_pos.y = 0;
+}
+impl Default for MoveTimer {
+    fn default() -> Self {
+        Self::new(FIXED_TIMESTEP as f32)
     }
 }
 
-fn segment_movement(mut q: Query<(&mut Position, &SnakeSegment)>) {
-    let heads: Vec<_> = q
-        .iter_mut()
-        .filter(|(_, s)| s.front.is_none())
-        .map(|(p, s)| (s.back, p.clone()))
-        .collect();
-    for (mut e, mut p) in heads {
-        while let Some(es) = e {
-            let oldp = *q.get_component::<Position>(es).unwrap();
-            q.set::<Position>(es, p).unwrap();
-            p = oldp;
-            e = q.get_component::<SnakeSegment>(es).unwrap().back;
-        }
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+impl Default for Difficulty {
+    fn default() -> Self {
+        Self::Medium
     }
 }
+impl Difficulty {
+    fn next(self) -> Self {
+        match self {
+            Self::Easy => Self::Medium,
+            Self::Medium => Self::Hard,
+            Self::Hard => Self::Easy,
+        }
+    }
 
-fn spawn_head(
-    commands: &mut Commands,
-    material: Handle<ColorMaterial>,
-    position: Position,
-) -> Entity {
-    commands
-        .spawn(SpriteBundle {
-            material,
-            transform: Transform::from_translation(Vec3::new(0., 0., 1.)),
-            ..Default::default()
-        })
-        .with(position)
-        .with(Size::square(0.8))
-        .with(Snake)
-        .with(SnakeHead)
-        .current_entity()
-        .unwrap()
+    fn name(self) -> &'static str {
+        match self {
+            Self::Easy => "Easy",
+            Self::Medium => "Medium",
+            Self::Hard => "Hard",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Easy" => Some(Self::Easy),
+            "Medium" => Some(Self::Medium),
+            "Hard" => Some(Self::Hard),
+            _ => None,
+        }
+    }
 }
 
-fn spawn_segment(
-    commands: &mut Commands,
-    material: Handle<ColorMaterial>,
-    position: Position,
-) -> Entity {
-    commands
-        .spawn(SpriteBundle {
-            material,
-            transform: Transform::from_translation(Vec3::new(0., 0., 1.)),
-            ..Default::default()
-        })
-        .with(position)
-        .with(Size::square(0.65))
-        .with(Snake)
-        .current_entity()
-        .unwrap()
+struct DifficultyPreset {
+    // Seconds per movement tick.
+    move_interval: f32,
+    // Seconds between food spawns.
+    food_interval: f32,
+    // Fraction of the free tiles turned into extra walls for each run.
+    obstacle_density: f32,
 }
 
-fn spawn_snake(commands: &mut Commands, materials: &Materials, position: Position) -> Entity {
-    let snake = spawn_head(commands, materials.head_material.clone(), position);
-
-    let mut pos = position;
-    let mut segments = vec![None, Some(snake)];
-    for _ in 0..3 {
-        pos.x += 1;
-        segments.push(Some(spawn_segment(
-            commands,
-            materials.body_material.clone(),
-            pos,
-        )));
-    }
-    segments.push(None);
-    for w in segments.windows(3) {
-        let seg = w[1].unwrap();
-        commands.set_current_entity(seg);
-        commands.with(SnakeSegment {
-            front: w[0],
-            back: w[2],
-        });
+struct DifficultyPresets {
+    easy: DifficultyPreset,
+    medium: DifficultyPreset,
+    hard: DifficultyPreset,
+}
+impl Default for DifficultyPresets {
+    fn default() -> Self {
+        Self {
+            easy: DifficultyPreset {
+                move_interval: 0.2,
+                food_interval: 0.8,
+                obstacle_density: 0.,
+            },
+            medium: DifficultyPreset {
+                move_interval: FIXED_TIMESTEP as f32,
+                food_interval: 1.,
+                obstacle_density: 0.,
+            },
+            hard: DifficultyPreset {
+                move_interval: 0.1,
+                food_interval: 1.5,
+                obstacle_density: 0.03,
+            },
+        }
     }
-    snake
 }
+impl DifficultyPresets {
+    // Every difficulty moving at the same speed, for `--speed`.
+    fn with_move_interval(mut self, interval: f32) -> Self {
+        self.easy.move_interval = interval;
+        self.medium.move_interval = interval;
+        self.hard.move_interval = interval;
+        self
+    }
 
-fn spawn_food(commands: &mut Commands, material: Handle<ColorMaterial>, position: Position) {
-    commands
-        .spawn(SpriteBundle {
-            material,
-            transform: Transform::from_translation(Vec3::new(0., 0., 1.)),
-            ..Default::default()
-        })
-        .with(Food)
-        .with(position)
-        .with(Size::square(0.4));
+    fn get(&self, difficulty: Difficulty) -> &DifficultyPreset {
+        match difficulty {
+            Difficulty::Easy => &self.easy,
+            Difficulty::Medium => &self.medium,
+            Difficulty::Hard => &self.hard,
+        }
+    }
 }
 
-fn food_spawner(
-    commands: &mut Commands,
-    occupied: Query<&Position>,
-    materials: Res<Materials>,
-    time: Res<Time>,
-    mut timer: Local<FoodSpawnTimer>,
-) {
-    timer.0.tick(time.delta_seconds()+ FIXED_TIMESTEP as f32);
-    if !timer.0.finished() {
-        return;
+// Extra walls placed for a single run; they go away on restart.
+struct Obstacle;
+
+const MAX_PLAYERS: usize = 2;
+
+struct DirectionKeys {
+    up: Vec<KeyCode>,
+    down: Vec<KeyCode>,
+    left: Vec<KeyCode>,
+    right: Vec<KeyCode>,
+}
+impl DirectionKeys {
+    fn just_pressed(&self, keys: &Input<KeyCode>) -> Option<Direction> {
+        if any_just_pressed(keys, &self.left) {
+            Some(Direction::Left)
+        } else if any_just_pressed(keys, &self.right) {
+            Some(Direction::Right)
+        } else if any_just_pressed(keys, &self.down) {
+            Some(Direction::Down)
+        } else if any_just_pressed(keys, &self.up) {
+            Some(Direction::Up)
+        } else {
+            None
+        }
     }
-    let mut grid = std::collections::HashSet::new();
-    for x in 0..ARENA_WIDTH as i32 {
-        for y in 0..ARENA_HEIGHT as i32 {
-            grid.insert(Position{x,y});
+}
+
+struct KeyBindings {
+    // A lone player can steer with either set of keys; in versus mode
+    // player one gets the arrows and player two WASD.
+    solo: DirectionKeys,
+    versus: [DirectionKeys; 2],
+    pause: KeyCode,
+    // Quits from the menu, and goes back to it from anywhere else.
+    back: KeyCode,
+    mute: KeyCode,
+    replay: KeyCode,
+    two_players: KeyCode,
+    versus_ai: KeyCode,
+    difficulty: KeyCode,
+    fps: KeyCode,
+    layout: KeyCode,
+    settings: KeyCode,
+    // Held down rather than pressed.
+    boost: KeyCode,
+    help: KeyCode,
+    minimap: KeyCode,
+    practice: KeyCode,
+    daily: KeyCode,
+    // Only while paused.
+    step: KeyCode,
+    save: KeyCode,
+    load: KeyCode,
+    grid_coords: KeyCode,
+    leaderboard: KeyCode,
+    frame_graph: KeyCode,
+}
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            solo: DirectionKeys {
+                up: vec![KeyCode::Up, KeyCode::W],
+                down: vec![KeyCode::Down, KeyCode::S],
+                left: vec![KeyCode::Left, KeyCode::A],
+                right: vec![KeyCode::Right, KeyCode::D],
+            },
+            versus: [
+                DirectionKeys {
+                    up: vec![KeyCode::Up],
+                    down: vec![KeyCode::Down],
+                    left: vec![KeyCode::Left],
+                    right: vec![KeyCode::Right],
+                },
+                DirectionKeys {
+                    up: vec![KeyCode::W],
+                    down: vec![KeyCode::S],
+                    left: vec![KeyCode::A],
+                    right: vec![KeyCode::D],
+                },
+            ],
+            pause: KeyCode::Space,
+            back: KeyCode::Escape,
+            mute: KeyCode::M,
+            replay: KeyCode::R,
+            two_players: KeyCode::Key2,
+            versus_ai: KeyCode::Key3,
+            difficulty: KeyCode::Tab,
+            fps: KeyCode::F3,
+            layout: KeyCode::F4,
+            settings: KeyCode::O,
+            boost: KeyCode::LShift,
+            help: KeyCode::H,
+            minimap: KeyCode::B,
+            practice: KeyCode::P,
+            daily: KeyCode::Key4,
+            step: KeyCode::N,
+            save: KeyCode::F5,
+            load: KeyCode::F9,
+            grid_coords: KeyCode::F6,
+            leaderboard: KeyCode::L,
+            frame_graph: KeyCode::F7,
         }
     }
-    let occupied: HashSet<Position> = occupied.iter().cloned().collect();
-    let free = grid.difference(&occupied);
-    let pos = free.into_iter().choose(&mut rand::thread_rng());
-    if let Some(pos) = pos {
-        spawn_food(commands, materials.food_material.clone(), *pos);
+}
+
+const GAMEPAD_DEADZONE: f32 = 0.5;
+
+// Food placement draws from this instead of `thread_rng`, and every run is
+// reseeded from a seed stored in the `Recorder`, so a replay sees the same food.
+// The whole session is seeded once at startup, so it is reproducible too.
+// It's the generator behind `StdRng`, named so its state can go in a saved game.
+#[derive(Clone, Serialize, Deserialize)]
+struct GameRng(ChaCha12Rng);
+impl GameRng {
+    fn from_seed(seed: u64) -> Self {
+        Self(ChaCha12Rng::seed_from_u64(seed))
     }
 }
 
-fn collision_solver(
-    heads_positions: Query<(Entity, &Position), With<SnakeHead>>,
-    body_positions: Query<(Entity, &Position), (With<Snake>, Without<SnakeHead>)>,
-    food_positions: Query<(Entity, &Position), With<Food>>,
-    mut eat_events: ResMut<Events<EatEvent>>,
-    mut bump_events: ResMut<Events<BumpEvent>>,
-) {
-    for (e1, p1) in heads_positions.iter() {
-        for (e2, p2) in food_positions.iter() {
-            if p1 == p2 {
-                eat_events.send(EatEvent {
-                    eater: e1,
-                    eaten: e2,
-                });
-            }
+// The turns committed during the current run by each player, keyed by
+// movement tick.
+#[derive(Default)]
+struct Recorder {
+    seed: u64,
+    mode: GameMode,
+    difficulty: Difficulty,
+    tick: u32,
+    turns: Vec<(u32, usize, Direction)>,
+    // Picked back up from a saved game. No seed lays out the board it went on
+    // from, so there's no replaying it, nor racing it as a best run.
+    loaded: bool,
+}
+impl Recorder {
+    fn new(seed: u64, mode: GameMode, difficulty: Difficulty) -> Self {
+        Self {
+            seed,
+            mode,
+            difficulty,
+            ..Default::default()
         }
     }
-    for (e1, p1) in heads_positions.iter() {
-        for (e2, p2) in body_positions.iter() {
-            if p1 == p2 {
-                bump_events.send(BumpEvent {
-                    head: e1,
-                    wall: e2,
-                });
-            }
+
+    fn loaded(mode: GameMode, difficulty: Difficulty) -> Self {
+        Self {
+            mode,
+            difficulty,
+            loaded: true,
+            ..Default::default()
         }
     }
-}
 
-fn get_tail(head: Entity, q: &mut Query<(Entity, &mut SnakeSegment)>) -> Entity {
-    let mut tail = head;
-    while let Ok((_, seg)) = q.get_mut(tail) {
-        if let Some(t) = seg.back {
-            tail = t;
-        } else {
-            break;
+    // `seed <n>`, `mode <mode>` and `difficulty <difficulty>` lines followed
+    // by one `<tick> <player> <direction>` line per turn.
+    fn serialize(&self) -> String {
+        let mut out = format!(
+            "seed {}\nmode {}\ndifficulty {}\n",
+            self.seed,
+            self.mode.name(),
+            self.difficulty.name()
+        );
+        for (tick, player, direction) in &self.turns {
+            out += &format!("{} {} {}\n", tick, player, direction.name());
         }
+        out
     }
-    tail
-}
 
-fn eat_events_solver(
-    commands: &mut Commands,
-    mut segments: Query<(Entity, &mut SnakeSegment)>,
-    positions: Query<&Position, With<SnakeSegment>>,
-    eat_events: Res<Events<EatEvent>>,
-    mut eat_reader: Local<EventReader<EatEvent>>,
-    materials: Res<Materials>,
-    mut player: ResMut<Player>,
-) {
-    while let Some(EatEvent { eater, eaten }) = eat_reader.iter(&eat_events).next() {
-        let tail = get_tail(*eater, &mut segments);
-        let tail_pos = positions.get(tail).unwrap();
-        let new_tail = spawn_segment(commands, materials.body_material.clone(), *tail_pos);
-        commands.with(SnakeSegment {
-            front: Some(tail),
-            back: None,
-        });
-        let (_, mut tail_seg) = segments.get_mut(tail).unwrap();
-        tail_seg.back = Some(new_tail);
-        commands.despawn(*eaten);
-        if *eater == player.snake {
-            player.food += 1;
+    fn deserialize(data: &str) -> Option<Self> {
+        let mut lines = data.lines();
+        let seed = lines.next()?.strip_prefix("seed ")?.parse().ok()?;
+        let mode = GameMode::from_name(lines.next()?.strip_prefix("mode ")?)?;
+        let difficulty = Difficulty::from_name(lines.next()?.strip_prefix("difficulty ")?)?;
+        let mut recorder = Self::new(seed, mode, difficulty);
+        for line in lines.filter(|l| !l.trim().is_empty()) {
+            let mut parts = line.split_whitespace();
+            let tick = parts.next()?.parse().ok()?;
+            let player = parts.next()?.parse().ok()?;
+            let direction = Direction::from_name(parts.next()?)?;
+            recorder.turns.push((tick, player, direction));
         }
+        Some(recorder)
     }
 }
 
-fn bump_events_solver(
-    mut gamestate: ResMut<State<GameState>>,
-    bump_events: Res<Events<BumpEvent>>,
-    mut bump_reader: Local<EventReader<BumpEvent>>,
-) {
-    while let Some(BumpEvent { head, wall }) = bump_reader.iter(&bump_events).next() {
-        gamestate.set_next(GameState::Lost).ok();
-        return;
+// The recording being played back while in `GameState::Replay`.
+#[derive(Default)]
+struct Replay(Recorder);
+
+// Whether a run pauses itself when the window loses focus.
+struct PauseOnBlur(bool);
+impl Default for PauseOnBlur {
+    fn default() -> Self {
+        Self(true)
     }
 }
 
-fn update_fps(diagnostics: Res<Diagnostics>, mut fps_text_q: Query<&mut Text, With<FpsText>>) {
+// The gamepad that steers player one when the controls are on `Gamepad`: the
+// first one plugged in that still is.
+#[derive(Default)]
+struct ActiveGamepad(Option<Gamepad>);
 
-    if let Some(fps) = diagnostics.get(FrameTimeDiagnosticsPlugin::FPS) {
-        if let Some(average) = fps.average() {
-            let mut text = fps_text_q.iter_mut().next().unwrap();
-            text.value = format!("FPS: {:.2}", average);
-        }
+// What steers player one. In versus, player two is on the keyboard whatever
+// this is. Only the settings screen changes it: plugging a gamepad in or out
+// doesn't.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+enum ControlScheme {
+    Keyboard,
+    // Toward the mouse cursor.
+    Mouse,
+    Gamepad,
+}
+impl Default for ControlScheme {
+    fn default() -> Self {
+        Self::Keyboard
     }
 }
-fn update_hud(player: Res<Player>, mut food_text_q: Query<&mut Text, With<FoodText>>) {
-    let mut food_text = food_text_q.iter_mut().next().unwrap();
-    food_text.value = format!("Food: {}", player.food);
+impl ControlScheme {
+    fn next(self) -> Self {
+        match self {
+            Self::Keyboard => Self::Mouse,
+            Self::Mouse => Self::Gamepad,
+            Self::Gamepad => Self::Keyboard,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Keyboard => "Keyboard",
+            Self::Mouse => "Mouse",
+            Self::Gamepad => "Gamepad",
+        }
+    }
 }
 
-fn main() {
-    let mut app = App::build();
+// Crashes are ignored, and every arena edge wraps. Only switched from the
+// menu, between runs.
+#[derive(Default)]
+struct Practice(bool);
+
+// The day of the daily challenge being played, if any. Everyone gets the
+// same board on the same day: the seed comes from the date, and the mode and
+// difficulty are fixed.
+#[derive(Default)]
+struct Daily(Option<u32>);
+
+const DAILY_DIFFICULTY: Difficulty = Difficulty::Medium;
+// Days since the Unix epoch of the first daily, 2021-01-01.
+const FIRST_DAILY: u64 = 18628;
+
+// Today's daily challenge, counted from 1. The web build has no clock to
+// read, so there it has none.
+#[cfg(not(target_arch = "wasm32"))]
+fn today_daily() -> Option<u32> {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).ok()?;
+    let day = now.as_secs() / (24 * 60 * 60);
+    Some((day.saturating_sub(FIRST_DAILY) + 1) as u32)
+}
+#[cfg(target_arch = "wasm32")]
+fn today_daily() -> Option<u32> {
+    None
+}
+
+// Set to run a single movement tick while paused, for going through a run
+// one step at a time. The tick clears it.
+#[derive(Default)]
+struct StepRequested(bool);
+
+// Set when Esc pauses a run, while the game asks whether to quit. Cleared
+// as soon as the run isn't paused any more.
+#[derive(Default)]
+struct ConfirmQuit(bool);
+
+// Set when the gamepad in use is unplugged mid-run, which pauses it. Cleared
+// when one is plugged back in, or the run isn't paused any more.
+#[derive(Default)]
+struct GamepadLost(bool);
+
+// While set, movement ticks come twice as often.
+#[derive(Default)]
+struct Boosting(bool);
+
+// Menu      -> Countdown (Space, 2, 3, 4), Replay (R), Settings (O),
+//              Leaderboard (L), Demo (left idle); Esc quits
+// Settings  -> Menu (O, Esc, Back)
+// Leaderboard -> Menu (Enter, Esc, L) or, after signing it, Lost (Enter, Esc)
+// Countdown -> Playing once the countdown ends
+// Playing   -> Paused (Space, Esc, losing focus, unplugging the gamepad),
+//              Dying (a snake crashes), Won (a snake reaches the goal length)
+// Dying     -> Lost once the crashed snakes are gone, or Leaderboard first
+//              if the score gets on it
+// Paused    -> Playing (Space), Countdown (R), Dying (crashing on a step);
+//              after Esc from Playing, Y quits
+// Lost, Won -> Countdown (Enter), Menu (Esc)
+// Replay    -> Menu (the snake crashes or reaches the goal length)
+// Demo      -> Countdown (any key but Esc), Menu (the snake crashes)
+// Esc from Countdown, Paused, Replay or Demo abandons the run for the Menu.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum GameState {
+    Menu,
+    // The board is set up but the snakes don't move yet.
+    Countdown,
+    Playing,
+    Paused,
+    // A snake crashed, and is shown dying before the game over screen.
+    Dying,
+    Lost,
+    // A snake grew to the `--goal` length.
+    Won,
+    Replay,
+    Settings,
+    // The computer plays behind the menu.
+    Demo,
+    Leaderboard,
+}
+
+const COUNTDOWN_STEPS: [&str; 4] = ["3", "2", "1", "Go!"];
+const COUNTDOWN_STEP_SECONDS: f32 = 0.6;
+
+struct Countdown {
+    timer: Timer,
+}
+impl Countdown {
+    fn label(&self) -> &'static str {
+        let step = (self.timer.elapsed() / COUNTDOWN_STEP_SECONDS) as usize;
+        COUNTDOWN_STEPS[step.min(COUNTDOWN_STEPS.len() - 1)]
+    }
+}
+impl Default for Countdown {
+    fn default() -> Self {
+        Self {
+            timer: Timer::from_seconds(COUNTDOWN_STEP_SECONDS * COUNTDOWN_STEPS.len() as f32, false),
+        }
+    }
+}
+
+fn setup(
+    commands: &mut Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+    palette: Res<Palette>,
+) {
+    let mut level = Level::load(&arg_value("--level").unwrap_or_else(|| DEFAULT_LEVEL.to_string()));
+    let arena_size = |size: &u32| (MIN_ARENA_SIZE..=MAX_ARENA_SIZE).contains(size);
+    let width = parsed_arg("--width", arena_size);
+    let height = parsed_arg("--height", arena_size);
+    if width.is_some() || height.is_some() {
+        level.resize(width.unwrap_or(level.width), height.unwrap_or(level.height));
+    }
+    // Any longer and the snake would start on top of itself.
+    let start_length = parsed_arg("--start-length", |length: &u32| (1..=level.width).contains(length));
+    if let Some(start_length) = start_length {
+        level.start_length = start_length;
+        // Dropping whatever the longer snake now lies on.
+        level.resize(level.width, level.height);
+    }
+    commands.insert_resource(ArenaTiles::new(&level));
+    commands.insert_resource(level);
+    commands.spawn(Camera2dBundle::default()).with(MainCamera);
+    commands.spawn(CameraUiBundle::default());
+    // A white square with rounded corners, tinted by each material's color.
+    // It only changes the sprites' outline: `Size` still spans the tile.
+    let rounded = asset_server.load("textures/rounded.png");
+    let colors = palette.colors();
+    commands.insert_resource(Materials {
+        head_material: materials
+            .add(ColorMaterial {
+                color: colors.head,
+                texture: Some(asset_server.load("textures/head.png")),
+            }),
+        body_material: materials
+            .add(ColorMaterial {
+                color: colors.body,
+                texture: Some(rounded.clone()),
+            }),
+        second_body_material: materials
+            .add(ColorMaterial {
+                color: colors.second_body,
+                texture: Some(rounded.clone()),
+            }),
+        food_material: materials
+            .add(ColorMaterial {
+                color: colors.food,
+                texture: Some(rounded.clone()),
+            }),
+        golden_food_material: materials
+            .add(ColorMaterial {
+                color: colors.golden_food,
+                texture: Some(rounded.clone()),
+            }),
+        rotten_food_material: materials
+            .add(ColorMaterial {
+                color: colors.rotten_food,
+                texture: Some(rounded.clone()),
+            }),
+        ghost_food_material: materials
+            .add(ColorMaterial {
+                color: colors.ghost_food,
+                texture: Some(rounded.clone()),
+            }),
+        moving_food_material: materials
+            .add(ColorMaterial {
+                color: colors.moving_food,
+                texture: Some(rounded.clone()),
+            }),
+        confusion_food_material: materials
+            .add(ColorMaterial {
+                color: colors.confusion_food,
+                texture: Some(rounded.clone()),
+            }),
+        magnet_food_material: materials
+            .add(ColorMaterial {
+                color: colors.magnet_food,
+                texture: Some(rounded.clone()),
+            }),
+        pair_materials: colors
+            .pairs
+            .iter()
+            .map(|(_, color)| {
+                materials.add(ColorMaterial {
+                    color: *color,
+                    texture: Some(rounded.clone()),
+                })
+            })
+            .collect(),
+        death_material: materials
+            .add(ColorMaterial {
+                color: colors.death,
+                texture: Some(rounded.clone()),
+            }),
+        tile_light_material: materials
+            .add(ColorMaterial {
+                color: colors.tile_light,
+                texture: None,
+            }),
+        tile_dark_material: materials
+            .add(ColorMaterial {
+                color: colors.tile_dark,
+                texture: None,
+            }),
+        wall_material: materials
+            .add(ColorMaterial {
+                color: colors.wall,
+                texture: None,
+            }),
+        portal_material: materials
+            .add(ColorMaterial {
+                color: colors.portal,
+                texture: None,
+            }),
+        poison_material: materials
+            .add(ColorMaterial {
+                color: colors.poison,
+                texture: None,
+            }),
+    });
+    #[cfg(feature = "audio")]
+    commands.insert_resource(SoundEffects {
+        chomp: asset_server.load("sounds/chomp.wav"),
+        crash: asset_server.load("sounds/crash.wav"),
+    });
+    #[cfg(feature = "audio")]
+    commands.insert_resource(Music {
+        track: asset_server.load("sounds/music.wav"),
+        timer: Timer::from_seconds(MUSIC_LOOP_SECONDS, false),
+    });
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(0.),
+                    left: Val::Px(0.),
+                    ..Default::default()
+                },
+                size: bevy::prelude::Size {
+                    width: Val::Percent(100.),
+                    height: Val::Px(ARENA_MARGIN),
+                },
+                ..Default::default()
+            },
+            material: materials.add(ColorMaterial {
+                color: Color::rgb(0., 0., 0.),
+                texture: None,
+            }),
+            ..Default::default()
+        })
+        .with(Letterbox::Horizontal);
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(0.),
+                    left: Val::Px(0.),
+                    ..Default::default()
+                },
+                size: bevy::prelude::Size {
+                    width: Val::Px(ARENA_MARGIN),
+                    height: Val::Percent(100.),
+                },
+                ..Default::default()
+            },
+            material: materials.add(ColorMaterial {
+                color: Color::rgb(0., 0., 0.),
+                texture: None,
+            }),
+            ..Default::default()
+        })
+        .with(Letterbox::Vertical);
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    bottom: Val::Px(0.),
+                    right: Val::Px(0.),
+                    ..Default::default()
+                },
+                size: bevy::prelude::Size {
+                    width: Val::Percent(100.),
+                    height: Val::Px(ARENA_MARGIN),
+                },
+                ..Default::default()
+            },
+            material: materials.add(ColorMaterial {
+                color: Color::rgb(0., 0., 0.),
+                texture: None,
+            }),
+            ..Default::default()
+        })
+        .with(Letterbox::Horizontal);
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    bottom: Val::Px(0.),
+                    right: Val::Px(0.),
+                    ..Default::default()
+                },
+                size: bevy::prelude::Size {
+                    width: Val::Px(ARENA_MARGIN),
+                    height: Val::Percent(100.),
+                },
+                ..Default::default()
+            },
+            material: materials.add(ColorMaterial {
+                color: Color::rgb(0., 0., 0.),
+                texture: None,
+            }),
+            ..Default::default()
+        })
+        .with(Letterbox::Vertical);
+
+}
+
+fn board_setup(commands: &mut Commands, materials: Res<Materials>, level: Res<Level>) {
+    for x in 0..level.width as i32 {
+        for y in 0..level.height as i32 {
+            let material = if (x + y) % 2 == 0 {
+                materials.tile_light_material.clone()
+            } else {
+                materials.tile_dark_material.clone()
+            };
+            commands
+                .spawn(SpriteBundle {
+                    material,
+                    transform: Transform::from_translation(Vec3::new(0., 0., 0.)),
+                    ..Default::default()
+                })
+                .with(Tile)
+                .with(Position { x, y })
+                .with(Size::square(1.));
+        }
+    }
+    for &position in &level.walls {
+        commands
+            .spawn(SpriteBundle {
+                material: materials.wall_material.clone(),
+                transform: Transform::from_translation(Vec3::new(0., 0., 0.5)),
+                ..Default::default()
+            })
+            .with(Wall)
+            .with(position)
+            .with(Size::square(1.));
+    }
+    for &(a, b) in &level.portals {
+        let a = spawn_portal(commands, &materials, a);
+        let b = spawn_portal(commands, &materials, b);
+        commands.insert_one(a, Portal { partner: b });
+        commands.insert_one(b, Portal { partner: a });
+    }
+}
+
+fn spawn_portal(commands: &mut Commands, materials: &Materials, position: Position) -> Entity {
+    commands
+        .spawn(SpriteBundle {
+            material: materials.portal_material.clone(),
+            transform: Transform::from_translation(Vec3::new(0., 0., 0.5)),
+            ..Default::default()
+        })
+        .with(position)
+        .with(Size::square(0.8))
+        .current_entity()
+        .unwrap()
+}
+
+#[allow(clippy::too_many_arguments)]
+fn game_setup(
+    commands: &mut Commands,
+    materials: Res<Materials>,
+    level: Res<Level>,
+    tiles: Res<ArenaTiles>,
+    gamestate: Res<State<GameState>>,
+    replay: Res<Replay>,
+    mode: Res<GameMode>,
+    difficulty: Res<Difficulty>,
+    daily: Res<Daily>,
+    presets: Res<DifficultyPresets>,
+    mut rng: ResMut<GameRng>,
+    mut combo: ResMut<Combo>,
+    mut shrink: ResMut<ShrinkTimer>,
+    mut growing: ResMut<GrowingWalls>,
+    mut poison: ResMut<PoisonTrail>,
+) {
+    // Leaving the menu for the settings screen doesn't start anything.
+    if !matches!(
+        gamestate.next(),
+        Some(GameState::Countdown) | Some(GameState::Replay) | Some(GameState::Demo)
+    ) {
+        return;
+    }
+    let demo = gamestate.next() == Some(&GameState::Demo);
+    let (seed, mode, difficulty) = if gamestate.next() == Some(&GameState::Replay) {
+        (replay.0.seed, replay.0.mode, replay.0.difficulty)
+    } else if demo {
+        (rng.0.gen(), GameMode::Solo, *difficulty)
+    } else if let Some(day) = daily.0 {
+        (day as u64, GameMode::Solo, DAILY_DIFFICULTY)
+    } else {
+        (rng.0.gen(), *mode, *difficulty)
+    };
+    *rng = GameRng::from_seed(seed);
+    combo.reset();
+    shrink.reset();
+    growing.reset();
+    poison.reset();
+    let preset = presets.get(difficulty);
+    insert_run_resources(commands, Recorder::new(seed, mode, difficulty), preset);
+
+    let mut occupied: HashSet<Position> = level
+        .walls
+        .iter()
+        .cloned()
+        .chain(level.portals.iter().flat_map(|&(a, b)| vec![a, b]))
+        .collect();
+    for index in 0..mode.players() {
+        let layout = level.snake_layout(index);
+        let (body_material, direction) = if index == 0 {
+            (materials.body_material.clone(), Direction::Up)
+        } else {
+            (materials.second_body_material.clone(), Direction::Down)
+        };
+        // Never back into its own body, whatever the level's layout.
+        let direction = start_direction(&layout, direction, &level);
+        let snake = spawn_snake(commands, &materials, body_material, &layout);
+        commands.insert(snake, (
+            Player {
+                index,
+                direction,
+                food: 0,
+            },
+            LastInput::default(),
+        ));
+        if (mode == GameMode::VersusAi && index == 1) || demo {
+            commands.insert_one(snake, AiController);
+        }
+        occupied.extend(layout);
+    }
+
+    // Obstacles keep a tile of clearance around the snakes' starting tiles
+    // so no run starts boxed in.
+    let level = &*level;
+    let directions = [Direction::Up, Direction::Left, Direction::Down, Direction::Right];
+    let clearance: HashSet<Position> = occupied
+        .iter()
+        .flat_map(|pos| directions.iter().map(move |direction| step(*pos, *direction, level)))
+        .collect();
+    let obstacle_count = (tiles.free(&occupied).count() as f32 * preset.obstacle_density) as usize;
+    let blocked: HashSet<Position> = occupied.union(&clearance).cloned().collect();
+    let obstacles = tiles.free(&blocked).choose_multiple(&mut rng.0, obstacle_count);
+    for pos in obstacles {
+        commands
+            .spawn(SpriteBundle {
+                material: materials.wall_material.clone(),
+                transform: Transform::from_translation(Vec3::new(0., 0., 0.5)),
+                ..Default::default()
+            })
+            .with(Wall)
+            .with(Obstacle)
+            .with(pos)
+            .with(Size::square(1.));
+        occupied.insert(pos);
+    }
+    let foods = tiles.free(&occupied).choose_multiple(&mut rng.0, level.initial_food as usize);
+    for pos in foods {
+        spawn_food(commands, &materials, FoodKind::Normal, pos);
+    }
+}
+
+// Everything kept for a single run, fresh for a new one. The resources that
+// last across runs and only need resetting are left to the caller.
+fn insert_run_resources(commands: &mut Commands, recorder: Recorder, preset: &DifficultyPreset) {
+    commands.insert_resource(recorder);
+    commands.insert_resource(RoundResult::default());
+    commands.insert_resource(GhostEffect::default());
+    commands.insert_resource(ControlsInverted::default());
+    commands.insert_resource(MagnetEffect::default());
+    commands.insert_resource(TickHistory::default());
+    commands.insert_resource(PendingColor::default());
+    commands.insert_resource(ElapsedTime::default());
+    commands.insert_resource(RunStats::default());
+    commands.insert_resource(Countdown::default());
+    commands.insert_resource(MoveTimer::new(preset.move_interval));
+    commands.insert_resource(FoodSpawnTimer(Timer::from_seconds(preset.food_interval, true)));
+    commands.insert_resource(PreyTimer::default());
+}
+
+fn game_cleanup(
+    commands: &mut Commands,
+    gamestate: Res<State<GameState>>,
+    snakes: Query<Entity, With<Snake>>,
+    foods: Query<Entity, With<Food>>,
+    obstacles: Query<Entity, With<Obstacle>>,
+    poisons: Query<Entity, With<Poison>>,
+) {
+    // Resuming from the pause carries on with the same run, as does
+    // crashing while stepping through it.
+    if matches!(gamestate.next(), Some(GameState::Playing) | Some(GameState::Dying)) {
+        return;
+    }
+    for entity in snakes.iter().chain(foods.iter()).chain(obstacles.iter()).chain(poisons.iter()) {
+        commands.despawn(entity);
+    }
+}
+
+fn save_replay(recorder: Res<Recorder>) {
+    if recorder.loaded {
+        return;
+    }
+    if let Err(err) = std::fs::write(REPLAY_PATH, recorder.serialize()) {
+        warn!("could not save replay to {}: {}", REPLAY_PATH, err);
+    }
+}
+
+// Tiles stay square whatever the shape of the window or the arena: the tile
+// size comes from whichever dimension runs out of room first, the board is
+// centered inside the margins, and the space left over is letterboxed. A
+// 20x10 arena in a square window gets bars above and below.
+//
+// All the layout math is in logical pixels: `Window::width`, UI `Val::Px`
+// and the 2D camera's projection are all scaled by the window's
+// `scale_factor` the same way, so the board and the bars agree at any DPI.
+// The tile size is snapped to whole physical pixels though, or at fractional
+// scale factors the tile edges, and the board's edges against the bars, land
+// between pixels and blur. The layout key logs all of it, to check by eye.
+fn tile_size(window: &Window, level: &Level) -> f32 {
+    let scale_factor = window.scale_factor() as f32;
+    let across = (window.width() - 2. * ARENA_MARGIN) / level.width as f32;
+    let down = (window.height() - 2. * ARENA_MARGIN) / level.height as f32;
+    let tile_size = across.min(down);
+    (tile_size * scale_factor).floor() / scale_factor
+}
+
+fn layout_report(keys: Res<Input<KeyCode>>, bindings: Res<KeyBindings>, windows: Res<Windows>, level: Res<Level>) {
+    if !keys.just_pressed(bindings.layout) {
+        return;
+    }
+    let window = windows.get_primary().unwrap();
+    let tile_size = tile_size(window, &level);
+    let (board_width, board_height) = (tile_size * level.width as f32, tile_size * level.height as f32);
+    info!(
+        "window {}x{} ({}x{} physical, scale factor {}), tile {}, board {}x{}, bars {} across and {} down",
+        window.width(),
+        window.height(),
+        window.physical_width(),
+        window.physical_height(),
+        window.scale_factor(),
+        tile_size,
+        board_width,
+        board_height,
+        (window.width() - board_width) / 2.,
+        (window.height() - board_height) / 2.
+    );
+}
+
+fn size_scaling(windows: Res<Windows>, level: Res<Level>, mut q: Query<(&Size, &mut Sprite)>) {
+    let window = windows.get_primary().unwrap();
+    let tile_size = tile_size(window, &level);
+    for (sprite_size, mut sprite) in q.iter_mut() {
+        sprite.size = Vec2::new(sprite_size.width * tile_size, sprite_size.height * tile_size);
+    }
+}
+
+// Covers everything around the board, so nothing sliding past its edges
+// shows.
+fn letterbox(windows: Res<Windows>, level: Res<Level>, mut bars: Query<(&Letterbox, &mut Style)>) {
+    let window = windows.get_primary().unwrap();
+    let tile_size = tile_size(window, &level);
+    let across = Val::Px((window.width() - tile_size * level.width as f32) / 2.);
+    let down = Val::Px((window.height() - tile_size * level.height as f32) / 2.);
+    for (bar, mut style) in bars.iter_mut() {
+        // Only touched on an actual change, which is what relayouts the UI.
+        match bar {
+            Letterbox::Horizontal if style.size.height != down => style.size.height = down,
+            Letterbox::Vertical if style.size.width != across => style.size.width = across,
+            _ => {}
+        }
+    }
+}
+
+// The top and bottom bars follow whether the vertical edges wrap, and the
+// side bars the horizontal ones.
+fn letterbox_color(
+    wrap: ChangedRes<WrapAxes>,
+    mut assets: ResMut<Assets<ColorMaterial>>,
+    bars: Query<(&Letterbox, &Handle<ColorMaterial>)>,
+) {
+    for (bar, handle) in bars.iter() {
+        let wraps = match bar {
+            Letterbox::Horizontal => wrap.y,
+            Letterbox::Vertical => wrap.x,
+        };
+        if let Some(material) = assets.get_mut(handle) {
+            material.color = if wraps { WRAP_BAR_COLOR } else { SOLID_BAR_COLOR };
+        }
+    }
+}
+
+// Where a tile's center is along one axis. The board is centered on the
+// window, as is the camera.
+fn convert(pos: f32, bound_game: f32, tile_size: f32) -> f32 {
+    (pos - (bound_game - 1.) / 2.) * tile_size
+}
+
+fn position_translation(
+    windows: Res<Windows>,
+    level: Res<Level>,
+    mut q: Query<(&Position, &mut Transform, Option<&mut RenderPosition>)>,
+) {
+    let window = windows.get_primary().unwrap();
+    let tile_size = tile_size(window, &level);
+    for (pos, mut transform, render_pos) in q.iter_mut() {
+        let target = Vec2::new(
+            convert(pos.x as f32, level.width as f32, tile_size),
+            convert(pos.y as f32, level.height as f32, tile_size),
+        );
+        if let Some(mut render_pos) = render_pos {
+            if target != render_pos.to {
+                let delta = target - render_pos.to;
+                let board = Vec2::new(tile_size * level.width as f32, tile_size * level.height as f32);
+                // Stepping across the arena edge lands on the far side of it.
+                let wrapped = Vec2::new(
+                    delta.x - (delta.x / board.x).round() * board.x,
+                    delta.y - (delta.y / board.y).round() * board.y,
+                );
+                let is_step = |d: Vec2| d.x.abs() <= 1.5 * tile_size && d.y.abs() <= 1.5 * tile_size;
+                if is_step(delta) {
+                    render_pos.from = render_pos.to;
+                    render_pos.wrap = Vec2::default();
+                } else if is_step(wrapped) {
+                    // Slide in from beyond the edge, while a copy `wrap` away
+                    // slides out past the opposite one.
+                    render_pos.from = target - wrapped;
+                    render_pos.wrap = render_pos.to - render_pos.from;
+                } else {
+                    // Anything else moving more than a tile at once (just
+                    // spawned, or through a portal) snaps into place.
+                    render_pos.from = target;
+                    render_pos.wrap = Vec2::default();
+                }
+                render_pos.to = target;
+            }
+            continue;
+        }
+        let z = transform.translation.z;
+        transform.translation = target.extend(z);
+    }
+}
+
+fn render_interpolation(
+    move_timer: Res<MoveTimer>,
+    mut q: Query<(&RenderPosition, &mut Transform)>,
+) {
+    let t = move_timer.progress();
+    for (render_pos, mut transform) in q.iter_mut() {
+        let z = transform.translation.z;
+        let pos = render_pos.from + (render_pos.to - render_pos.from) * t + render_pos.offset;
+        transform.translation = pos.extend(z);
+    }
+}
+
+fn toggle_pause(gamestate: &mut State<GameState>) {
+    match gamestate.current() {
+        GameState::Menu => gamestate.set_next(GameState::Countdown).ok(),
+        GameState::Paused => gamestate.set_next(GameState::Playing).ok(),
+        GameState::Playing => gamestate.set_next(GameState::Paused).ok(),
+        GameState::Countdown
+        | GameState::Dying
+        | GameState::Lost
+        | GameState::Won
+        | GameState::Replay
+        | GameState::Settings
+        | GameState::Demo
+        | GameState::Leaderboard => None,
+    };
+}
+
+fn restart(gamestate: &mut State<GameState>) {
+    if matches!(gamestate.current(), GameState::Lost | GameState::Won | GameState::Paused) {
+        gamestate.set_next(GameState::Countdown).ok();
+    }
+}
+
+fn toggle_settings(gamestate: &mut State<GameState>) {
+    match gamestate.current() {
+        GameState::Menu => gamestate.set_next(GameState::Settings).ok(),
+        GameState::Settings => gamestate.set_next(GameState::Menu).ok(),
+        _ => None,
+    };
+}
+
+fn start_replay(gamestate: &mut State<GameState>, replay: &mut Replay) {
+    if *gamestate.current() != GameState::Menu {
+        return;
+    }
+    let recorder = std::fs::read_to_string(REPLAY_PATH)
+        .ok()
+        .and_then(|data| Recorder::deserialize(&data));
+    if let Some(recorder) = recorder {
+        replay.0 = recorder;
+        gamestate.set_next(GameState::Replay).ok();
+    } else {
+        warn!("no valid replay found at {}", REPLAY_PATH);
+    }
+}
+
+fn any_just_pressed(keys: &Input<KeyCode>, codes: &[KeyCode]) -> bool {
+    codes.iter().any(|code| keys.just_pressed(*code))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn input_events_sender(
+    keys: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    confirm_quit: Res<ConfirmQuit>,
+    mut gamestate: ResMut<State<GameState>>,
+    mut audio_settings: ResMut<AudioSettings>,
+    mut show_fps: ResMut<ShowFps>,
+    mut show_help: ResMut<ShowHelp>,
+    mut show_minimap: ResMut<ShowMinimap>,
+    mut practice: ResMut<Practice>,
+    mut step: ResMut<StepRequested>,
+    mut daily: ResMut<Daily>,
+    mut replay: ResMut<Replay>,
+    mut mode: ResMut<GameMode>,
+    mut difficulty: ResMut<Difficulty>,
+    mut boosting: ResMut<Boosting>,
+) {
+    // Every key is handled as a single-shot action: `just_pressed` fires
+    // only on the frame the key goes down, so holding a key toggles once
+    // instead of flickering every frame, and each turn is queued once.
+    boosting.0 = keys.pressed(bindings.boost) && *gamestate.current() == GameState::Playing;
+    if keys.just_pressed(bindings.mute) {
+        audio_settings.muted = !audio_settings.muted;
+    }
+    if keys.just_pressed(bindings.fps) {
+        show_fps.0 = !show_fps.0;
+    }
+    if keys.just_pressed(bindings.step) && *gamestate.current() == GameState::Paused {
+        // Unless it's the answer to quitting.
+        if !confirm_quit.0 {
+            step.0 = true;
+        }
+    }
+    if keys.just_pressed(bindings.minimap) {
+        show_minimap.0 = !show_minimap.0;
+    }
+    if keys.just_pressed(bindings.practice) && *gamestate.current() == GameState::Menu {
+        practice.0 = !practice.0;
+    }
+    if keys.just_pressed(bindings.help) && *gamestate.current() == GameState::Menu {
+        show_help.0 = !show_help.0;
+    }
+    if keys.just_pressed(bindings.pause) {
+        if *gamestate.current() == GameState::Menu {
+            *mode = GameMode::Solo;
+            daily.0 = None;
+        }
+        toggle_pause(&mut gamestate);
+    }
+    if keys.just_pressed(bindings.two_players) && *gamestate.current() == GameState::Menu {
+        *mode = GameMode::Versus;
+        daily.0 = None;
+        toggle_pause(&mut gamestate);
+    }
+    if keys.just_pressed(bindings.versus_ai) && *gamestate.current() == GameState::Menu {
+        *mode = GameMode::VersusAi;
+        daily.0 = None;
+        toggle_pause(&mut gamestate);
+    }
+    // Restarting keeps playing the same daily, until back in the menu.
+    if keys.just_pressed(bindings.daily) && *gamestate.current() == GameState::Menu {
+        if let Some(day) = today_daily() {
+            *mode = GameMode::Solo;
+            daily.0 = Some(day);
+            toggle_pause(&mut gamestate);
+        }
+    }
+    if keys.just_pressed(KeyCode::Return) && matches!(gamestate.current(), GameState::Lost | GameState::Won) {
+        restart(&mut gamestate);
+    }
+    // Only between runs: the new presets apply from the next one.
+    if keys.just_pressed(bindings.difficulty)
+        && matches!(gamestate.current(), GameState::Menu | GameState::Lost | GameState::Won)
+    {
+        *difficulty = difficulty.next();
+    }
+    if keys.just_pressed(bindings.replay) {
+        if *gamestate.current() == GameState::Paused {
+            restart(&mut gamestate);
+        } else {
+            start_replay(&mut gamestate, &mut replay);
+        }
+    }
+    if keys.just_pressed(bindings.settings) {
+        toggle_settings(&mut gamestate);
+    }
+}
+
+// Esc backs out to the menu, and quits from there. Mid-run it only pauses
+// and asks first, so one stray press can't end the game. A key held since
+// before the question doesn't answer it: `just_pressed` only fires on the
+// frame a key goes down.
+fn back_input(
+    keys: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    mut app_exit_events: ResMut<Events<AppExit>>,
+    mut gamestate: ResMut<State<GameState>>,
+    mut confirm_quit: ResMut<ConfirmQuit>,
+) {
+    if *gamestate.current() != GameState::Paused {
+        confirm_quit.0 = false;
+    }
+    if confirm_quit.0 {
+        if keys.just_pressed(KeyCode::Y) {
+            app_exit_events.send(AppExit);
+        } else if keys.just_pressed(KeyCode::N) || keys.just_pressed(bindings.back) {
+            confirm_quit.0 = false;
+        }
+        return;
+    }
+    if keys.just_pressed(bindings.back) {
+        match gamestate.current() {
+            GameState::Menu => app_exit_events.send(AppExit),
+            GameState::Playing => {
+                gamestate.set_next(GameState::Paused).ok();
+                confirm_quit.0 = true;
+            }
+            // Nothing cuts the death animation short.
+            GameState::Dying => {}
+            // Left to the leaderboard, which knows where it was reached from.
+            GameState::Leaderboard => {}
+            _ => {
+                gamestate.set_next(GameState::Menu).ok();
+            }
+        }
+    }
+}
+
+// Turns are queued already swapped around while confused, so `snake_movement`
+// still checks them against the direction the snake actually goes in.
+fn direction_input(
+    keys: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    mode: Res<GameMode>,
+    scheme: Res<ControlScheme>,
+    inverted: Res<ControlsInverted>,
+    mut players: Query<(Entity, &Player, &mut LastInput), Without<AiController>>,
+) {
+    for (head, player, mut last_input) in players.iter_mut() {
+        if player.index == 0 && *scheme != ControlScheme::Keyboard {
+            continue;
+        }
+        let direction_keys = match *mode {
+            GameMode::Solo | GameMode::VersusAi => &bindings.solo,
+            GameMode::Versus => &bindings.versus[player.index],
+        };
+        if let Some(direction) = direction_keys.just_pressed(&keys) {
+            last_input.push(inverted.apply(head, direction));
+        }
+    }
+}
+
+// Regaining focus doesn't resume: the player does that with the pause key.
+fn pause_on_blur(
+    pause_on_blur: Res<PauseOnBlur>,
+    focus_events: Res<Events<WindowFocused>>,
+    mut focus_reader: Local<EventReader<WindowFocused>>,
+    mut gamestate: ResMut<State<GameState>>,
+) {
+    for event in focus_reader.iter(&focus_events) {
+        if pause_on_blur.0
+            && !event.focused
+            && event.id == WindowId::primary()
+            && *gamestate.current() == GameState::Playing
+        {
+            gamestate.set_next(GameState::Paused).ok();
+        }
+    }
+}
+
+// Gamepads only give input: Bevy's gamepad support has no way to make one
+// rumble yet, so eating and crashing can't be felt through it.
+fn gamepad_connections(
+    mut active: ResMut<ActiveGamepad>,
+    scheme: Res<ControlScheme>,
+    mut gamestate: ResMut<State<GameState>>,
+    mut lost: ResMut<GamepadLost>,
+    gamepad_events: Res<Events<GamepadEvent>>,
+    mut gamepad_reader: Local<EventReader<GamepadEvent>>,
+    // In the order they were plugged in.
+    mut connected: Local<Vec<Gamepad>>,
+) {
+    if lost.0 && *gamestate.current() != GameState::Paused {
+        lost.0 = false;
+    }
+    for GamepadEvent(gamepad, event) in gamepad_reader.iter(&gamepad_events) {
+        match event {
+            GamepadEventType::Connected => {
+                if !connected.contains(gamepad) {
+                    connected.push(*gamepad);
+                }
+                if active.0.is_none() {
+                    active.0 = Some(*gamepad);
+                    lost.0 = false;
+                }
+            }
+            GamepadEventType::Disconnected => {
+                connected.retain(|pad| pad != gamepad);
+                if active.0 != Some(*gamepad) {
+                    continue;
+                }
+                // Any other one still plugged in takes over.
+                active.0 = connected.first().copied();
+                // Rather than carry on with nothing steering.
+                if active.0.is_none()
+                    && *scheme == ControlScheme::Gamepad
+                    && *gamestate.current() == GameState::Playing
+                {
+                    gamestate.set_next(GameState::Paused).ok();
+                    lost.0 = true;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn gamepad_input(
+    active: Res<ActiveGamepad>,
+    scheme: Res<ControlScheme>,
+    buttons: Res<Input<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+    inverted: Res<ControlsInverted>,
+    mut players: Query<(Entity, &Player, &mut LastInput)>,
+    mut gamestate: ResMut<State<GameState>>,
+    mut last_stick: Local<Option<Direction>>,
+) {
+    let gamepad = if let Some(gamepad) = active.0 {
+        gamepad
+    } else {
+        return;
+    };
+    if buttons.just_pressed(GamepadButton(gamepad, GamepadButtonType::South)) {
+        toggle_pause(&mut gamestate);
+    }
+    if buttons.just_pressed(GamepadButton(gamepad, GamepadButtonType::Start)) {
+        restart(&mut gamestate);
+    }
+    // The gamepad steers player one, when it's the one steering.
+    if *scheme != ControlScheme::Gamepad {
+        return;
+    }
+    let player_one = players.iter_mut().find(|(_, p, _)| p.index == 0);
+    let (head, mut last_input) = if let Some((head, _, last_input)) = player_one {
+        (head, last_input)
+    } else {
+        return;
+    };
+
+    let dpad = if buttons.just_pressed(GamepadButton(gamepad, GamepadButtonType::DPadLeft)) {
+        Some(Direction::Left)
+    } else if buttons.just_pressed(GamepadButton(gamepad, GamepadButtonType::DPadRight)) {
+        Some(Direction::Right)
+    } else if buttons.just_pressed(GamepadButton(gamepad, GamepadButtonType::DPadDown)) {
+        Some(Direction::Down)
+    } else if buttons.just_pressed(GamepadButton(gamepad, GamepadButtonType::DPadUp)) {
+        Some(Direction::Up)
+    } else {
+        None
+    };
+    if let Some(direction) = dpad {
+        last_input.push(inverted.apply(head, direction));
+    }
+
+    // The stick only turns the snake once it leaves the deadzone along its
+    // dominant axis, and only again after it changes direction or recenters.
+    let x = axes.get(GamepadAxis(gamepad, GamepadAxisType::LeftStickX)).unwrap_or(0.);
+    let y = axes.get(GamepadAxis(gamepad, GamepadAxisType::LeftStickY)).unwrap_or(0.);
+    let stick = if x.abs().max(y.abs()) < GAMEPAD_DEADZONE {
+        None
+    } else if x.abs() > y.abs() {
+        Some(if x < 0. { Direction::Left } else { Direction::Right })
+    } else {
+        Some(if y < 0. { Direction::Down } else { Direction::Up })
+    };
+    if stick != *last_stick {
+        if let Some(direction) = stick {
+            last_input.push(inverted.apply(head, direction));
+        }
+        *last_stick = stick;
+    }
+}
+
+// Steers player one toward the cursor, along whichever axis it's further
+// from the head on. A turn straight back is left out, so with the cursor
+// behind it the snake keeps going until the cursor is off to a side.
+fn mouse_input(
+    scheme: Res<ControlScheme>,
+    windows: Res<Windows>,
+    level: Res<Level>,
+    inverted: Res<ControlsInverted>,
+    mut players: Query<(Entity, &Player, &Position, &mut LastInput), Without<AiController>>,
+) {
+    if *scheme != ControlScheme::Mouse {
+        return;
+    }
+    let window = windows.get_primary().unwrap();
+    let cursor = if let Some(cursor) = window.cursor_position() {
+        cursor
+    } else {
+        return;
+    };
+    let player_one = players.iter_mut().find(|(_, p, _, _)| p.index == 0);
+    let (head, player, pos, mut last_input) = if let Some(player_one) = player_one {
+        player_one
+    } else {
+        return;
+    };
+    // The cursor is measured from the window's bottom left corner, the board
+    // from its center.
+    let tile_size = tile_size(window, &level);
+    let head_on_screen = Vec2::new(
+        convert(pos.x as f32, level.width as f32, tile_size),
+        convert(pos.y as f32, level.height as f32, tile_size),
+    ) + Vec2::new(window.width(), window.height()) / 2.;
+    let delta = cursor - head_on_screen;
+    // Over the head itself, there's no telling which way.
+    if delta.x.abs().max(delta.y.abs()) < tile_size / 2. {
+        return;
+    }
+    let aim = if delta.x.abs() > delta.y.abs() {
+        if delta.x < 0. { Direction::Left } else { Direction::Right }
+    } else if delta.y < 0. {
+        Direction::Down
+    } else {
+        Direction::Up
+    };
+    let direction = inverted.apply(head, aim);
+    if direction != player.direction && direction != player.direction.opposite() {
+        last_input.push(direction);
+    }
+}
+
+#[cfg(feature = "audio")]
+fn music_player(
+    gamestate: Res<State<GameState>>,
+    audio_settings: Res<AudioSettings>,
+    audio: Res<Audio>,
+    time: Res<Time>,
+    mut music: ResMut<Music>,
+) {
+    if *gamestate.current() != GameState::Playing || audio_settings.muted {
+        // Keep the timer expired so the track starts right away on resume.
+        let duration = music.timer.duration();
+        music.timer.tick(duration);
+        return;
+    }
+    if music.timer.finished() {
+        audio.play(music.track.clone());
+        music.timer.reset();
+    }
+    music.timer.tick(time.delta_seconds());
+}
+
+// Turns queued during the countdown still apply on the first tick.
+fn countdown(mut gamestate: ResMut<State<GameState>>, time: Res<Time>, mut countdown: ResMut<Countdown>) {
+    if *gamestate.current() != GameState::Countdown {
+        return;
+    }
+    countdown.timer.tick(time.delta_seconds());
+    if countdown.timer.finished() {
+        gamestate.set_next(GameState::Playing).ok();
+    }
+}
+
+fn tick_move_timer(
+    gamestate: Res<State<GameState>>,
+    time: Res<Time>,
+    boosting: Res<Boosting>,
+    mut timer: ResMut<MoveTimer>,
+) {
+    // Paused time doesn't count towards the next tick. Boosting runs the
+    // clock faster rather than changing the interval, so everything counted
+    // in ticks keeps its pace per tick, and letting go is felt right away.
+    if matches!(gamestate.current(), GameState::Playing | GameState::Replay | GameState::Demo) {
+        let speed = if boosting.0 { 2. } else { 1. };
+        timer.0.tick(time.delta_seconds() * speed);
+    }
+}
+
+fn move_timestep(timer: Res<MoveTimer>) -> ShouldRun {
+    if timer.0.just_finished() {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
+fn step_timestep(mut step: ResMut<StepRequested>) -> ShouldRun {
+    if step.0 {
+        step.0 = false;
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
+// Playing and replaying a run share the same movement tick. Each plugin
+// fills it in with `add_tick_system`, in the order the plugins are added.
+fn tick_stage() -> SystemStage {
+    SystemStage::parallel().with_run_criteria(move_timestep.system())
+}
+
+// The same tick while paused, run only when a step is requested.
+fn step_stage() -> SystemStage {
+    SystemStage::parallel().with_run_criteria(step_timestep.system())
+}
+
+// Runs a system on every movement tick, while playing, replaying, running
+// the demo, and stepping through a paused run. Each state needs its own instance, so it
+// takes a constructor.
+fn add_tick_system<S: System<In = (), Out = ()>>(app: &mut AppBuilder, system: impl Fn() -> S) {
+    app.on_state_update(GAME_STATES, GameState::Playing, system());
+    app.on_state_update(GAME_STATES, GameState::Replay, system());
+    app.on_state_update(GAME_STATES, GameState::Demo, system());
+    app.on_state_update(GAME_STATES, GameState::Paused, system());
+}
+
+// The argument following `name` on the command line, if any.
+fn arg_value(name: &str) -> Option<String> {
+    let mut args = std::env::args().skip_while(|arg| arg != name);
+    args.next()?;
+    args.next()
+}
+
+// Every argument takes a value, so anything else where a name is expected
+// is unknown.
+fn check_args() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.chunks(2).any(|pair| pair.len() < 2 || !ARGS.contains(&pair[0].as_str())) {
+        eprintln!("{}", USAGE);
+    }
+}
+
+// The value following `name`, if it parses and passes `valid`. Otherwise the
+// usage is printed, and the caller falls back to its default.
+fn parsed_arg<T: std::str::FromStr>(name: &str, valid: impl Fn(&T) -> bool) -> Option<T> {
+    let value = arg_value(name)?;
+    match value.parse() {
+        Ok(parsed) if valid(&parsed) => Some(parsed),
+        _ => {
+            eprintln!("invalid value {:?} for {}\n{}", value, name, USAGE);
+            None
+        }
+    }
+}
+
+// `--seed <n>` on the command line, or `DEFAULT_SEED`.
+fn seed_from_args() -> u64 {
+    parsed_arg("--seed", |_| true).unwrap_or(DEFAULT_SEED)
+}
+
+// `--combo-window <seconds>` on the command line, or `COMBO_WINDOW`.
+fn combo_window_from_args() -> f32 {
+    parsed_arg("--combo-window", |window: &f32| *window > 0.).unwrap_or(COMBO_WINDOW)
+}
+
+// `--wrap <both|x|y|none>` on the command line picks the edges snakes pass
+// through; by default they all do.
+fn wrap_axes_from_args() -> WrapAxes {
+    parsed_arg("--wrap", |name: &String| WrapAxes::from_name(name).is_some())
+        .and_then(|name| WrapAxes::from_name(&name))
+        .unwrap_or_default()
+}
+
+// `--movement <classic|rigid>` on the command line; classic by default.
+fn movement_style_from_args() -> MovementStyle {
+    parsed_arg("--movement", |name: &String| MovementStyle::from_name(name).is_some())
+        .and_then(|name| MovementStyle::from_name(&name))
+        .unwrap_or_default()
+}
+
+// `--wrap-anim <slide|dip>` on the command line; slide by default.
+fn wrap_animation_from_args() -> WrapAnimation {
+    parsed_arg("--wrap-anim", |name: &String| WrapAnimation::from_name(name).is_some())
+        .and_then(|name| WrapAnimation::from_name(&name))
+        .unwrap_or_default()
+}
+
+// `--speed <seconds per move>` on the command line overrides every
+// difficulty's own speed.
+fn presets_from_args() -> DifficultyPresets {
+    let presets = DifficultyPresets::default();
+    match parsed_arg("--speed", |interval: &f32| (0.02..=1.).contains(interval)) {
+        Some(interval) => presets.with_move_interval(interval),
+        None => presets,
+    }
+}
+
+fn main() {
+    check_args();
+    let mut app = App::build();
     app.add_resource(WindowDescriptor {
             title: "Snake!".to_owned(),
             width: 600.,
@@ -598,33 +1982,216 @@ fn main() {
     app.add_plugins(DefaultPlugins);
     #[cfg(target_arch = "wasm32")]
     app.add_plugin(bevy_webgl2::WebGL2Plugin);
+    #[cfg(feature = "audio")]
+    app.add_system(music_player.system());
     app
         .add_resource(ClearColor(Color::rgb(0.1, 0.1, 0.1)))
-        .add_plugin(FrameTimeDiagnosticsPlugin::default())
         .add_startup_system(setup.system())
         .add_startup_stage(
-            "game_setup",
-            SystemStage::serial().with_system(game_setup.system()),
+            BOARD_SETUP,
+            SystemStage::serial().with_system(board_setup.system()),
         )
-        .add_event::<EatEvent>()
-        .add_event::<BumpEvent>()
-        .add_resource(State::new(GameState::Paused))
-        .add_resource(LastInput{direction:Direction::Up})
+        .add_resource(State::new(GameState::Menu))
+        .add_resource(GameMode::default())
+        .add_resource(presets_from_args())
+        .add_resource(MoveTimer::default())
+        .add_resource(KeyBindings::default())
+        .add_resource(ActiveGamepad::default())
+        .add_resource(Boosting::default())
+        .add_resource(Practice::default())
+        .add_resource(StepRequested::default())
+        .add_resource(Daily::default())
+        .add_resource(PauseOnBlur::default())
+        .add_resource(ConfirmQuit::default())
+        .add_resource(GamepadLost::default())
+        .add_resource(GameRng::from_seed(seed_from_args()))
+        .add_resource(Recorder::default())
+        .add_resource(Replay::default())
+        .add_resource(Countdown::default())
+        // Before the plugins, which read their parts of it as they're added.
+        .add_resource(Config::load())
         .add_system(input_events_sender.system())
-        .add_system(update_fps.system())
-        .add_stage_after(stage::UPDATE, "game_states", StateStage::<GameState>::default()
-            .with_update_stage(GameState::Playing, SystemStage::parallel()
-                .with_run_criteria(FixedTimestep::step(FIXED_TIMESTEP))
-                .with_system(food_spawner.system())
-                .with_system(segment_movement.system())
-                .with_system(snake_movement.system())
-                .with_system(collision_solver.system())
-                .with_system(eat_events_solver.system())
-                .with_system(bump_events_solver.system())
-                .with_system(update_hud.system())
+        // After it, so it still sees the quit question N answers.
+        .add_system(back_input.system())
+        .add_system(direction_input.system())
+        .add_system(pause_on_blur.system())
+        .add_system(gamepad_connections.system())
+        .add_system(gamepad_input.system())
+        .add_system(mouse_input.system())
+        .add_system(countdown.system())
+        .add_system(tick_move_timer.system())
+        // The plugins hook into these stages, so they have to exist first.
+        .add_stage_after(stage::UPDATE, GAME_STATES, StateStage::<GameState>::default()
+            // Any run still on the board is abandoned on the way back.
+            .with_enter_stage(GameState::Menu, SystemStage::serial()
+                .with_system(game_cleanup.system())
+            )
+            .with_exit_stage(GameState::Menu, SystemStage::serial()
+                .with_system(game_setup.system())
+            )
+            .with_enter_stage(GameState::Lost, SystemStage::serial()
+                .with_system(save_replay.system())
+            )
+            .with_exit_stage(GameState::Lost, SystemStage::serial()
+                .with_system(game_cleanup.system())
+                .with_system(game_setup.system())
+            )
+            .with_enter_stage(GameState::Won, SystemStage::serial()
+                .with_system(save_replay.system())
+            )
+            .with_exit_stage(GameState::Won, SystemStage::serial()
+                .with_system(game_cleanup.system())
+                .with_system(game_setup.system())
             )
+            // Restarting from the pause.
+            .with_exit_stage(GameState::Paused, SystemStage::serial()
+                .with_system(game_cleanup.system())
+                .with_system(game_setup.system())
+            )
+            // Starting a real run from the demo.
+            .with_exit_stage(GameState::Demo, SystemStage::serial()
+                .with_system(game_cleanup.system())
+                .with_system(game_setup.system())
+            )
+            .with_update_stage(GameState::Playing, tick_stage())
+            .with_update_stage(GameState::Replay, tick_stage())
+            .with_update_stage(GameState::Demo, tick_stage())
+            .with_update_stage(GameState::Paused, step_stage())
         )
+        .add_plugin(SnakePlugin)
+        // Before the collision systems, so a snake crushed by the shrinking
+        // arena crashes on the same tick.
+        .add_plugin(ShrinkPlugin)
+        // Also before the collision systems, so pulled food is eaten as soon
+        // as it reaches the head.
+        .add_plugin(MagnetPlugin)
+        // After the snakes move, and before the collision systems, so a bite
+        // crashes on the same tick.
+        .add_plugin(PoisonPlugin)
+        // Before the collision systems too, so the stress test's snakes are
+        // checked where they just moved.
+        .add_plugin(StressPlugin)
+        .add_plugin(CollisionPlugin)
+        .add_plugin(FoodPlugin)
+        .add_plugin(FrenzyPlugin)
+        .add_plugin(ObjectivePlugin)
+        .add_plugin(SurvivalPlugin)
+        // Before the UI, so the game over screen shows this run in the totals.
+        .add_plugin(StatsPlugin)
+        .add_plugin(UiPlugin)
+        .add_plugin(SettingsPlugin)
+        .add_plugin(FogPlugin)
+        .add_plugin(MinimapPlugin)
+        .add_plugin(KillCamPlugin)
+        .add_plugin(BestRunPlugin)
+        .add_plugin(SaveGamePlugin)
+        .add_plugin(AttractPlugin)
+        .add_plugin(GridCoordsPlugin)
+        .add_plugin(LeaderboardPlugin)
+        .add_plugin(SpeedLinesPlugin)
+        .add_plugin(ScorePopupsPlugin)
+        .add_plugin(FrameGraphPlugin)
         .add_system(position_translation.system())
+        .add_system(render_interpolation.system())
         .add_system(size_scaling.system())
+        .add_system(letterbox.system())
+        .add_system(letterbox_color.system())
+        .add_system(layout_report.system())
         .run();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::run_system;
+
+    #[test]
+    fn pick_free_tile_on_a_full_board() {
+        let level = Level::default();
+        let tiles = ArenaTiles::new(&level);
+        let occupied: HashSet<Position> = tiles.0.iter().copied().collect();
+        assert_eq!(tiles.pick_free_tile(&occupied, &mut GameRng::from_seed(DEFAULT_SEED)), None);
+    }
+
+    #[test]
+    fn pick_free_tile_finds_the_last_one() {
+        let level = Level::default();
+        let tiles = ArenaTiles::new(&level);
+        let free = Position { x: 3, y: 11 };
+        let occupied: HashSet<Position> = tiles.0.iter().copied().filter(|pos| *pos != free).collect();
+        let mut rng = GameRng::from_seed(DEFAULT_SEED);
+        for _ in 0..20 {
+            assert_eq!(tiles.pick_free_tile(&occupied, &mut rng), Some(free));
+        }
+    }
+
+    // Each action gets its own key. Versus splits the solo keys between the
+    // players, and the quit question's Y and N only count while it's asked.
+    #[test]
+    fn key_bindings_dont_clash() {
+        let bindings = KeyBindings::default();
+        let mut keys = vec![
+            bindings.pause,
+            bindings.back,
+            bindings.mute,
+            bindings.replay,
+            bindings.two_players,
+            bindings.versus_ai,
+            bindings.difficulty,
+            bindings.fps,
+            bindings.layout,
+            bindings.settings,
+            bindings.boost,
+            bindings.help,
+            bindings.minimap,
+            bindings.practice,
+            bindings.daily,
+            bindings.step,
+            bindings.save,
+            bindings.load,
+            bindings.grid_coords,
+            bindings.leaderboard,
+            bindings.frame_graph,
+        ];
+        let solo = &bindings.solo;
+        keys.extend(solo.up.iter().chain(&solo.down).chain(&solo.left).chain(&solo.right).copied());
+        let unique: HashSet<KeyCode> = keys.iter().copied().collect();
+        assert_eq!(unique.len(), keys.len(), "{:?}", keys);
+    }
+
+    // Sends `events` and runs `gamepad_connections` over them once, with
+    // the controls on `Gamepad` during a run.
+    fn gamepads_after(events: &[(usize, GamepadEventType)]) -> App {
+        let mut app = App::default();
+        app.resources.insert(State::new(GameState::Playing));
+        app.resources.insert(ActiveGamepad::default());
+        app.resources.insert(ControlScheme::Gamepad);
+        app.resources.insert(GamepadLost::default());
+        let mut gamepad_events = Events::<GamepadEvent>::default();
+        for (id, event) in events {
+            gamepad_events.send(GamepadEvent(Gamepad(*id), event.clone()));
+        }
+        app.resources.insert(gamepad_events);
+        run_system(&mut app, gamepad_connections.system());
+        app
+    }
+
+    #[test]
+    fn unplugging_the_gamepad_falls_back_to_another() {
+        use GamepadEventType::{Connected, Disconnected};
+        let app = gamepads_after(&[(0, Connected), (1, Connected), (0, Disconnected)]);
+        assert_eq!(app.resources.get::<ActiveGamepad>().unwrap().0, Some(Gamepad(1)));
+        assert!(!app.resources.get::<GamepadLost>().unwrap().0);
+        assert_eq!(*app.resources.get::<ControlScheme>().unwrap(), ControlScheme::Gamepad);
+    }
+
+    #[test]
+    fn unplugging_the_last_gamepad_pauses_and_keeps_the_controls() {
+        use GamepadEventType::{Connected, Disconnected};
+        let app = gamepads_after(&[(0, Connected), (0, Disconnected)]);
+        assert_eq!(app.resources.get::<ActiveGamepad>().unwrap().0, None);
+        assert!(app.resources.get::<GamepadLost>().unwrap().0);
+        assert!(app.resources.get::<State<GameState>>().unwrap().next() == Some(&GameState::Paused));
+        assert_eq!(*app.resources.get::<ControlScheme>().unwrap(), ControlScheme::Gamepad);
+    }
+}
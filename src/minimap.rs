@@ -0,0 +1,125 @@
+use bevy::prelude::*;
+use bevy::render::draw::Visible;
+use bevy::render::texture::{Extent3d, TextureDimension, TextureFormat};
+
+use crate::food::Food;
+use crate::snake::{Snake, SnakeHead};
+use crate::{add_tick_system, Level, Position, Wall, ARENA_MARGIN, BOARD_SETUP};
+
+// Screen pixels per tile.
+const MINIMAP_SCALE: f32 = 3.;
+
+const EMPTY_COLOR: [u8; 4] = [20, 20, 20, 200];
+const WALL_COLOR: [u8; 4] = [120, 120, 120, 255];
+const FOOD_COLOR: [u8; 4] = [255, 0, 255, 255];
+const BODY_COLOR: [u8; 4] = [180, 180, 180, 255];
+const HEAD_COLOR: [u8; 4] = [255, 255, 255, 255];
+
+// The whole arena drawn a pixel per tile in a corner, for boards too big to
+// take in at a glance.
+pub(crate) struct MinimapPlugin;
+impl Plugin for MinimapPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(ShowMinimap::default())
+            .add_startup_system_to_stage(BOARD_SETUP, minimap_setup.system())
+            .add_system(show_minimap.system());
+        add_tick_system(app, || update_minimap.system());
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct ShowMinimap(pub(crate) bool);
+
+// The texture the minimap is drawn into.
+struct MinimapImage(Handle<Texture>);
+
+fn minimap_setup(
+    commands: &mut Commands,
+    level: Res<Level>,
+    mut textures: ResMut<Assets<Texture>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let texture = textures.add(Texture::new_fill(
+        Extent3d::new(level.width, level.height, 1),
+        TextureDimension::D2,
+        &EMPTY_COLOR,
+        TextureFormat::Rgba8UnormSrgb,
+    ));
+    commands
+        .spawn(ImageBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(ARENA_MARGIN + 10.),
+                    right: Val::Px(10.),
+                    ..Default::default()
+                },
+                size: bevy::prelude::Size::new(
+                    Val::Px(level.width as f32 * MINIMAP_SCALE),
+                    Val::Px(level.height as f32 * MINIMAP_SCALE),
+                ),
+                ..Default::default()
+            },
+            material: materials.add(ColorMaterial::texture(texture.clone())),
+            visible: Visible {
+                is_visible: false,
+                is_transparent: true,
+            },
+            ..Default::default()
+        })
+        .with(MinimapImage(texture));
+}
+
+fn show_minimap(show: ChangedRes<ShowMinimap>, mut images: Query<&mut Visible, With<MinimapImage>>) {
+    for mut visible in images.iter_mut() {
+        visible.is_visible = show.0;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_minimap(
+    show: Res<ShowMinimap>,
+    level: Res<Level>,
+    mut textures: ResMut<Assets<Texture>>,
+    images: Query<&MinimapImage>,
+    walls: Query<&Position, With<Wall>>,
+    foods: Query<&Position, With<Food>>,
+    bodies: Query<&Position, (With<Snake>, Without<SnakeHead>)>,
+    heads: Query<&Position, With<SnakeHead>>,
+) {
+    // Nothing to pay for while it's hidden.
+    if !show.0 {
+        return;
+    }
+    for image in images.iter() {
+        let texture = match textures.get_mut(&image.0) {
+            Some(texture) => texture,
+            None => continue,
+        };
+        for pixel in texture.data.chunks_mut(4) {
+            pixel.copy_from_slice(&EMPTY_COLOR);
+        }
+        // Each layer draws over the ones before, so heads always show.
+        for pos in walls.iter() {
+            paint(&mut texture.data, &level, pos, &WALL_COLOR);
+        }
+        for pos in foods.iter() {
+            paint(&mut texture.data, &level, pos, &FOOD_COLOR);
+        }
+        for pos in bodies.iter() {
+            paint(&mut texture.data, &level, pos, &BODY_COLOR);
+        }
+        for pos in heads.iter() {
+            paint(&mut texture.data, &level, pos, &HEAD_COLOR);
+        }
+    }
+}
+
+// Texture rows go top to bottom, while `y` grows upwards.
+fn paint(data: &mut [u8], level: &Level, pos: &Position, color: &[u8; 4]) {
+    let row = level.height as i32 - 1 - pos.y;
+    let i = (row * level.width as i32 + pos.x) as usize * 4;
+    if let Some(pixel) = data.get_mut(i..i + 4) {
+        pixel.copy_from_slice(color);
+    }
+}
@@ -0,0 +1,66 @@
+use bevy::prelude::*;
+
+use crate::snake::{snake_length, Player, SnakeSegment};
+use crate::{parsed_arg, GameState, GAME_STATES};
+
+// `--goal <length>` turns a run into a race: the first snake to grow that
+// long wins it, and the game over screen says how long it took.
+pub(crate) struct ObjectivePlugin;
+impl Plugin for ObjectivePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(objective_from_args())
+            .on_state_enter(GAME_STATES, GameState::Countdown, objective_reset.system())
+            .on_state_enter(GAME_STATES, GameState::Replay, objective_reset.system())
+            .add_system(objective_check.system());
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Objective {
+    // No goal without `--goal`: runs go on until a crash.
+    pub(crate) target_len: Option<u32>,
+    // The index of the player who got there first, this run.
+    pub(crate) reached_by: Option<usize>,
+}
+
+fn objective_from_args() -> Objective {
+    Objective {
+        target_len: parsed_arg("--goal", |length: &u32| *length > 1),
+        reached_by: None,
+    }
+}
+
+fn objective_reset(mut objective: ResMut<Objective>) {
+    objective.reached_by = None;
+}
+
+// Every frame rather than on the tick: the segments a snake grows by are only
+// there once the tick's commands have been applied. A replay of a won run
+// ends where the run did, back in the menu.
+fn objective_check(
+    mut gamestate: ResMut<State<GameState>>,
+    mut objective: ResMut<Objective>,
+    players: Query<(Entity, &Player)>,
+    segments: Query<&SnakeSegment>,
+) {
+    let target = match objective.target_len {
+        Some(target) => target as usize,
+        None => return,
+    };
+    if !matches!(gamestate.current(), GameState::Playing | GameState::Replay) || objective.reached_by.is_some() {
+        return;
+    }
+    // Two snakes getting there on the same tick go to the lower index.
+    let mut players: Vec<(Entity, &Player)> = players.iter().collect();
+    players.sort_by_key(|(_, player)| player.index);
+    let winner = players.iter().find(|(head, _)| snake_length(*head, &segments) >= target);
+    if let Some((_, player)) = winner {
+        objective.reached_by = Some(player.index);
+        let next = if *gamestate.current() == GameState::Replay {
+            GameState::Menu
+        } else {
+            GameState::Won
+        };
+        gamestate.set_next(next).ok();
+    }
+}
@@ -0,0 +1,118 @@
+use bevy::prelude::*;
+
+use crate::collision::BumpEvent;
+use crate::snake::{snake_body, SnakeHead, SnakeSegment};
+use crate::{add_tick_system, parsed_arg, Materials, MoveTimer, Position, Size};
+
+// The "poison trail" hazard mode, on when `--poison` is given: every tile a
+// tail leaves behind turns poisonous for a few ticks, and a head moving onto
+// one crashes.
+pub(crate) struct PoisonPlugin;
+impl Plugin for PoisonPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(PoisonTrail::new(poison_from_args()));
+        add_tick_system(app, || poison_bite.system());
+        add_tick_system(app, || poison_expiry.system());
+        add_tick_system(app, || poison_trail.system());
+    }
+}
+
+// How many ticks a poison tile lasts, and where each snake's tail was at
+// the end of the last tick.
+pub(crate) struct PoisonTrail {
+    lifetime: Option<u32>,
+    tails: Vec<(Entity, Position)>,
+}
+impl PoisonTrail {
+    fn new(lifetime: Option<u32>) -> Self {
+        Self {
+            lifetime,
+            tails: Vec::new(),
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.tails.clear();
+    }
+}
+
+// Counted in ticks, like the ghost effect. Not a `Tile`, so nothing is
+// placed on it while it lasts.
+pub(crate) struct Poison {
+    timer: Timer,
+}
+
+// `--poison <ticks>` on the command line; without it there's no trail.
+fn poison_from_args() -> Option<u32> {
+    parsed_arg("--poison", |ticks: &u32| *ticks > 0)
+}
+
+// Only poison left on earlier ticks counts, so a head can still follow
+// right behind its own tail.
+fn poison_bite(
+    mut bump_events: ResMut<Events<BumpEvent>>,
+    heads: Query<(Entity, &Position), With<SnakeHead>>,
+    poisons: Query<(Entity, &Position), With<Poison>>,
+) {
+    for (head, head_pos) in heads.iter() {
+        if let Some((poison, _)) = poisons.iter().find(|(_, pos)| *pos == head_pos) {
+            bump_events.send(BumpEvent {
+                head,
+                wall: Some(poison),
+            });
+        }
+    }
+}
+
+fn poison_expiry(
+    commands: &mut Commands,
+    move_timer: Res<MoveTimer>,
+    mut poisons: Query<(Entity, &mut Poison)>,
+) {
+    for (entity, mut poison) in poisons.iter_mut() {
+        poison.timer.tick(move_timer.interval());
+        if poison.timer.finished() {
+            commands.despawn(entity);
+        }
+    }
+}
+
+// A tail that moved on leaves poison on the tile it was on, unless the
+// snake still covers it (it just grew, or it's coiled up).
+fn poison_trail(
+    commands: &mut Commands,
+    materials: Res<Materials>,
+    move_timer: Res<MoveTimer>,
+    mut trail: ResMut<PoisonTrail>,
+    heads: Query<Entity, With<SnakeHead>>,
+    segments: Query<&SnakeSegment>,
+    positions: Query<&Position>,
+) {
+    let lifetime = match trail.lifetime {
+        Some(lifetime) => lifetime,
+        None => return,
+    };
+    let mut tails = Vec::new();
+    for head in heads.iter() {
+        let body = snake_body(head, &segments, &positions);
+        let old_tail = trail.tails.iter().find(|(h, _)| *h == head).map(|(_, pos)| *pos);
+        if let Some(old_tail) = old_tail.filter(|pos| !body.contains(pos)) {
+            let lifetime = move_timer.interval() * lifetime as f32;
+            commands
+                .spawn(SpriteBundle {
+                    material: materials.poison_material.clone(),
+                    transform: Transform::from_translation(Vec3::new(0., 0., 0.6)),
+                    ..Default::default()
+                })
+                .with(Poison {
+                    timer: Timer::from_seconds(lifetime, false),
+                })
+                .with(old_tail)
+                .with(Size::square(0.9));
+        }
+        if let Some(&tail) = body.last() {
+            tails.push((head, tail));
+        }
+    }
+    trail.tails = tails;
+}
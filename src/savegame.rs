@@ -0,0 +1,341 @@
+use bevy::ecs::Or;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::food::{spawn_food, Combo, Food, FoodKind};
+use crate::poison::Poison;
+use crate::shrink::ShrinkTimer;
+use crate::best_run::CurrentRun;
+use crate::snake::{snake_body, spawn_snake, step, AiController, LastInput, Player, Snake, SnakeSegment};
+use crate::survival::GrowingWalls;
+use crate::{
+    insert_run_resources, restart, toggle_pause, Daily, Difficulty, DifficultyPresets, Direction, GameMode, GameRng,
+    GameState, KeyBindings, Level, Materials, Obstacle, Position, Recorder, Size, Wall,
+};
+
+const SAVE_PATH: &str = "game.sav";
+
+// F5 saves the run in progress, F9 picks it back up, from the menu or in
+// place of a paused run. A loaded run starts paused.
+pub(crate) struct SaveGamePlugin;
+impl Plugin for SaveGamePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system(save_game.system()).add_system(load_game.system());
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedSnake {
+    index: usize,
+    direction: Direction,
+    food: u32,
+    // Head first. The links between segments follow the order.
+    body: Vec<Position>,
+}
+
+// Only the board and the generator carry over: the timers, effects and stats
+// of the run start over, and a loaded run isn't recorded for replaying.
+#[derive(Serialize, Deserialize)]
+struct SaveGame {
+    width: u32,
+    height: u32,
+    mode: GameMode,
+    difficulty: Difficulty,
+    // Where the run's generator was, so the loaded run goes on drawing the
+    // same food as it would have.
+    rng: GameRng,
+    shrunk_rings: i32,
+    snakes: Vec<SavedSnake>,
+    foods: Vec<(Position, FoodKind)>,
+    obstacles: Vec<Position>,
+}
+impl SaveGame {
+    fn validate(&self, level: &Level) -> Result<(), String> {
+        if (self.width, self.height) != (level.width, level.height) {
+            return Err(format!("saved on a {}x{} arena", self.width, self.height));
+        }
+        let inside = |pos: &Position| {
+            pos.x >= 0 && pos.y >= 0 && pos.x < level.width as i32 && pos.y < level.height as i32
+        };
+        let mut indices: Vec<usize> = self.snakes.iter().map(|snake| snake.index).collect();
+        indices.sort_unstable();
+        if indices != (0..self.mode.players()).collect::<Vec<_>>() {
+            return Err(format!("wrong players {:?} for {}", indices, self.mode.name()));
+        }
+        for snake in &self.snakes {
+            if snake.body.is_empty() || !snake.body.iter().all(inside) {
+                return Err(format!("player {}'s snake is empty or off the arena", snake.index + 1));
+            }
+            if !snake.body.windows(2).all(|parts| linked(parts[0], parts[1], level)) {
+                return Err(format!("player {}'s snake is broken up", snake.index + 1));
+            }
+        }
+        if !self.foods.iter().map(|(pos, _)| pos).chain(self.obstacles.iter()).all(inside) {
+            return Err("food or walls off the arena".to_string());
+        }
+        Ok(())
+    }
+}
+
+// Whether two parts can be next to each other in a snake: on the same tile
+// (a segment just grown), a step apart, or a step from either end of a
+// portal the snake is going through.
+fn linked(a: Position, b: Position, level: &Level) -> bool {
+    let near = |a: Position, b: Position| {
+        let directions = [Direction::Up, Direction::Left, Direction::Down, Direction::Right];
+        a == b || directions.iter().any(|direction| step(a, *direction, level) == b)
+    };
+    near(a, b)
+        || level
+            .portals
+            .iter()
+            .any(|&(p, q)| (near(a, p) && near(b, q)) || (near(a, q) && near(b, p)))
+}
+
+// Head first, as `SavedSnake` keeps them.
+fn saved_snakes(
+    players: &Query<(Entity, &Player)>,
+    segments: &Query<&SnakeSegment>,
+    positions: &Query<&Position>,
+) -> Vec<SavedSnake> {
+    players
+        .iter()
+        .map(|(head, player)| SavedSnake {
+            index: player.index,
+            direction: player.direction,
+            food: player.food,
+            body: snake_body(head, segments, positions),
+        })
+        .collect()
+}
+
+// Puts a save's snakes, food and obstacles on an empty board.
+fn spawn_saved(commands: &mut Commands, materials: &Materials, save: &SaveGame) {
+    for snake in &save.snakes {
+        let body_material = if snake.index == 0 {
+            materials.body_material.clone()
+        } else {
+            materials.second_body_material.clone()
+        };
+        let head = spawn_snake(commands, materials, body_material, &snake.body);
+        commands.insert(head, (
+            Player {
+                index: snake.index,
+                direction: snake.direction,
+                food: snake.food,
+            },
+            LastInput::default(),
+        ));
+        if save.mode == GameMode::VersusAi && snake.index == 1 {
+            commands.insert_one(head, AiController);
+        }
+    }
+    for &(pos, kind) in &save.foods {
+        spawn_food(commands, materials, kind, pos);
+    }
+    for &pos in &save.obstacles {
+        commands
+            .spawn(SpriteBundle {
+                material: materials.wall_material.clone(),
+                transform: Transform::from_translation(Vec3::new(0., 0., 0.5)),
+                ..Default::default()
+            })
+            .with(Wall)
+            .with(Obstacle)
+            .with(pos)
+            .with(Size::square(1.));
+    }
+}
+
+// Saving leaves the run alone: the generator is copied, not reseeded.
+#[allow(clippy::too_many_arguments)]
+fn save_game(
+    keys: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    gamestate: Res<State<GameState>>,
+    level: Res<Level>,
+    recorder: Res<Recorder>,
+    shrink: Res<ShrinkTimer>,
+    rng: Res<GameRng>,
+    players: Query<(Entity, &Player)>,
+    segments: Query<&SnakeSegment>,
+    positions: Query<&Position>,
+    foods: Query<(&Position, &FoodKind)>,
+    obstacles: Query<&Position, With<Obstacle>>,
+) {
+    if !keys.just_pressed(bindings.save) || !matches!(gamestate.current(), GameState::Playing | GameState::Paused) {
+        return;
+    }
+    let save = SaveGame {
+        width: level.width,
+        height: level.height,
+        mode: recorder.mode,
+        difficulty: recorder.difficulty,
+        rng: (*rng).clone(),
+        shrunk_rings: shrink.rings(),
+        snakes: saved_snakes(&players, &segments, &positions),
+        foods: foods.iter().map(|(pos, kind)| (*pos, *kind)).collect(),
+        obstacles: obstacles.iter().cloned().collect(),
+    };
+    let result = ron::ser::to_string(&save)
+        .map_err(|err| err.to_string())
+        .and_then(|data| std::fs::write(SAVE_PATH, data).map_err(|err| err.to_string()));
+    match result {
+        Ok(()) => info!("saved the game to {}", SAVE_PATH),
+        Err(err) => warn!("could not save the game to {}: {}", SAVE_PATH, err),
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn load_game(
+    commands: &mut Commands,
+    keys: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    level: Res<Level>,
+    materials: Res<Materials>,
+    presets: Res<DifficultyPresets>,
+    mut gamestate: ResMut<State<GameState>>,
+    mut daily: ResMut<Daily>,
+    mut rng: ResMut<GameRng>,
+    mut combo: ResMut<Combo>,
+    mut shrink: ResMut<ShrinkTimer>,
+    mut growing: ResMut<GrowingWalls>,
+    mut current_run: ResMut<CurrentRun>,
+    board: Query<Entity, Or<(With<Snake>, With<Food>, With<Obstacle>, With<Poison>)>>,
+) {
+    if !keys.just_pressed(bindings.load) || !matches!(gamestate.current(), GameState::Menu | GameState::Paused) {
+        return;
+    }
+    let save = std::fs::read_to_string(SAVE_PATH)
+        .map_err(|err| err.to_string())
+        .and_then(|data| ron::from_str::<SaveGame>(&data).map_err(|err| err.to_string()))
+        .and_then(|save| save.validate(&level).map(|()| save));
+    let save = match save {
+        Ok(save) => save,
+        Err(err) => {
+            warn!("could not load the game from {}: {}, starting a new one", SAVE_PATH, err);
+            if *gamestate.current() == GameState::Menu {
+                toggle_pause(&mut gamestate);
+            } else {
+                restart(&mut gamestate);
+            }
+            return;
+        }
+    };
+
+    for entity in board.iter() {
+        commands.despawn(entity);
+    }
+    // Not the daily any more, whatever it was saved from.
+    daily.0 = None;
+    *rng = save.rng.clone();
+    combo.reset();
+    shrink.resume(save.shrunk_rings);
+    growing.reset();
+    *current_run = CurrentRun::default();
+    insert_run_resources(commands, Recorder::loaded(save.mode, save.difficulty), presets.get(save.difficulty));
+    commands.insert_resource(save.mode);
+    spawn_saved(commands, &materials, &save);
+    if *gamestate.current() == GameState::Menu {
+        gamestate.set_next(GameState::Paused).ok();
+    }
+    info!("loaded the game from {}", SAVE_PATH);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::{headless_app, run_system, snake_positions, spawn_player, tiles};
+    use crate::DEFAULT_SEED;
+    use rand::Rng;
+
+    fn solo_save(body: Vec<Position>) -> SaveGame {
+        let level = Level::default();
+        SaveGame {
+            width: level.width,
+            height: level.height,
+            mode: GameMode::Solo,
+            difficulty: Difficulty::default(),
+            rng: GameRng::from_seed(DEFAULT_SEED),
+            shrunk_rings: 0,
+            snakes: vec![SavedSnake {
+                index: 0,
+                direction: Direction::Right,
+                food: 0,
+                body,
+            }],
+            foods: Vec::new(),
+            obstacles: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn broken_snake_is_rejected() {
+        let level = Level::default();
+        assert!(solo_save(tiles(&[(5, 5), (4, 5), (3, 5)])).validate(&level).is_ok());
+        assert!(solo_save(tiles(&[(5, 5), (4, 5), (1, 1)])).validate(&level).is_err());
+    }
+
+    #[test]
+    fn snake_off_the_arena_is_rejected() {
+        let level = Level::default();
+        let edge = level.width as i32;
+        assert!(solo_save(tiles(&[(edge, 5), (edge - 1, 5)])).validate(&level).is_err());
+        assert!(solo_save(tiles(&[(0, -1), (0, 0)])).validate(&level).is_err());
+    }
+
+    #[test]
+    fn wrong_players_are_rejected() {
+        let level = Level::default();
+        let mut save = solo_save(tiles(&[(5, 5), (4, 5)]));
+        save.snakes[0].index = 1;
+        assert!(save.validate(&level).is_err());
+        // Versus with only one snake.
+        save.snakes[0].index = 0;
+        save.mode = GameMode::Versus;
+        assert!(save.validate(&level).is_err());
+    }
+
+    // Hands what `save_game` would save of the snakes back to the test.
+    #[derive(Default)]
+    struct Captured(Vec<SavedSnake>);
+
+    fn capture(
+        mut captured: ResMut<Captured>,
+        players: Query<(Entity, &Player)>,
+        segments: Query<&SnakeSegment>,
+        positions: Query<&Position>,
+    ) {
+        captured.0 = saved_snakes(&players, &segments, &positions);
+    }
+
+    fn spawn_loaded(commands: &mut Commands, materials: Res<Materials>, save: Res<SaveGame>) {
+        spawn_saved(commands, &materials, &save);
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let body = tiles(&[(5, 5), (4, 5), (4, 6), (3, 6), (3, 6)]);
+        let mut app = headless_app(Level::default());
+        spawn_player(&mut app, 0, Direction::Right, &body);
+        app.resources.insert(Captured::default());
+        run_system(&mut app, capture.system());
+        let mut save = solo_save(Vec::new());
+        save.snakes = std::mem::take(&mut app.resources.get_mut::<Captured>().unwrap().0);
+        // Partway through the run.
+        let _: u64 = save.rng.0.gen();
+
+        let data = ron::ser::to_string(&save).unwrap();
+        let loaded: SaveGame = ron::from_str(&data).unwrap();
+        assert!(loaded.validate(&Level::default()).is_ok());
+        // The generator goes on from where it was.
+        assert_eq!(loaded.rng.clone().0.gen::<u64>(), save.rng.0.gen::<u64>());
+
+        let mut app = headless_app(Level::default());
+        app.resources.insert(loaded);
+        run_system(&mut app, spawn_loaded.system());
+        let heads: Vec<Entity> = app.world.query_filtered::<Entity, With<Player>>().collect();
+        assert_eq!(heads.len(), 1);
+        assert_eq!(snake_positions(&app, heads[0]), body);
+    }
+}
@@ -0,0 +1,82 @@
+use bevy::prelude::*;
+use bevy::text::{HorizontalAlign, TextAlignment, VerticalAlign};
+
+use crate::food::ScoreEvent;
+use crate::{add_tick_system, tile_size, Level};
+
+const FLOATING_TEXT_SECONDS: f32 = 0.6;
+const MAX_FLOATING_TEXTS: usize = 8;
+
+// A "+N" rises off each food a player scores for, fading as it goes. Only
+// drawn, so it's left out of the headless app.
+pub(crate) struct ScorePopupsPlugin;
+impl Plugin for ScorePopupsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system(floating_text.system());
+        add_tick_system(app, || score_popups.system());
+    }
+}
+
+struct FloatingText {
+    timer: Timer,
+}
+
+// Only for points actually won. With too many already on the board, newer
+// ones are skipped rather than stacking up.
+fn score_popups(
+    commands: &mut Commands,
+    score_events: Res<Events<ScoreEvent>>,
+    mut score_reader: Local<EventReader<ScoreEvent>>,
+    windows: Res<Windows>,
+    level: Res<Level>,
+    asset_server: Res<AssetServer>,
+    texts: Query<&FloatingText>,
+) {
+    let mut room = MAX_FLOATING_TEXTS.saturating_sub(texts.iter().count());
+    let font_size = tile_size(windows.get_primary().unwrap(), &level) * 0.6;
+    for ScoreEvent { points, at, .. } in score_reader.iter(&score_events) {
+        let at = match at {
+            Some(at) if *points > 0 && room > 0 => *at,
+            _ => continue,
+        };
+        room -= 1;
+        commands
+            .spawn(Text2dBundle {
+                text: Text {
+                    value: format!("+{}", points),
+                    font: asset_server.load("fonts/DejaVuSans.ttf"),
+                    style: TextStyle {
+                        font_size,
+                        color: Color::WHITE,
+                        alignment: TextAlignment {
+                            vertical: VerticalAlign::Center,
+                            horizontal: HorizontalAlign::Center,
+                        },
+                    },
+                },
+                // Over the snakes and the particles.
+                transform: Transform::from_translation(Vec3::new(at.x, at.y, 3.)),
+                ..Default::default()
+            })
+            .with(FloatingText {
+                timer: Timer::from_seconds(FLOATING_TEXT_SECONDS, false),
+            });
+    }
+}
+
+// In real time, like the particles.
+fn floating_text(
+    commands: &mut Commands,
+    time: Res<Time>,
+    mut texts: Query<(Entity, &mut FloatingText, &mut Text, &mut Transform)>,
+) {
+    for (entity, mut floating, mut text, mut transform) in texts.iter_mut() {
+        floating.timer.tick(time.delta_seconds());
+        if floating.timer.finished() {
+            commands.despawn(entity);
+            continue;
+        }
+        transform.translation.y += 40. * time.delta_seconds();
+        text.style.color.set_a(floating.timer.percent_left());
+    }
+}
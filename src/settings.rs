@@ -0,0 +1,567 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::food::PAIR_COLORS;
+use crate::ui::{despawn_all, ButtonMaterials, MenuActivated, MenuItem, MenuSelection};
+use crate::{AudioSettings, ControlScheme, Difficulty, GameState, Materials, GAME_STATES};
+
+// The settings screen, and loading and saving what it changes.
+pub(crate) struct SettingsPlugin;
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        let mut saved = app.resources().get::<Config>().map(|config| config.settings).unwrap_or_default();
+        // A frame cap the settings screen doesn't offer, from a file edited
+        // by hand, is dropped.
+        if !FRAME_CAPS.contains(&saved.vsync.frame_cap) {
+            saved.vsync.frame_cap = None;
+        }
+        app.add_resource(AudioSettings { muted: saved.muted })
+            .add_resource(saved.difficulty)
+            .add_resource(saved.palette)
+            .add_resource(saved.vsync)
+            .add_resource(saved.controls)
+            .add_system(apply_palette.system())
+            .add_system(apply_vsync.system())
+            .add_system(settings_buttons.system())
+            .add_system(update_settings_labels.system())
+            .on_state_enter(GAME_STATES, GameState::Settings, settings_setup.system())
+            .on_state_exit(GAME_STATES, GameState::Settings, despawn_all::<SettingsUi>.system())
+            .on_state_exit(GAME_STATES, GameState::Settings, save_settings.system());
+        #[cfg(not(target_arch = "wasm32"))]
+        app.add_system_to_stage(stage::LAST, frame_limiter.system());
+    }
+}
+
+struct SettingsUi;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub(crate) enum Palette {
+    Default,
+    Deuteranopia,
+    Protanopia,
+    Tritanopia,
+}
+impl Default for Palette {
+    fn default() -> Self {
+        Self::Default
+    }
+}
+impl Palette {
+    fn next(self) -> Self {
+        match self {
+            Self::Default => Self::Deuteranopia,
+            Self::Deuteranopia => Self::Protanopia,
+            Self::Protanopia => Self::Tritanopia,
+            Self::Tritanopia => Self::Default,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Default => "Default",
+            Self::Deuteranopia => "Deuteranopia",
+            Self::Protanopia => "Protanopia",
+            Self::Tritanopia => "Tritanopia",
+        }
+    }
+
+    // The default palette is the game's own look. The others keep the snake
+    // and the normal food on either side of the one color axis each kind of
+    // colorblindness leaves intact (blue-yellow for the red-green ones,
+    // red-cyan for tritanopia), and far apart in brightness too. Everything
+    // else was picked to stay apart from them and from each other, both as
+    // the palette is meant for and with full color vision.
+    pub(crate) fn colors(self) -> PaletteColors {
+        match self {
+            Self::Default => PaletteColors {
+                head: Color::rgb(0.7, 0.7, 0.7),
+                body: Color::rgb(0.3, 0.3, 0.3),
+                second_body: Color::rgb(0.2, 0.35, 0.5),
+                food: Color::rgb(1.0, 0.0, 1.0),
+                golden_food: Color::rgb(1.0, 0.85, 0.0),
+                rotten_food: Color::rgb(0.4, 0.5, 0.1),
+                ghost_food: Color::rgb(0.85, 0.9, 1.0),
+                moving_food: Color::rgb(0.3, 0.9, 0.4),
+                confusion_food: Color::rgb(1.0, 0.5, 0.1),
+                magnet_food: Color::rgb(0.2, 0.5, 1.0),
+                pairs: PAIR_COLORS,
+                death: Color::rgb(0.9, 0.1, 0.1),
+                tile_light: Color::rgb(0.16, 0.16, 0.16),
+                tile_dark: Color::rgb(0.13, 0.13, 0.13),
+                wall: Color::rgb(0.45, 0.3, 0.2),
+                portal: Color::rgb(0.2, 0.5, 1.0),
+                poison: Color::rgba(0.5, 0.9, 0.1, 0.5),
+            },
+            Self::Deuteranopia => PaletteColors {
+                head: Color::rgb(0.35, 0.6, 0.95),
+                body: Color::rgb(0.1, 0.3, 0.7),
+                second_body: Color::rgb(0.3, 0.25, 0.35),
+                food: Color::rgb(1.0, 0.6, 0.0),
+                golden_food: Color::rgb(0.9, 1.0, 0.0),
+                rotten_food: Color::rgb(0.35, 0.15, 0.0),
+                ghost_food: Color::rgb(0.05, 0.9, 0.7),
+                moving_food: Color::rgb(0.65, 0.6, 0.7),
+                confusion_food: Color::rgb(1.0, 0.8, 0.55),
+                magnet_food: Color::rgb(1.0, 1.0, 0.95),
+                pairs: [
+                    ("Olive", Color::rgb(0.35, 0.45, 0.2)),
+                    ("Sage", Color::rgb(0.35, 0.65, 0.55)),
+                    ("Ice", Color::rgb(0.65, 1.0, 1.0)),
+                    ("Blue", Color::rgb(0.15, 0.4, 1.0)),
+                ],
+                death: Color::rgb(0.75, 0.1, 0.6),
+                wall: Color::rgb(0.1, 0.0, 0.45),
+                portal: Color::rgb(0.75, 0.5, 0.25),
+                poison: Color::rgba(0.0, 0.85, 0.65, 0.5),
+                ..Self::Default.colors()
+            },
+            Self::Protanopia => PaletteColors {
+                head: Color::rgb(0.4, 0.65, 1.0),
+                body: Color::rgb(0.15, 0.35, 0.75),
+                second_body: Color::rgb(0.35, 0.55, 0.55),
+                food: Color::rgb(1.0, 0.95, 0.6),
+                golden_food: Color::rgb(0.15, 0.8, 0.25),
+                rotten_food: Color::rgb(0.25, 0.5, 0.0),
+                ghost_food: Color::rgb(0.05, 0.95, 0.8),
+                moving_food: Color::rgb(1.0, 1.0, 0.15),
+                confusion_food: Color::rgb(0.6, 0.2, 0.45),
+                magnet_food: Color::rgb(0.5, 0.8, 0.95),
+                pairs: [
+                    ("Plum", Color::rgb(0.25, 0.0, 0.35)),
+                    ("Teal", Color::rgb(0.25, 0.7, 0.6)),
+                    ("Ice", Color::rgb(0.65, 0.9, 0.9)),
+                    ("Lilac", Color::rgb(0.65, 0.4, 0.95)),
+                ],
+                death: Color::rgb(0.65, 0.0, 0.05),
+                wall: Color::rgb(0.25, 0.4, 0.4),
+                portal: Color::rgb(0.45, 0.45, 0.6),
+                poison: Color::rgba(0.0, 0.9, 0.65, 0.5),
+                ..Self::Default.colors()
+            },
+            Self::Tritanopia => PaletteColors {
+                head: Color::rgb(0.95, 0.45, 0.45),
+                body: Color::rgb(0.75, 0.15, 0.2),
+                second_body: Color::rgb(0.3, 0.4, 0.2),
+                food: Color::rgb(0.2, 0.85, 0.85),
+                golden_food: Color::rgb(0.9, 0.75, 0.45),
+                rotten_food: Color::rgb(0.6, 0.55, 0.7),
+                ghost_food: Color::rgb(0.85, 0.65, 1.0),
+                moving_food: Color::rgb(0.7, 1.0, 0.1),
+                confusion_food: Color::rgb(0.35, 0.2, 0.05),
+                magnet_food: Color::rgb(0.0, 0.25, 0.8),
+                pairs: [
+                    ("Magenta", Color::rgb(0.8, 0.05, 0.8)),
+                    ("Sage", Color::rgb(0.5, 0.65, 0.5)),
+                    ("Yellow", Color::rgb(1.0, 0.95, 0.3)),
+                    ("Sky", Color::rgb(0.6, 0.8, 1.0)),
+                ],
+                death: Color::rgb(0.95, 0.0, 0.0),
+                wall: Color::rgb(0.5, 0.2, 0.8),
+                portal: Color::rgb(0.5, 0.4, 0.9),
+                poison: Color::rgba(0.2, 0.95, 0.0, 0.5),
+                ..Self::Default.colors()
+            },
+        }
+    }
+}
+
+// Every color the board is drawn in, one for each of `Materials`. Pairs come
+// with the name the HUD calls them by.
+pub(crate) struct PaletteColors {
+    pub(crate) head: Color,
+    pub(crate) body: Color,
+    pub(crate) second_body: Color,
+    pub(crate) food: Color,
+    pub(crate) golden_food: Color,
+    pub(crate) rotten_food: Color,
+    pub(crate) ghost_food: Color,
+    pub(crate) moving_food: Color,
+    pub(crate) confusion_food: Color,
+    pub(crate) magnet_food: Color,
+    pub(crate) pairs: [(&'static str, Color); 4],
+    pub(crate) death: Color,
+    pub(crate) tile_light: Color,
+    pub(crate) tile_dark: Color,
+    pub(crate) wall: Color,
+    pub(crate) portal: Color,
+    pub(crate) poison: Color,
+}
+
+// Movement runs off its own timer, so neither of these changes how fast the
+// game goes, only how often it's drawn. With a frame cap below the tick rate
+// (only reachable with `--speed`) some frames would owe more than one tick,
+// and only get one.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+struct VsyncSettings {
+    vsync: bool,
+    frame_cap: Option<u32>,
+}
+impl Default for VsyncSettings {
+    fn default() -> Self {
+        Self {
+            vsync: true,
+            frame_cap: None,
+        }
+    }
+}
+
+// The frame caps the settings screen cycles through, `None` for uncapped.
+const FRAME_CAPS: [Option<u32>; 4] = [None, Some(120), Some(60), Some(30)];
+
+fn next_frame_cap(cap: Option<u32>) -> Option<u32> {
+    let index = FRAME_CAPS.iter().position(|c| *c == cap).unwrap_or(0);
+    FRAME_CAPS[(index + 1) % FRAME_CAPS.len()]
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SettingsButton {
+    Sound,
+    Difficulty,
+    Palette,
+    Vsync,
+    FrameCap,
+    Controls,
+    Back,
+}
+
+// On the text inside each button.
+struct SettingsLabel(SettingsButton);
+
+// The settings' part of the config file.
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct SavedSettings {
+    muted: bool,
+    difficulty: Difficulty,
+    palette: Palette,
+    vsync: VsyncSettings,
+    controls: ControlScheme,
+}
+
+fn save_settings(
+    mut config: ResMut<Config>,
+    audio_settings: Res<AudioSettings>,
+    difficulty: Res<Difficulty>,
+    palette: Res<Palette>,
+    vsync: Res<VsyncSettings>,
+    scheme: Res<ControlScheme>,
+) {
+    config.settings = SavedSettings {
+        muted: audio_settings.muted,
+        difficulty: *difficulty,
+        palette: *palette,
+        vsync: *vsync,
+        controls: *scheme,
+    };
+    config.save();
+}
+
+// Recolors the shared materials in place, so every sprite already using them
+// follows along.
+fn apply_palette(palette: ChangedRes<Palette>, materials: Res<Materials>, mut assets: ResMut<Assets<ColorMaterial>>) {
+    let colors = palette.colors();
+    let mut recolors = vec![
+        (&materials.head_material, colors.head),
+        (&materials.body_material, colors.body),
+        (&materials.second_body_material, colors.second_body),
+        (&materials.food_material, colors.food),
+        (&materials.golden_food_material, colors.golden_food),
+        (&materials.rotten_food_material, colors.rotten_food),
+        (&materials.ghost_food_material, colors.ghost_food),
+        (&materials.moving_food_material, colors.moving_food),
+        (&materials.confusion_food_material, colors.confusion_food),
+        (&materials.magnet_food_material, colors.magnet_food),
+        (&materials.death_material, colors.death),
+        (&materials.tile_light_material, colors.tile_light),
+        (&materials.tile_dark_material, colors.tile_dark),
+        (&materials.wall_material, colors.wall),
+        (&materials.portal_material, colors.portal),
+        (&materials.poison_material, colors.poison),
+    ];
+    recolors.extend(materials.pair_materials.iter().zip(colors.pairs.iter().map(|(_, color)| *color)));
+    for (handle, color) in recolors {
+        if let Some(material) = assets.get_mut(handle) {
+            material.color = color;
+        }
+    }
+}
+
+fn apply_vsync(vsync: ChangedRes<VsyncSettings>, mut windows: ResMut<Windows>) {
+    if let Some(window) = windows.get_primary_mut() {
+        if window.vsync() != vsync.vsync {
+            window.set_vsync(vsync.vsync);
+        }
+    }
+}
+
+// Sleeps off whatever is left of the frame's share of a second, at the very
+// end of the frame.
+#[cfg(not(target_arch = "wasm32"))]
+fn frame_limiter(vsync: Res<VsyncSettings>, mut frame_start: Local<Option<Instant>>) {
+    if let (Some(fps), Some(start)) = (vsync.frame_cap, *frame_start) {
+        let frame = Duration::from_secs_f64(1. / fps as f64);
+        if let Some(left) = frame.checked_sub(start.elapsed()) {
+            std::thread::sleep(left);
+        }
+    }
+    *frame_start = Some(Instant::now());
+}
+
+fn settings_setup(
+    commands: &mut Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    button_materials: Res<ButtonMaterials>,
+    mut selection: ResMut<MenuSelection>,
+    asset_server: Res<AssetServer>,
+) {
+    selection.0 = 0;
+    let font = asset_server.load("fonts/DejaVuSans.ttf");
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: bevy::prelude::Size::new(Val::Percent(100.), Val::Percent(100.)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: materials.add(Color::NONE.into()),
+            ..Default::default()
+        })
+        .with(SettingsUi)
+        .with_children(|parent| {
+            parent.spawn(TextBundle {
+                text: Text {
+                    value: "Settings".to_string(),
+                    font: font.clone(),
+                    style: TextStyle {
+                        font_size: 60.0,
+                        color: Color::WHITE,
+                        ..Default::default()
+                    },
+                },
+                ..Default::default()
+            });
+            for (index, &button) in [
+                SettingsButton::Sound,
+                SettingsButton::Difficulty,
+                SettingsButton::Palette,
+                SettingsButton::Vsync,
+                SettingsButton::FrameCap,
+                SettingsButton::Controls,
+                SettingsButton::Back,
+            ]
+            .iter()
+            .enumerate()
+            {
+                parent
+                    .spawn(ButtonBundle {
+                        style: Style {
+                            size: bevy::prelude::Size::new(Val::Px(260.), Val::Px(50.)),
+                            margin: Rect::all(Val::Px(8.)),
+                            justify_content: JustifyContent::Center,
+                            align_items: AlignItems::Center,
+                            ..Default::default()
+                        },
+                        material: button_materials.normal.clone(),
+                        ..Default::default()
+                    })
+                    .with(button)
+                    .with(MenuItem(index))
+                    .with_children(|parent| {
+                        parent
+                            .spawn(TextBundle {
+                                text: Text {
+                                    value: String::new(),
+                                    font: font.clone(),
+                                    style: TextStyle {
+                                        font_size: 24.0,
+                                        color: Color::WHITE,
+                                        ..Default::default()
+                                    },
+                                },
+                                ..Default::default()
+                            })
+                            .with(SettingsLabel(button));
+                    });
+            }
+        });
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn settings_buttons(
+    mut gamestate: ResMut<State<GameState>>,
+    mut audio_settings: ResMut<AudioSettings>,
+    mut difficulty: ResMut<Difficulty>,
+    mut palette: ResMut<Palette>,
+    mut vsync: ResMut<VsyncSettings>,
+    mut scheme: ResMut<ControlScheme>,
+    activated_events: Res<Events<MenuActivated>>,
+    mut activated_reader: Local<EventReader<MenuActivated>>,
+    clicked: Query<(&Interaction, &SettingsButton), (Mutated<Interaction>, With<Button>)>,
+    buttons: Query<&SettingsButton>,
+) {
+    let mut pressed: Vec<SettingsButton> = clicked
+        .iter()
+        .filter(|(interaction, _)| **interaction == Interaction::Clicked)
+        .map(|(_, button)| *button)
+        .collect();
+    pressed.extend(
+        activated_reader
+            .iter(&activated_events)
+            .filter_map(|MenuActivated(entity)| buttons.get(*entity).ok().copied()),
+    );
+    for button in pressed {
+        match button {
+            SettingsButton::Sound => audio_settings.muted = !audio_settings.muted,
+            SettingsButton::Difficulty => *difficulty = difficulty.next(),
+            SettingsButton::Palette => *palette = palette.next(),
+            SettingsButton::Vsync => vsync.vsync = !vsync.vsync,
+            SettingsButton::FrameCap => vsync.frame_cap = next_frame_cap(vsync.frame_cap),
+            SettingsButton::Controls => *scheme = scheme.next(),
+            SettingsButton::Back => {
+                gamestate.set_next(GameState::Menu).ok();
+            }
+        }
+    }
+}
+
+fn update_settings_labels(
+    audio_settings: Res<AudioSettings>,
+    difficulty: Res<Difficulty>,
+    palette: Res<Palette>,
+    vsync: Res<VsyncSettings>,
+    scheme: Res<ControlScheme>,
+    mut labels: Query<(&SettingsLabel, &mut Text)>,
+) {
+    for (label, mut text) in labels.iter_mut() {
+        text.value = match label.0 {
+            // Bevy can't set the volume of a sound, so this is all or nothing.
+            SettingsButton::Sound => format!("Sound: {}", if audio_settings.muted { "Off" } else { "On" }),
+            SettingsButton::Difficulty => format!("Difficulty: {}", difficulty.name()),
+            SettingsButton::Palette => format!("Palette: {}", palette.name()),
+            SettingsButton::Vsync => format!("VSync: {}", if vsync.vsync { "On" } else { "Off" }),
+            SettingsButton::FrameCap => match vsync.frame_cap {
+                Some(fps) => format!("Frame Cap: {} FPS", fps),
+                None => "Frame Cap: Off".to_string(),
+            },
+            SettingsButton::Controls => format!("Controls: {}", scheme.name()),
+            SettingsButton::Back => "Back".to_string(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Anything closer than this in CIELAB is too easy to mix up.
+    const MIN_DISTANCE: f32 = 12.;
+
+    // How each kind of colorblindness sees linear RGB, at full severity
+    // (Machado, Oliveira and Fernandes, 2009).
+    fn simulation(palette: Palette) -> Option<[[f32; 3]; 3]> {
+        match palette {
+            Palette::Default => None,
+            Palette::Deuteranopia => Some([
+                [0.367322, 0.860646, -0.227968],
+                [0.280085, 0.672501, 0.047413],
+                [-0.011820, 0.042940, 0.968881],
+            ]),
+            Palette::Protanopia => Some([
+                [0.152286, 1.052583, -0.204868],
+                [0.114503, 0.786281, 0.099216],
+                [-0.003882, -0.048116, 1.051998],
+            ]),
+            Palette::Tritanopia => Some([
+                [1.255528, -0.076749, -0.178779],
+                [-0.078411, 0.930809, 0.147602],
+                [0.004733, 0.691367, 0.303900],
+            ]),
+        }
+    }
+
+    fn lab(color: Color, simulation: Option<[[f32; 3]; 3]>) -> [f32; 3] {
+        let linear = |c: f32| if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) };
+        let mut rgb = [linear(color.r()), linear(color.g()), linear(color.b())];
+        if let Some(matrix) = simulation {
+            let seen = |row: [f32; 3]| (row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2]).max(0.).min(1.);
+            rgb = [seen(matrix[0]), seen(matrix[1]), seen(matrix[2])];
+        }
+        let [r, g, b] = rgb;
+        // Relative to the D65 white point.
+        let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+        let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+        let f = |t: f32| if t > 216. / 24389. { t.cbrt() } else { (24389. / 27. * t + 16.) / 116. };
+        let (x, y, z) = (f(x), f(y), f(z));
+        [116. * y - 16., 500. * (x - y), 200. * (y - z)]
+    }
+
+    // Poison is see-through, so it's checked as drawn over a tile.
+    fn over(color: Color, under: Color) -> Color {
+        let a = color.a();
+        Color::rgb(
+            a * color.r() + (1. - a) * under.r(),
+            a * color.g() + (1. - a) * under.g(),
+            a * color.b() + (1. - a) * under.b(),
+        )
+    }
+
+    // The two tiles are meant to be close; the rest should all stand apart.
+    fn board_colors(colors: &PaletteColors) -> Vec<(&'static str, Color)> {
+        let mut board = vec![
+            ("head", colors.head),
+            ("body", colors.body),
+            ("second body", colors.second_body),
+            ("food", colors.food),
+            ("golden food", colors.golden_food),
+            ("rotten food", colors.rotten_food),
+            ("ghost food", colors.ghost_food),
+            ("moving food", colors.moving_food),
+            ("confusion food", colors.confusion_food),
+            ("magnet food", colors.magnet_food),
+            ("death", colors.death),
+            ("tile", colors.tile_light),
+            ("wall", colors.wall),
+            ("portal", colors.portal),
+            ("poison", over(colors.poison, colors.tile_light)),
+        ];
+        board.extend(colors.pairs.iter().copied());
+        board
+    }
+
+    #[test]
+    fn palettes_stay_distinct() {
+        for &palette in &[Palette::Deuteranopia, Palette::Protanopia, Palette::Tritanopia] {
+            let colors = palette.colors();
+            let board = board_colors(&colors);
+            let is_pair = |name: &str| colors.pairs.iter().any(|(pair, _)| *pair == name);
+            // As the palette is meant to be seen, and with full color vision.
+            for &vision in &[simulation(palette), None] {
+                for (i, &(a, color_a)) in board.iter().enumerate() {
+                    for &(b, color_b) in &board[i + 1..] {
+                        // Normal food is never out together with pairs.
+                        if (a == "food" && is_pair(b)) || (b == "food" && is_pair(a)) {
+                            continue;
+                        }
+                        let (lab_a, lab_b) = (lab(color_a, vision), lab(color_b, vision));
+                        let distance = lab_a.iter().zip(lab_b.iter()).map(|(p, q)| (p - q).powi(2)).sum::<f32>().sqrt();
+                        assert!(
+                            distance >= MIN_DISTANCE,
+                            "{} and {} are only {:.1} apart in the {} palette",
+                            a,
+                            b,
+                            distance,
+                            palette.name()
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use bevy::prelude::*;
+use std::collections::HashSet;
+
+use crate::collision::BumpEvent;
+use crate::food::Food;
+use crate::snake::{snake_body, SnakeHead, SnakeSegment};
+use crate::{add_tick_system, parsed_arg, Level, Materials, MoveTimer, Obstacle, Position, Size, Wall};
+
+// The arena stops shrinking once it's down to this many tiles across.
+const MIN_SHRUNK_SIZE: i32 = 3;
+
+// The "shrinking arena" hazard, on when `--shrink-interval` is given.
+pub(crate) struct ShrinkPlugin;
+impl Plugin for ShrinkPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(ShrinkTimer::new(shrink_interval_from_args()));
+        add_tick_system(app, || shrink_arena.system());
+    }
+}
+
+// Every time `timer` runs out the next ring of the arena, from the outside
+// in, turns into wall. Like the food spawner it counts movement ticks, so it
+// stands still outside of `Playing` and a replay shrinks on the same ticks.
+pub(crate) struct ShrinkTimer {
+    timer: Option<Timer>,
+    rings: i32,
+}
+impl ShrinkTimer {
+    fn new(interval: Option<f32>) -> Self {
+        Self {
+            timer: interval.map(|interval| Timer::from_seconds(interval, true)),
+            rings: 0,
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        if let Some(timer) = &mut self.timer {
+            timer.reset();
+        }
+        self.rings = 0;
+    }
+
+    pub(crate) fn rings(&self) -> i32 {
+        self.rings
+    }
+
+    // Picks a run back up with `rings` already walled off, the next one due
+    // a whole interval from now.
+    pub(crate) fn resume(&mut self, rings: i32) {
+        self.reset();
+        self.rings = rings;
+    }
+
+    // Whether `pos` lies in one of the rings already walled off.
+    pub(crate) fn shrunk(&self, level: &Level, pos: Position) -> bool {
+        let right = level.width as i32 - 1 - pos.x;
+        let top = level.height as i32 - 1 - pos.y;
+        pos.x.min(pos.y).min(right).min(top) < self.rings
+    }
+}
+
+// `--shrink-interval <seconds>` on the command line; without it the arena
+// keeps its size.
+fn shrink_interval_from_args() -> Option<f32> {
+    parsed_arg("--shrink-interval", |interval: &f32| *interval > 0.)
+}
+
+fn ring(level: &Level, ring: i32) -> Vec<Position> {
+    let (right, top) = (level.width as i32 - 1 - ring, level.height as i32 - 1 - ring);
+    (ring..=right)
+        .flat_map(|x| (ring..=top).map(move |y| Position { x, y }))
+        .filter(|pos| pos.x == ring || pos.x == right || pos.y == ring || pos.y == top)
+        .collect()
+}
+
+#[allow(clippy::too_many_arguments)]
+fn shrink_arena(
+    commands: &mut Commands,
+    level: Res<Level>,
+    materials: Res<Materials>,
+    move_timer: Res<MoveTimer>,
+    mut shrink: ResMut<ShrinkTimer>,
+    walls: Query<&Position, With<Wall>>,
+    foods: Query<(Entity, &Position), With<Food>>,
+    heads: Query<Entity, With<SnakeHead>>,
+    segments: Query<&SnakeSegment>,
+    positions: Query<&Position>,
+    mut bump_events: ResMut<Events<BumpEvent>>,
+) {
+    let rings = shrink.rings;
+    let timer = match &mut shrink.timer {
+        Some(timer) => timer,
+        None => return,
+    };
+    let remaining = (level.width as i32).min(level.height as i32) - 2 * (rings + 1);
+    if remaining < MIN_SHRUNK_SIZE {
+        return;
+    }
+    timer.tick(move_timer.interval());
+    if !timer.just_finished() {
+        return;
+    }
+    shrink.rings += 1;
+
+    let walled: HashSet<Position> = walls.iter().cloned().collect();
+    let mut new_walls = Vec::new();
+    for pos in ring(&level, rings).into_iter().filter(|pos| !walled.contains(pos)) {
+        let wall = commands
+            .spawn(SpriteBundle {
+                material: materials.wall_material.clone(),
+                transform: Transform::from_translation(Vec3::new(0., 0., 0.5)),
+                ..Default::default()
+            })
+            .with(Wall)
+            .with(Obstacle)
+            .with(pos)
+            .with(Size::square(1.))
+            .current_entity()
+            .unwrap();
+        new_walls.push((pos, wall));
+    }
+    // Food that was lying in the ring is buried.
+    for (food, pos) in foods.iter() {
+        if new_walls.iter().any(|(wall_pos, _)| wall_pos == pos) {
+            commands.despawn(food);
+        }
+    }
+    // Any snake with a part on a tile that just turned into wall is crushed.
+    for head in heads.iter() {
+        let caught = snake_body(head, &segments, &positions)
+            .into_iter()
+            .find_map(|pos| new_walls.iter().find(|(wall_pos, _)| *wall_pos == pos));
+        if let Some(&(_, wall)) = caught {
+            bump_events.send(BumpEvent { head, wall: Some(wall) });
+        }
+    }
+}
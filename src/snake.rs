@@ -0,0 +1,1090 @@
+use bevy::prelude::*;
+use bevy::render::draw::Visible;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
+
+use crate::collision::BumpEvent;
+use crate::food::Food;
+use crate::{
+    add_tick_system, movement_style_from_args, wrap_animation_from_args, wrap_axes_from_args, Direction, GameState,
+    Level, Materials, MoveTimer, Portal, Position, Practice, Recorder, Replay, Size, Wall,
+};
+
+const SEGMENT_CONNECTORS: &str = "segment_connectors";
+
+// Snake spawning, steering and movement.
+pub(crate) struct SnakePlugin;
+impl Plugin for SnakePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(GhostEffect::default())
+            .add_resource(ControlsInverted::default())
+            .add_resource(wrap_axes_from_args())
+            .add_resource(movement_style_from_args())
+            .add_resource(wrap_animation_from_args())
+            .add_system(ghost_flash.system())
+            // After `render_interpolation`, so the connectors see where the
+            // segments are drawn this frame.
+            .add_stage_after(stage::UPDATE, SEGMENT_CONNECTORS, SystemStage::parallel())
+            .add_system_to_stage(SEGMENT_CONNECTORS, connect_segments.system())
+            // Before `wrap_copies`, so the copy dips along with the head.
+            .add_system_to_stage(SEGMENT_CONNECTORS, wrap_dip.system())
+            .add_system_to_stage(SEGMENT_CONNECTORS, wrap_copies.system());
+        add_tick_system(app, || segment_movement.system());
+        add_tick_system(app, || ai_controller.system());
+        add_tick_system(app, || snake_movement.system());
+        add_tick_system(app, || portal_teleport.system());
+        add_tick_system(app, || head_rotation.system());
+        add_tick_system(app, || confusion_expiry.system());
+    }
+}
+
+pub(crate) struct Snake;
+
+// Where a snake sprite is drawn: it slides from `from` to `to` over the
+// course of each movement tick, shifted by `offset`. While it wraps around
+// the arena, a copy is also drawn `wrap` away, on the side it left from.
+#[derive(Default)]
+pub(crate) struct RenderPosition {
+    pub(crate) from: Vec2,
+    pub(crate) to: Vec2,
+    pub(crate) wrap: Vec2,
+    pub(crate) offset: Vec2,
+}
+pub(crate) struct SnakeHead;
+pub(crate) struct SnakeSegment {
+    pub(crate) front: Option<Entity>,
+    pub(crate) back: Option<Entity>,
+}
+
+// A sprite bridging the gap between `segment` and the one in front of it,
+// so the body reads as one shape. It runs center to center at the body's
+// width, so where the body turns the two connectors fill the corner.
+struct Connector {
+    segment: Entity,
+}
+
+// The copy of `source` drawn on the side of the arena it's wrapping away from.
+// It doesn't have a `Position`, so collisions never see it.
+struct WrapCopy {
+    source: Entity,
+}
+
+// How a head wrapping around the arena is drawn. With `Slide` it just slides
+// off one edge while its copy slides in at the other. With `Dip` it also
+// shrinks away into the edge it leaves and grows back out of the one it
+// enters. Only the drawing changes, never where the snake is.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum WrapAnimation {
+    Slide,
+    Dip,
+}
+impl Default for WrapAnimation {
+    fn default() -> Self {
+        Self::Slide
+    }
+}
+impl WrapAnimation {
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "slide" => Some(Self::Slide),
+            "dip" => Some(Self::Dip),
+            _ => None,
+        }
+    }
+}
+
+// On a head while it dips through an edge, so its scale is put back after.
+struct WrapAnim;
+
+// Which pairs of arena edges a snake passes through to the opposite side.
+// Crossing an edge that doesn't wrap is a crash.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct WrapAxes {
+    pub(crate) x: bool,
+    pub(crate) y: bool,
+}
+impl Default for WrapAxes {
+    fn default() -> Self {
+        Self { x: true, y: true }
+    }
+}
+impl WrapAxes {
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "both" => Some(Self { x: true, y: true }),
+            "x" => Some(Self { x: true, y: false }),
+            "y" => Some(Self { x: false, y: true }),
+            "none" => Some(Self { x: false, y: false }),
+            _ => None,
+        }
+    }
+
+    // Whether stepping from `pos` in `direction` runs into an edge that
+    // doesn't wrap.
+    pub(crate) fn blocks(self, pos: Position, direction: Direction, level: &Level) -> bool {
+        match direction {
+            Direction::Left => !self.x && pos.x == 0,
+            Direction::Right => !self.x && pos.x == level.width as i32 - 1,
+            Direction::Down => !self.y && pos.y == 0,
+            Direction::Up => !self.y && pos.y == level.height as i32 - 1,
+        }
+    }
+}
+
+// How the body follows the head. In `Classic` each segment moves into the
+// tile the one ahead of it just left, so the body bends around every corner
+// the head takes. In `Rigid` the whole body moves a tile in the head's
+// direction, like a train: going straight the two are the same, but when the
+// head turns the body slides sideways with it and keeps its shape, so the
+// snake never runs into itself.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum MovementStyle {
+    Classic,
+    Rigid,
+}
+impl Default for MovementStyle {
+    fn default() -> Self {
+        Self::Classic
+    }
+}
+impl MovementStyle {
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "classic" => Some(Self::Classic),
+            "rigid" => Some(Self::Rigid),
+            _ => None,
+        }
+    }
+}
+
+// Marks a snake steered by `ai_controller` rather than by input.
+pub(crate) struct AiController;
+
+// Attached to the head of each player's snake.
+pub(crate) struct Player {
+    pub(crate) index: usize,
+    pub(crate) direction: Direction,
+    pub(crate) food: u32,
+}
+
+// While `owner` is set, that snake's head passes through its own body.
+// The timer counts movement ticks rather than real time, so a replay sees
+// the effect run out on the same tick. The tick it runs out on still counts
+// as a ghost one: a head inside its body at that moment gets one more move
+// to get clear before it crashes.
+pub(crate) struct GhostEffect {
+    pub(crate) owner: Option<Entity>,
+    pub(crate) timer: Timer,
+}
+impl Default for GhostEffect {
+    fn default() -> Self {
+        Self {
+            owner: None,
+            timer: Timer::new(Duration::from_millis(5000), false),
+        }
+    }
+}
+
+// Confusion food swaps `owner`'s controls around, Left for Right and Up for
+// Down, until `timer` runs out.
+pub(crate) struct ControlsInverted {
+    pub(crate) owner: Option<Entity>,
+    pub(crate) timer: Timer,
+}
+impl Default for ControlsInverted {
+    fn default() -> Self {
+        Self {
+            owner: None,
+            timer: Timer::new(Duration::from_millis(4000), false),
+        }
+    }
+}
+impl ControlsInverted {
+    // What pressing `direction` means for `head`'s snake.
+    pub(crate) fn apply(&self, head: Entity, direction: Direction) -> Direction {
+        if self.owner == Some(head) {
+            direction.opposite()
+        } else {
+            direction
+        }
+    }
+}
+
+const INPUT_QUEUE_LEN: usize = 3;
+
+// Attached to the head of each player's snake, next to `Player`.
+#[derive(Default)]
+pub(crate) struct LastInput {
+    queue: VecDeque<Direction>,
+}
+impl LastInput {
+    pub(crate) fn push(&mut self, direction: Direction) {
+        if self.queue.back() != Some(&direction) && self.queue.len() < INPUT_QUEUE_LEN {
+            self.queue.push_back(direction);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn snake_movement(
+    gamestate: Res<State<GameState>>,
+    level: Res<Level>,
+    wrap: Res<WrapAxes>,
+    practice: Res<Practice>,
+    replay: Res<Replay>,
+    mut recorder: ResMut<Recorder>,
+    mut bump_events: ResMut<Events<BumpEvent>>,
+    mut players: Query<(Entity, &mut Player, &mut LastInput, &mut Position)>,
+) {
+    let tick = recorder.tick;
+    recorder.tick += 1;
+    for (head, mut player, mut last_input, mut player_head_pos) in players.iter_mut() {
+        if *gamestate.current() == GameState::Replay {
+            // Recorded turns were already validated when they were committed.
+            last_input.queue.clear();
+            let turn = replay.0.turns.iter().find(|(t, p, _)| *t == tick && *p == player.index);
+            if let Some((_, _, direction)) = turn {
+                player.direction = *direction;
+            }
+        } else {
+            // Queued turns are checked against the direction actually committed on
+            // the previous tick, so no sequence of fast inputs can reverse the snake.
+            while let Some(direction) = last_input.queue.pop_front() {
+                if direction != player.direction && direction != player.direction.opposite() {
+                    player.direction = direction;
+                    recorder.turns.push((tick, player.index, direction));
+                    break;
+                }
+            }
+        }
+        // The head stays put, and crashes into the edge. Practice wraps
+        // around everywhere instead.
+        if !practice.0 && wrap.blocks(*player_head_pos, player.direction, &level) {
+            bump_events.send(BumpEvent { head, wall: None });
+            continue;
+        }
+        *player_head_pos = step(*player_head_pos, player.direction, &level);
+    }
+}
+
+// The tile one step away in `direction`, wrapping around the arena.
+pub(crate) fn step(mut pos: Position, direction: Direction, level: &Level) -> Position {
+    match direction {
+        Direction::Left => {
+            pos.x -= 1;
+        }
+        Direction::Right => {
+            pos.x += 1;
+        }
+        Direction::Down => {
+            pos.y -= 1;
+        }
+        Direction::Up => {
+            pos.y += 1;
+        }
+    }
+    if pos.x < 0 {
+        pos.x = level.width as i32 - 1;
+    } else if pos.x >= level.width as i32 {
+        pos.x = 0;
+    }
+    if pos.y < 0 {
+        pos.y = level.height as i32 - 1;
+    } else if pos.y >= level.height as i32 {
+        pos.y = 0;
+    }
+    pos
+}
+
+// Manhattan distance, going around the arena edges that wrap when that's
+// shorter.
+fn wrapped_distance(a: Position, b: Position, level: &Level, wrap: WrapAxes) -> u32 {
+    let dx = (a.x - b.x).abs() as u32;
+    let dy = (a.y - b.y).abs() as u32;
+    let dx = if wrap.x { dx.min(level.width - dx) } else { dx };
+    let dy = if wrap.y { dy.min(level.height - dy) } else { dy };
+    dx + dy
+}
+
+// Breadth-first search for a shortest path from `start` to `goal` that avoids
+// `blocked` tiles. The path excludes `start` and ends at `goal`.
+fn plan_path(
+    start: Position,
+    goal: Position,
+    blocked: &HashSet<Position>,
+    w: u32,
+    h: u32,
+    wrap: WrapAxes,
+) -> Option<Vec<Position>> {
+    let (w, h) = (w as i32, h as i32);
+    let mut came_from = HashMap::new();
+    came_from.insert(start, start);
+    let mut frontier = VecDeque::new();
+    frontier.push_back(start);
+    while let Some(pos) = frontier.pop_front() {
+        if pos == goal {
+            let mut path = vec![pos];
+            let mut pos = pos;
+            while came_from[&pos] != start {
+                pos = came_from[&pos];
+                path.push(pos);
+            }
+            path.reverse();
+            return if goal == start { Some(Vec::new()) } else { Some(path) };
+        }
+        let neighbours = [(-1, 0), (1, 0), (0, -1), (0, 1)].iter().filter_map(|(dx, dy)| {
+            let x = if wrap.x { (pos.x + dx).rem_euclid(w) } else { pos.x + dx };
+            let y = if wrap.y { (pos.y + dy).rem_euclid(h) } else { pos.y + dy };
+            if x >= 0 && x < w && y >= 0 && y < h {
+                Some(Position { x, y })
+            } else {
+                None
+            }
+        });
+        for next in neighbours {
+            if !blocked.contains(&next) && !came_from.contains_key(&next) {
+                came_from.insert(next, pos);
+                frontier.push_back(next);
+            }
+        }
+    }
+    None
+}
+
+#[allow(clippy::too_many_arguments)]
+fn ai_controller(
+    level: Res<Level>,
+    wrap: Res<WrapAxes>,
+    mut ais: Query<(&Player, &Position, &mut LastInput), With<AiController>>,
+    heads: Query<Entity, With<SnakeHead>>,
+    segments: Query<&SnakeSegment>,
+    positions: Query<&Position>,
+    walls: Query<&Position, With<Wall>>,
+    foods: Query<&Position, With<Food>>,
+) {
+    let mut blocked: HashSet<Position> = walls.iter().cloned().collect();
+    for head in heads.iter() {
+        blocked.extend(snake_body(head, &segments, &positions));
+    }
+    let directions = [Direction::Up, Direction::Left, Direction::Down, Direction::Right];
+    for (player, head, mut last_input) in ais.iter_mut() {
+        // Follow the first step of the shortest safe path to the closest
+        // reachable food.
+        let mut foods: Vec<Position> = foods.iter().cloned().collect();
+        foods.sort_by_key(|food| wrapped_distance(*head, *food, &level, *wrap));
+        let first_step = foods
+            .iter()
+            .find_map(|food| plan_path(*head, *food, &blocked, level.width, level.height, *wrap))
+            .and_then(|path| path.first().copied());
+        let planned = first_step.and_then(|next| {
+            directions.iter().copied().find(|direction| step(*head, *direction, &level) == next)
+        });
+        // Without a path, any move that doesn't crash right away will do,
+        // preferably carrying on straight.
+        let choice = planned.or_else(|| {
+            directions
+                .iter()
+                .copied()
+                .filter(|direction| *direction != player.direction.opposite())
+                .filter(|direction| !wrap.blocks(*head, *direction, &level))
+                .filter(|direction| !blocked.contains(&step(*head, *direction, &level)))
+                .max_by_key(|direction| *direction == player.direction)
+        });
+        if let Some(direction) = choice {
+            last_input.queue.clear();
+            last_input.push(direction);
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn portal_teleport(
+    mut q: QuerySet<(
+        Query<(&Portal, &Position)>,
+        Query<&mut Position, With<SnakeHead>>,
+    )>,
+) {
+    let exits: Vec<(Position, Position)> = q
+        .q0()
+        .iter()
+        .filter_map(|(portal, pos)| Some((*pos, *q.q0().get(portal.partner).ok()?.1)))
+        .collect();
+    // The body follows through on its own, since each segment just takes
+    // the tile its front segment had.
+    for mut head_pos in q.q1_mut().iter_mut() {
+        if let Some((_, exit)) = exits.iter().find(|(entry, _)| *entry == *head_pos) {
+            *head_pos = *exit;
+        }
+    }
+}
+
+fn head_rotation(mut heads: Query<(&Player, &mut Transform)>) {
+    for (player, mut transform) in heads.iter_mut() {
+        // The head texture faces up.
+        let angle = match player.direction {
+            Direction::Up => 0.,
+            Direction::Left => std::f32::consts::FRAC_PI_2,
+            Direction::Down => std::f32::consts::PI,
+            Direction::Right => -std::f32::consts::FRAC_PI_2,
+        };
+        transform.rotation = Quat::from_rotation_z(angle);
+    }
+}
+
+// Runs before `snake_movement`, so the heads haven't moved yet, and their
+// direction is still the one they moved in on the last tick. Only heads are
+// checked for crashes, so in `Rigid` a segment whose slide would take it over
+// an edge that doesn't wrap, into a wall, or onto another snake follows the
+// one ahead instead, as in `Classic`.
+fn segment_movement(
+    style: Res<MovementStyle>,
+    level: Res<Level>,
+    wrap: Res<WrapAxes>,
+    players: Query<&Player>,
+    walls: Query<&Position, (With<Wall>, Without<SnakeSegment>)>,
+    mut q: Query<(Entity, &mut Position, &SnakeSegment)>,
+) {
+    let heads: Vec<_> = q
+        .iter_mut()
+        .filter(|(_, _, s)| s.front.is_none())
+        .map(|(h, p, s)| (h, s.back, *p))
+        .collect();
+    let rigid = *style == MovementStyle::Rigid;
+    let walls: HashSet<Position> = if rigid { walls.iter().copied().collect() } else { HashSet::new() };
+    // How many parts of each snake, and of all of them, are on each tile
+    // before any of them move.
+    let mut bodies: Vec<HashMap<Position, usize>> = Vec::new();
+    let mut everyone: HashMap<Position, usize> = HashMap::new();
+    for &(head, _, _) in heads.iter().filter(|_| rigid) {
+        let mut body = HashMap::new();
+        let mut part = Some(head);
+        while let Some(entity) = part {
+            let pos = *q.get_component::<Position>(entity).unwrap();
+            *body.entry(pos).or_insert(0) += 1;
+            *everyone.entry(pos).or_insert(0) += 1;
+            part = q.get_component::<SnakeSegment>(entity).unwrap().back;
+        }
+        bodies.push(body);
+    }
+    for (index, (head, mut e, mut p)) in heads.into_iter().enumerate() {
+        let direction = players.get(head).map(|player| player.direction);
+        let others = |pos: &Position| {
+            let own = bodies.get(index).and_then(|body| body.get(pos)).copied().unwrap_or(0);
+            everyone.get(pos).copied().unwrap_or(0) > own
+        };
+        while let Some(es) = e {
+            let oldp = *q.get_component::<Position>(es).unwrap();
+            let newp = match (*style, direction) {
+                // Segments just grown sit on the tile of the one ahead, and
+                // stay behind to spread out like they do in `Classic`.
+                (MovementStyle::Rigid, Ok(direction)) if oldp != p => {
+                    let slid = step(oldp, direction, &level);
+                    if wrap.blocks(oldp, direction, &level) || walls.contains(&slid) || others(&slid) {
+                        p
+                    } else {
+                        slid
+                    }
+                }
+                _ => p,
+            };
+            q.set::<Position>(es, newp).unwrap();
+            p = oldp;
+            e = q.get_component::<SnakeSegment>(es).unwrap().back;
+        }
+    }
+}
+
+fn spawn_head(
+    commands: &mut Commands,
+    material: Handle<ColorMaterial>,
+    position: Position,
+) -> Entity {
+    commands
+        .spawn(SpriteBundle {
+            material,
+            transform: Transform::from_translation(Vec3::new(0., 0., 1.)),
+            ..Default::default()
+        })
+        .with(position)
+        .with(Size::square(0.8))
+        .with(RenderPosition::default())
+        .with(Snake)
+        .with(SnakeHead)
+        .current_entity()
+        .unwrap()
+}
+
+fn spawn_segment(
+    commands: &mut Commands,
+    material: Handle<ColorMaterial>,
+    position: Position,
+) -> Entity {
+    commands
+        .spawn(SpriteBundle {
+            material,
+            transform: Transform::from_translation(Vec3::new(0., 0., 1.)),
+            ..Default::default()
+        })
+        .with(position)
+        .with(Size::square(0.65))
+        .with(RenderPosition::default())
+        .with(Snake)
+        .current_entity()
+        .unwrap()
+}
+
+// Appends `n` segments behind the tail of `head`'s snake. They all start on
+// the tail's tile, and spread out over the next ticks as the snake moves on.
+pub(crate) fn grow_snake(
+    commands: &mut Commands,
+    segments: &mut Query<(Entity, &mut SnakeSegment)>,
+    positions: &Query<&Position, With<SnakeSegment>>,
+    body_material: Handle<ColorMaterial>,
+    head: Entity,
+    n: u32,
+) {
+    if n == 0 {
+        return;
+    }
+    let tail = get_tail(head, segments);
+    let tail_pos = *positions.get(tail).unwrap();
+    let new_segments: Vec<Entity> = (0..n)
+        .map(|_| spawn_segment(commands, body_material.clone(), tail_pos))
+        .collect();
+    let mut front = tail;
+    for (i, &seg) in new_segments.iter().enumerate() {
+        commands.insert_one(seg, SnakeSegment {
+            front: Some(front),
+            back: new_segments.get(i + 1).cloned(),
+        });
+        front = seg;
+    }
+    let (_, mut tail_seg) = segments.get_mut(tail).unwrap();
+    tail_seg.back = Some(new_segments[0]);
+}
+
+// Removes up to `n` segments from the tail of `head`'s snake, but never the
+// head itself. Returns how many were removed.
+pub(crate) fn shrink_snake(
+    commands: &mut Commands,
+    segments: &mut Query<(Entity, &mut SnakeSegment)>,
+    head: Entity,
+    n: u32,
+) -> u32 {
+    let mut tail = get_tail(head, segments);
+    let mut removed = 0;
+    while removed < n && tail != head {
+        let new_tail = segments.get_mut(tail).unwrap().1.front.unwrap();
+        segments.get_mut(new_tail).unwrap().1.back = None;
+        commands.despawn(tail);
+        tail = new_tail;
+        removed += 1;
+    }
+    removed
+}
+
+// `layout` goes from head to tail and must not be empty. A single tile makes
+// a lone head, with no segment behind it.
+pub(crate) fn spawn_snake(
+    commands: &mut Commands,
+    materials: &Materials,
+    body_material: Handle<ColorMaterial>,
+    layout: &[Position],
+) -> Entity {
+    let snake = spawn_head(commands, materials.head_material.clone(), layout[0]);
+
+    let mut segments = vec![None, Some(snake)];
+    for &pos in &layout[1..] {
+        segments.push(Some(spawn_segment(
+            commands,
+            body_material.clone(),
+            pos,
+        )));
+    }
+    segments.push(None);
+    for w in segments.windows(3) {
+        let seg = w[1].unwrap();
+        commands.set_current_entity(seg);
+        commands.with(SnakeSegment {
+            front: w[0],
+            back: w[2],
+        });
+    }
+    snake
+}
+
+// The direction a snake laid out along `layout` (head first) starts off in.
+// It keeps to `preferred` unless that would take the head straight back
+// into the segment behind it, in which case it carries on away from it.
+pub(crate) fn start_direction(layout: &[Position], preferred: Direction, level: &Level) -> Direction {
+    let (head, neck) = match layout {
+        [head, neck, ..] if head != neck => (*head, *neck),
+        _ => return preferred,
+    };
+    if step(head, preferred, level) != neck {
+        return preferred;
+    }
+    let directions = [Direction::Up, Direction::Left, Direction::Down, Direction::Right];
+    directions
+        .iter()
+        .copied()
+        .find(|direction| step(neck, *direction, level) == head)
+        .unwrap_or(preferred)
+}
+
+fn get_tail(head: Entity, q: &mut Query<(Entity, &mut SnakeSegment)>) -> Entity {
+    let mut tail = head;
+    while let Ok((_, seg)) = q.get_mut(tail) {
+        if let Some(t) = seg.back {
+            tail = t;
+        } else {
+            break;
+        }
+    }
+    tail
+}
+
+// Every segment behind `head`, front to back.
+pub(crate) fn body_of(head: Entity, segments: &Query<&SnakeSegment>) -> Vec<Entity> {
+    let mut body = Vec::new();
+    let mut next = segments.get(head).ok().and_then(|seg| seg.back);
+    while let Some(seg) = next {
+        body.push(seg);
+        next = segments.get(seg).ok().and_then(|seg| seg.back);
+    }
+    body
+}
+
+// How many tiles `head`'s snake covers, the head included.
+pub(crate) fn snake_length(head: Entity, segments: &Query<&SnakeSegment>) -> usize {
+    body_of(head, segments).len() + 1
+}
+
+// Where each part of `head`'s snake is, head first and tail last.
+pub(crate) fn snake_body(head: Entity, segments: &Query<&SnakeSegment>, positions: &Query<&Position>) -> Vec<Position> {
+    std::iter::once(head)
+        .chain(body_of(head, segments))
+        .filter_map(|part| positions.get(part).ok().copied())
+        .collect()
+}
+
+// Counted in ticks, like the ghost effect.
+fn confusion_expiry(move_timer: Res<MoveTimer>, mut inverted: ResMut<ControlsInverted>) {
+    if inverted.owner.is_some() {
+        inverted.timer.tick(move_timer.interval());
+        if inverted.timer.finished() {
+            inverted.owner = None;
+        }
+    }
+}
+
+fn ghost_flash(
+    time: Res<Time>,
+    ghost: Res<GhostEffect>,
+    segments: Query<&SnakeSegment>,
+    mut visibles: Query<&mut Visible, With<Snake>>,
+) {
+    for mut visible in visibles.iter_mut() {
+        visible.is_visible = true;
+    }
+    if let Some(head) = ghost.owner {
+        let shown = (time.seconds_since_startup() * 8.) as u64 % 2 == 0;
+        for entity in std::iter::once(head).chain(body_of(head, &segments)) {
+            if let Ok(mut visible) = visibles.get_mut(entity) {
+                visible.is_visible = shown;
+            }
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn connect_segments(
+    commands: &mut Commands,
+    mut q: QuerySet<(
+        Query<(Entity, &SnakeSegment, &Transform, &Sprite, &Handle<ColorMaterial>, &Visible)>,
+        Query<(Entity, &Connector, &mut Transform, &mut Sprite, &mut Handle<ColorMaterial>, &mut Visible)>,
+    )>,
+) {
+    let links: HashMap<Entity, (Vec3, Vec3, f32, Handle<ColorMaterial>, bool)> = q
+        .q0()
+        .iter()
+        .filter_map(|(entity, segment, transform, sprite, material, visible)| {
+            let front = q.q0().get(segment.front?).ok()?.2.translation;
+            let width = sprite.size.x.min(sprite.size.y);
+            Some((entity, (transform.translation, front, width, material.clone(), visible.is_visible)))
+        })
+        .collect();
+    let mut connected = HashSet::new();
+    for (entity, connector, mut transform, mut sprite, mut material, mut visible) in q.q1_mut().iter_mut() {
+        match links.get(&connector.segment) {
+            Some((back, front, width, segment_material, shown)) if connected.insert(connector.segment) => {
+                let gap = (*front - *back).truncate();
+                transform.translation = ((*back + *front) / 2.).truncate().extend(0.9);
+                sprite.size = Vec2::new(gap.x.abs().max(*width), gap.y.abs().max(*width));
+                *material = segment_material.clone();
+                // Right after wrapping around the arena the two ends sit on
+                // opposite edges, with nothing to bridge.
+                visible.is_visible = *shown && gap.x.abs().max(gap.y.abs()) < 2. * width;
+            }
+            _ => commands.despawn(entity),
+        }
+    }
+    for (segment, (_, _, _, material, _)) in links.iter().filter(|(segment, _)| !connected.contains(*segment)) {
+        commands
+            .spawn(SpriteBundle {
+                material: material.clone(),
+                sprite: Sprite::new(Vec2::new(0., 0.)),
+                transform: Transform::from_translation(Vec3::new(0., 0., 0.9)),
+                ..Default::default()
+            })
+            .with(Connector { segment: *segment });
+    }
+}
+
+fn wrap_dip(
+    commands: &mut Commands,
+    animation: Res<WrapAnimation>,
+    move_timer: Res<MoveTimer>,
+    mut heads: Query<(Entity, &RenderPosition, &mut Transform, Option<&WrapAnim>), With<SnakeHead>>,
+) {
+    for (head, render_pos, mut transform, dipping) in heads.iter_mut() {
+        let wrapping = *animation == WrapAnimation::Dip && render_pos.wrap != Vec2::default();
+        if wrapping {
+            // Gone halfway through the tick, right as it crosses the edge.
+            let t = move_timer.progress();
+            transform.scale = Vec3::splat((1. - 2. * t).abs());
+            if dipping.is_none() {
+                commands.insert_one(head, WrapAnim);
+            }
+        } else if dipping.is_some() {
+            transform.scale = Vec3::one();
+            commands.remove_one::<WrapAnim>(head);
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn wrap_copies(
+    commands: &mut Commands,
+    mut q: QuerySet<(
+        Query<(Entity, &RenderPosition, &Transform, &Sprite, &Handle<ColorMaterial>, &Visible)>,
+        Query<(Entity, &WrapCopy, &mut Transform, &mut Sprite, &mut Handle<ColorMaterial>, &mut Visible)>,
+    )>,
+) {
+    let wrapping: HashMap<Entity, (Transform, Vec2, Handle<ColorMaterial>, bool)> = q
+        .q0()
+        .iter()
+        .filter(|(_, render_pos, ..)| render_pos.wrap != Vec2::default())
+        .map(|(entity, render_pos, transform, sprite, material, visible)| {
+            let mut transform = *transform;
+            transform.translation += render_pos.wrap.extend(0.);
+            (entity, (transform, sprite.size, material.clone(), visible.is_visible))
+        })
+        .collect();
+    let mut copied = HashSet::new();
+    for (entity, copy, mut transform, mut sprite, mut material, mut visible) in q.q1_mut().iter_mut() {
+        match wrapping.get(&copy.source) {
+            Some((source_transform, size, source_material, shown)) if copied.insert(copy.source) => {
+                *transform = *source_transform;
+                sprite.size = *size;
+                *material = source_material.clone();
+                visible.is_visible = *shown;
+            }
+            _ => commands.despawn(entity),
+        }
+    }
+    for (source, (transform, size, material, _)) in wrapping.iter().filter(|(source, _)| !copied.contains(*source)) {
+        commands
+            .spawn(SpriteBundle {
+                material: material.clone(),
+                sprite: Sprite::new(*size),
+                transform: *transform,
+                ..Default::default()
+            })
+            .with(WrapCopy { source: *source });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ArenaTiles;
+    use crate::headless::{
+        headless_app, push_direction, run_system, run_ticks, snake_positions, spawn_player, tiles,
+    };
+
+    #[test]
+    fn quick_turn_back_does_not_reverse() {
+        let mut app = headless_app(Level::default());
+        let snake = spawn_player(&mut app, 0, Direction::Right, &tiles(&[(5, 5), (4, 5), (3, 5)]));
+        // Both before the tick: Down is only a reversal once Up is taken.
+        push_direction(&mut app, snake, Direction::Up);
+        push_direction(&mut app, snake, Direction::Down);
+        for head in tiles(&[(5, 6), (5, 7)]) {
+            run_ticks(&mut app, 1);
+            let body = snake_positions(&app, snake);
+            assert_eq!(body[0], head);
+            assert!(!body[1..].contains(&head));
+            assert_eq!(app.world.get::<Player>(snake).unwrap().direction, Direction::Up);
+        }
+    }
+
+    #[test]
+    fn plan_path_wraps_around_the_edge() {
+        let blocked = HashSet::new();
+        let (start, goal) = (Position { x: 0, y: 5 }, Position { x: 14, y: 5 });
+        let path = plan_path(start, goal, &blocked, 15, 15, WrapAxes::default());
+        assert_eq!(path, Some(vec![goal]));
+        // The long way round when that edge doesn't wrap.
+        let path = plan_path(start, goal, &blocked, 15, 15, WrapAxes { x: false, y: true }).unwrap();
+        assert_eq!(path.len(), 14);
+        assert_eq!(path.last(), Some(&goal));
+    }
+
+    #[test]
+    fn plan_path_on_a_blocked_board() {
+        let (start, goal) = (Position { x: 0, y: 0 }, Position { x: 7, y: 7 });
+        let blocked: HashSet<Position> = ArenaTiles::new(&Level::default())
+            .0
+            .into_iter()
+            .filter(|pos| *pos != start && *pos != goal)
+            .collect();
+        assert_eq!(plan_path(start, goal, &blocked, 15, 15, WrapAxes::default()), None);
+    }
+
+    #[test]
+    fn wrap_axes_block_only_their_solid_edges() {
+        let level = Level::default();
+        let (left, right) = (Position { x: 0, y: 7 }, Position { x: 14, y: 7 });
+        let (bottom, top) = (Position { x: 7, y: 0 }, Position { x: 7, y: 14 });
+        let middle = Position { x: 7, y: 7 };
+        // Whether each axis's edges block, for each setting.
+        let settings = [("none", true, true), ("x", false, true), ("y", true, false), ("both", false, false)];
+        for &(name, x_blocks, y_blocks) in &settings {
+            let wrap = WrapAxes::from_name(name).unwrap();
+            assert_eq!(wrap.blocks(left, Direction::Left, &level), x_blocks, "{}", name);
+            assert_eq!(wrap.blocks(right, Direction::Right, &level), x_blocks, "{}", name);
+            assert_eq!(wrap.blocks(bottom, Direction::Down, &level), y_blocks, "{}", name);
+            assert_eq!(wrap.blocks(top, Direction::Up, &level), y_blocks, "{}", name);
+            // Moving along an edge, or away from it, never does.
+            assert!(!wrap.blocks(left, Direction::Up, &level), "{}", name);
+            assert!(!wrap.blocks(top, Direction::Down, &level), "{}", name);
+            for &direction in &[Direction::Left, Direction::Up, Direction::Right, Direction::Down] {
+                assert!(!wrap.blocks(middle, direction, &level), "{}", name);
+            }
+        }
+    }
+
+    // Which snake `grow` and `shrink` work on, by how much, and how many
+    // segments `shrink` removed.
+    struct Resize {
+        head: Entity,
+        n: u32,
+        removed: u32,
+    }
+
+    fn grow(
+        commands: &mut Commands,
+        resize: Res<Resize>,
+        mut segments: Query<(Entity, &mut SnakeSegment)>,
+        positions: Query<&Position, With<SnakeSegment>>,
+    ) {
+        grow_snake(commands, &mut segments, &positions, Handle::default(), resize.head, resize.n);
+    }
+
+    fn shrink(commands: &mut Commands, mut resize: ResMut<Resize>, mut segments: Query<(Entity, &mut SnakeSegment)>) {
+        resize.removed = shrink_snake(commands, &mut segments, resize.head, resize.n);
+    }
+
+    // Checks every link both ways, from the head to the tail, and that there
+    // are `length` parts.
+    fn assert_linked(app: &App, head: Entity, length: usize) {
+        assert_eq!(app.world.get::<SnakeSegment>(head).unwrap().front, None);
+        let mut parts = vec![head];
+        while let Some(back) = app.world.get::<SnakeSegment>(*parts.last().unwrap()).unwrap().back {
+            assert_eq!(app.world.get::<SnakeSegment>(back).unwrap().front, parts.last().copied());
+            assert!(!parts.contains(&back));
+            parts.push(back);
+        }
+        assert_eq!(parts.len(), length);
+    }
+
+    #[test]
+    fn grow_snake_links_new_segments_to_the_tail() {
+        let mut app = headless_app(Level::default());
+        let snake = spawn_player(&mut app, 0, Direction::Right, &tiles(&[(5, 5), (4, 5), (3, 5)]));
+        app.resources.insert(Resize {
+            head: snake,
+            n: 3,
+            removed: 0,
+        });
+        run_system(&mut app, grow.system());
+        assert_linked(&app, snake, 6);
+        // Stacked on the tail's tile.
+        assert_eq!(
+            snake_positions(&app, snake),
+            tiles(&[(5, 5), (4, 5), (3, 5), (3, 5), (3, 5), (3, 5)])
+        );
+    }
+
+    // A snake of 5 along row 5, shrunk by `n`, and how many it lost.
+    fn shrunk_by(n: u32) -> (App, Entity, u32) {
+        let mut app = headless_app(Level::default());
+        let layout = tiles(&[(5, 5), (4, 5), (3, 5), (2, 5), (1, 5)]);
+        let snake = spawn_player(&mut app, 0, Direction::Right, &layout);
+        app.resources.insert(Resize {
+            head: snake,
+            n,
+            removed: 0,
+        });
+        run_system(&mut app, shrink.system());
+        let removed = app.resources.get::<Resize>().unwrap().removed;
+        (app, snake, removed)
+    }
+
+    #[test]
+    fn shrink_snake_removes_from_the_tail() {
+        let (app, snake, removed) = shrunk_by(2);
+        assert_eq!(removed, 2);
+        assert_linked(&app, snake, 3);
+        assert_eq!(snake_positions(&app, snake), tiles(&[(5, 5), (4, 5), (3, 5)]));
+    }
+
+    #[test]
+    fn shrink_snake_keeps_the_head() {
+        let (app, snake, removed) = shrunk_by(10);
+        assert_eq!(removed, 4);
+        assert_linked(&app, snake, 1);
+        assert_eq!(snake_positions(&app, snake), tiles(&[(5, 5)]));
+    }
+
+    // Where a snake heading right is two ticks after turning up.
+    fn after_turn(style: MovementStyle) -> Vec<Position> {
+        let mut app = headless_app(Level::default());
+        app.resources.insert(style);
+        let snake = spawn_player(&mut app, 0, Direction::Right, &tiles(&[(5, 5), (4, 5), (3, 5)]));
+        push_direction(&mut app, snake, Direction::Up);
+        run_ticks(&mut app, 2);
+        snake_positions(&app, snake)
+    }
+
+    #[test]
+    fn classic_and_rigid_bodies_after_a_turn() {
+        // Following the head round the corner.
+        assert_eq!(after_turn(MovementStyle::Classic), tiles(&[(5, 7), (5, 6), (5, 5)]));
+        // Moved as a whole: right on the tick the head turned, then up.
+        assert_eq!(after_turn(MovementStyle::Rigid), tiles(&[(5, 7), (5, 6), (4, 6)]));
+    }
+
+    // An L-shaped rigid snake heading up, its tail on the top row, moved one
+    // tick with `wrap` and a wall at `wall`, if any.
+    fn rigid_slide(wrap: WrapAxes, wall: Option<(i32, i32)>) -> Vec<Position> {
+        let mut app = headless_app(Level::default());
+        app.resources.insert(MovementStyle::Rigid);
+        app.resources.insert(wrap);
+        if let Some((x, y)) = wall {
+            app.world.spawn((Wall, Position { x, y }));
+        }
+        let snake = spawn_player(&mut app, 0, Direction::Up, &tiles(&[(5, 12), (4, 12), (4, 13), (4, 14)]));
+        run_ticks(&mut app, 1);
+        snake_positions(&app, snake)
+    }
+
+    #[test]
+    fn rigid_segments_dont_slide_through_edges_or_walls() {
+        // The tail wraps round to the bottom row, the way the head would.
+        assert_eq!(rigid_slide(WrapAxes::default(), None), tiles(&[(5, 13), (4, 13), (4, 14), (4, 0)]));
+        // Without wrapping it follows the segment ahead instead.
+        let no_wrap = WrapAxes { x: false, y: false };
+        assert_eq!(rigid_slide(no_wrap, None), tiles(&[(5, 13), (4, 13), (4, 14), (4, 13)]));
+        // As does a segment sliding into a wall.
+        assert_eq!(
+            rigid_slide(WrapAxes::default(), Some((4, 0))),
+            tiles(&[(5, 13), (4, 13), (4, 14), (4, 13)])
+        );
+    }
+
+    #[test]
+    fn spawned_snakes_are_linked_head_to_tail() {
+        for &length in &[1, 6] {
+            let level = Level {
+                start_length: length,
+                ..Default::default()
+            };
+            let layout = level.snake_layout(0);
+            let mut app = headless_app(level);
+            let snake = spawn_player(&mut app, 0, Direction::Up, &layout);
+            assert_linked(&app, snake, length as usize);
+            assert_eq!(snake_positions(&app, snake), layout);
+        }
+    }
+
+    #[test]
+    fn start_direction_never_faces_the_body() {
+        let level = Level::default();
+        let head = (7, 7);
+        // The neck on each side of the head, and the way off it.
+        let orientations = [
+            ((8, 7), Direction::Right, Direction::Left),
+            ((6, 7), Direction::Left, Direction::Right),
+            ((7, 8), Direction::Up, Direction::Down),
+            ((7, 6), Direction::Down, Direction::Up),
+        ];
+        for &(neck, towards, away) in &orientations {
+            let layout = tiles(&[head, neck]);
+            assert_eq!(start_direction(&layout, towards, &level), away);
+            assert_eq!(start_direction(&layout, away, &level), away);
+            // A turn to the side is fine as it is.
+            let side = if towards == Direction::Left || towards == Direction::Right {
+                Direction::Up
+            } else {
+                Direction::Left
+            };
+            assert_eq!(start_direction(&layout, side, &level), side);
+        }
+    }
+
+    #[test]
+    fn start_direction_across_a_wrapped_edge() {
+        let level = Level::default();
+        // The neck is on the far side, one step left of the head.
+        let layout = tiles(&[(0, 7), (14, 7)]);
+        assert_eq!(start_direction(&layout, Direction::Left, &level), Direction::Right);
+        // A lone head goes wherever it's told.
+        assert_eq!(start_direction(&tiles(&[(0, 7)]), Direction::Left, &level), Direction::Left);
+    }
+
+    // Which snake `read_body` looks at, and where its parts were.
+    struct Body {
+        head: Entity,
+        positions: Vec<Position>,
+    }
+
+    fn read_body(mut body: ResMut<Body>, segments: Query<&SnakeSegment>, positions: Query<&Position>) {
+        body.positions = snake_body(body.head, &segments, &positions);
+    }
+
+    fn body_of_layout(layout: &[Position]) -> Vec<Position> {
+        let mut app = headless_app(Level::default());
+        let snake = spawn_player(&mut app, 0, Direction::Up, layout);
+        app.resources.insert(Body {
+            head: snake,
+            positions: Vec::new(),
+        });
+        run_system(&mut app, read_body.system());
+        let body = app.resources.get::<Body>().unwrap();
+        body.positions.clone()
+    }
+
+    #[test]
+    fn snake_body_goes_head_to_tail() {
+        // Bent, so the order can't come out right by sorting.
+        let layout = tiles(&[(5, 5), (5, 4), (4, 4), (4, 5), (3, 5)]);
+        assert_eq!(body_of_layout(&layout), layout);
+    }
+
+    #[test]
+    fn snake_body_of_a_lone_head() {
+        assert_eq!(body_of_layout(&tiles(&[(2, 2)])), tiles(&[(2, 2)]));
+    }
+}
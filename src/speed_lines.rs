@@ -0,0 +1,103 @@
+use bevy::prelude::*;
+
+use crate::snake::{Player, SnakeHead};
+use crate::{add_tick_system, tile_size, Boosting, Direction, Level, Materials};
+
+const SPEED_LINE_SECONDS: f32 = 0.15;
+const MAX_SPEED_LINES: usize = 12;
+
+// While boosting, every tick leaves a couple of short streaks behind each
+// head, on either side of the body, that shrink away. They're only drawn.
+pub(crate) struct SpeedLinesPlugin;
+impl Plugin for SpeedLinesPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<SpeedLineMaterial>()
+            .add_system(speed_line_fade.system());
+        add_tick_system(app, || speed_lines.system());
+    }
+}
+
+// The body's color made brighter, taken again on every tick so it follows
+// the palette.
+struct SpeedLineMaterial(Handle<ColorMaterial>);
+impl FromResources for SpeedLineMaterial {
+    fn from_resources(resources: &Resources) -> Self {
+        let mut materials = resources.get_mut::<Assets<ColorMaterial>>().unwrap();
+        Self(materials.add(Color::WHITE.into()))
+    }
+}
+
+struct SpeedLine(Timer);
+
+#[allow(clippy::too_many_arguments)]
+fn speed_lines(
+    commands: &mut Commands,
+    boosting: Res<Boosting>,
+    windows: Res<Windows>,
+    level: Res<Level>,
+    materials: Res<Materials>,
+    material: Res<SpeedLineMaterial>,
+    mut assets: ResMut<Assets<ColorMaterial>>,
+    heads: Query<(&Player, &Transform), With<SnakeHead>>,
+    lines: Query<&SpeedLine>,
+) {
+    if !boosting.0 {
+        return;
+    }
+    if let Some(body) = assets.get(&materials.body_material).map(|body| body.color) {
+        let brighter = Color::rgba(
+            0.5 + body.r() * 0.5,
+            0.5 + body.g() * 0.5,
+            0.5 + body.b() * 0.5,
+            0.7,
+        );
+        if let Some(streak) = assets.get_mut(&material.0) {
+            streak.color = brighter;
+        }
+    }
+    let tile_size = tile_size(windows.get_primary().unwrap(), &level);
+    let mut room = MAX_SPEED_LINES.saturating_sub(lines.iter().count());
+    for (player, transform) in heads.iter() {
+        // Pointing back the way the head came.
+        let (back, across) = match player.direction {
+            Direction::Up => (Vec2::new(0., -1.), Vec2::new(1., 0.)),
+            Direction::Down => (Vec2::new(0., 1.), Vec2::new(1., 0.)),
+            Direction::Left => (Vec2::new(1., 0.), Vec2::new(0., 1.)),
+            Direction::Right => (Vec2::new(-1., 0.), Vec2::new(0., 1.)),
+        };
+        // Long along the way it goes, thin across it.
+        let size = (back.abs() * 0.8 + across * 0.06) * tile_size;
+        for side in [-0.35, 0.35].iter() {
+            if room == 0 {
+                return;
+            }
+            room -= 1;
+            let origin = transform.translation.truncate() + (back * 0.9 + across * *side) * tile_size;
+            commands
+                .spawn(SpriteBundle {
+                    material: material.0.clone(),
+                    sprite: Sprite::new(size),
+                    // Under the snakes.
+                    transform: Transform::from_translation(origin.extend(0.8)),
+                    ..Default::default()
+                })
+                .with(SpeedLine(Timer::from_seconds(SPEED_LINE_SECONDS, false)));
+        }
+    }
+}
+
+// In real time, so they're gone soon after boosting stops, paused or not.
+fn speed_line_fade(
+    commands: &mut Commands,
+    time: Res<Time>,
+    mut lines: Query<(Entity, &mut SpeedLine, &mut Transform)>,
+) {
+    for (entity, mut line, mut transform) in lines.iter_mut() {
+        line.0.tick(time.delta_seconds());
+        if line.0.finished() {
+            commands.despawn(entity);
+            continue;
+        }
+        transform.scale = Vec3::splat(line.0.percent_left());
+    }
+}
@@ -0,0 +1,80 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::config::Config;
+use crate::snake::Player;
+use crate::{add_tick_system, Daily, GameState, Practice, GAME_STATES};
+
+// Numbers about the current run, and totals over every run ever played.
+pub(crate) struct StatsPlugin;
+impl Plugin for StatsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        let lifetime = app.resources().get::<Config>().map(|config| config.stats.clone()).unwrap_or_default();
+        app.add_resource(RunStats::default())
+            .add_resource(lifetime)
+            .on_state_enter(GAME_STATES, GameState::Lost, record_run.system())
+            .on_state_enter(GAME_STATES, GameState::Won, record_run.system());
+        add_tick_system(app, || count_ticks.system());
+    }
+}
+
+// Reset by `game_setup` at the start of every run.
+#[derive(Default)]
+pub(crate) struct RunStats {
+    pub(crate) ticks: u32,
+    pub(crate) foods: u32,
+    pub(crate) max_combo: u32,
+}
+impl RunStats {
+    // The snake moves a tile per tick.
+    pub(crate) fn tiles_per_food(&self) -> Option<f32> {
+        if self.foods == 0 {
+            None
+        } else {
+            Some(self.ticks as f32 / self.foods as f32)
+        }
+    }
+}
+
+// Kept in the config file.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct LifetimeStats {
+    pub(crate) runs: u32,
+    pub(crate) ticks: u64,
+    pub(crate) foods: u64,
+    pub(crate) max_combo: u32,
+    // The best score on each daily challenge played, by its number.
+    pub(crate) daily_best: BTreeMap<u32, u32>,
+}
+
+fn count_ticks(mut stats: ResMut<RunStats>) {
+    stats.ticks += 1;
+}
+
+// Runs before `game_cleanup`, so the players are still around.
+fn record_run(
+    practice: Res<Practice>,
+    daily: Res<Daily>,
+    run: Res<RunStats>,
+    mut lifetime: ResMut<LifetimeStats>,
+    mut config: ResMut<Config>,
+    players: Query<&Player>,
+) {
+    // Practice runs don't count.
+    if practice.0 {
+        return;
+    }
+    lifetime.runs += 1;
+    lifetime.ticks += run.ticks as u64;
+    lifetime.foods += run.foods as u64;
+    lifetime.max_combo = lifetime.max_combo.max(run.max_combo);
+    // A daily is always played solo.
+    if let (Some(day), Some(player)) = (daily.0, players.iter().next()) {
+        let best = lifetime.daily_best.entry(day).or_insert(0);
+        *best = (*best).max(player.food);
+    }
+    config.stats = lifetime.clone();
+    config.save();
+}
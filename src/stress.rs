@@ -0,0 +1,148 @@
+use bevy::prelude::*;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::HashSet;
+
+use crate::collision::BumpEvent;
+use crate::snake::{spawn_snake, step, Snake, WrapAxes};
+use crate::{
+    add_tick_system, parsed_arg, Direction, GameRng, GameState, Level, Materials, Position, Tile, Wall, GAME_STATES,
+};
+
+// How many times a snake's spot is picked at random before giving up on it.
+const PLACEMENT_TRIES: usize = 100;
+
+// A debug mode for timing movement and collisions with lots of snakes on the
+// board: `--stress <snakes>` adds that many computer snakes to every run,
+// `--stress-length <length>` long. They're nobody's, so they don't score,
+// and crashing only clears them off the board.
+pub(crate) struct StressPlugin;
+impl Plugin for StressPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(stress_from_args())
+            .on_state_enter(GAME_STATES, GameState::Countdown, stress_setup.system());
+        add_tick_system(app, || stress_movement.system());
+    }
+}
+
+#[derive(Default)]
+struct StressTest {
+    snakes: u32,
+    // The level's starting length when not given.
+    length: Option<u32>,
+}
+
+// On the head of each of the stress test's snakes.
+pub(crate) struct StressSnake {
+    direction: Direction,
+}
+
+fn stress_from_args() -> StressTest {
+    StressTest {
+        snakes: parsed_arg("--stress", |snakes: &u32| *snakes > 0).unwrap_or(0),
+        length: parsed_arg("--stress-length", |length: &u32| *length > 0),
+    }
+}
+
+// Runs after `game_setup`, so the players' snakes are already on the board.
+// Each snake is laid out straight on free tiles, heading away from its tail.
+fn stress_setup(
+    commands: &mut Commands,
+    stress: Res<StressTest>,
+    level: Res<Level>,
+    materials: Res<Materials>,
+    mut rng: ResMut<GameRng>,
+    occupied: Query<&Position, Without<Tile>>,
+) {
+    if stress.snakes == 0 {
+        return;
+    }
+    let length = stress.length.unwrap_or(level.start_length) as i32;
+    let mut occupied: HashSet<Position> = occupied.iter().cloned().collect();
+    let directions = [Direction::Up, Direction::Left, Direction::Down, Direction::Right];
+    // From the run's generator, so the same seed lays out the same snakes.
+    let rng = &mut rng.0;
+    let mut spawned = 0;
+    for _ in 0..stress.snakes {
+        let layout = (0..PLACEMENT_TRIES).find_map(|_| {
+            let head = Position {
+                x: rng.gen_range(0..level.width as i32),
+                y: rng.gen_range(0..level.height as i32),
+            };
+            let direction = *directions.choose(rng).unwrap();
+            let (dx, dy) = match direction.opposite() {
+                Direction::Left => (-1, 0),
+                Direction::Right => (1, 0),
+                Direction::Up => (0, 1),
+                Direction::Down => (0, -1),
+            };
+            let layout: Vec<Position> = (0..length)
+                .map(|i| Position {
+                    x: head.x + dx * i,
+                    y: head.y + dy * i,
+                })
+                .collect();
+            let inside = |pos: &Position| {
+                pos.x >= 0 && pos.y >= 0 && pos.x < level.width as i32 && pos.y < level.height as i32
+            };
+            if layout.iter().all(|pos| inside(pos) && !occupied.contains(pos)) {
+                Some((layout, direction))
+            } else {
+                None
+            }
+        });
+        let (layout, direction) = match layout {
+            Some(layout) => layout,
+            None => break,
+        };
+        occupied.extend(layout.iter().cloned());
+        let head = spawn_snake(commands, &materials, materials.second_body_material.clone(), &layout);
+        commands.insert_one(head, StressSnake { direction });
+        spawned += 1;
+    }
+    if spawned < stress.snakes {
+        warn!("only found room for {} of {} stress test snakes", spawned, stress.snakes);
+    }
+    info!("stress test: {} snakes {} long", spawned, length);
+}
+
+// Keeps going straight, turning now and then, and away from anything about
+// to be run into. With nowhere left to go it crashes. Added after
+// `SnakePlugin`, so the bodies have already moved up.
+#[allow(clippy::type_complexity)]
+fn stress_movement(
+    level: Res<Level>,
+    wrap: Res<WrapAxes>,
+    mut rng: ResMut<GameRng>,
+    mut bump_events: ResMut<Events<BumpEvent>>,
+    mut q: QuerySet<(
+        Query<&Position, With<Snake>>,
+        Query<&Position, With<Wall>>,
+        Query<(Entity, &mut StressSnake, &mut Position)>,
+    )>,
+) {
+    let mut blocked: HashSet<Position> = q.q0().iter().chain(q.q1().iter()).cloned().collect();
+    let directions = [Direction::Up, Direction::Left, Direction::Down, Direction::Right];
+    // The run's generator too, so the same seed plays out the same.
+    let rng = &mut rng.0;
+    for (head, mut snake, mut pos) in q.q2_mut().iter_mut() {
+        let safe: Vec<Direction> = directions
+            .iter()
+            .copied()
+            .filter(|direction| *direction != snake.direction.opposite())
+            .filter(|direction| !wrap.blocks(*pos, *direction, &level))
+            .filter(|direction| !blocked.contains(&step(*pos, *direction, &level)))
+            .collect();
+        let straight = safe.contains(&snake.direction);
+        if !safe.is_empty() && (!straight || rng.gen_ratio(1, 8)) {
+            snake.direction = *safe.choose(rng).unwrap();
+        }
+        if wrap.blocks(*pos, snake.direction, &level) {
+            bump_events.send(BumpEvent { head, wall: None });
+            continue;
+        }
+        *pos = step(*pos, snake.direction, &level);
+        // So the ones moving after it steer clear of where it went.
+        blocked.insert(*pos);
+    }
+}
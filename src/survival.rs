@@ -0,0 +1,134 @@
+use bevy::prelude::*;
+use rand::seq::SliceRandom;
+use std::collections::{HashSet, VecDeque};
+
+use crate::shrink::ShrinkTimer;
+use crate::snake::{SnakeHead, WrapAxes};
+use crate::stats::RunStats;
+use crate::{add_tick_system, parsed_arg, ArenaTiles, GameRng, Level, Materials, Obstacle, Position, Size, Tile, Wall};
+
+// The "growing walls" survival mode, on when `--grow-walls` is given.
+pub(crate) struct SurvivalPlugin;
+impl Plugin for SurvivalPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(GrowingWalls::new(grow_walls_from_args()));
+        add_tick_system(app, || grow_walls.system());
+    }
+}
+
+// A wall goes up on a random free tile for every `every` foods eaten.
+pub(crate) struct GrowingWalls {
+    every: Option<u32>,
+    // How many of those have come due, whether or not there was room.
+    due: u32,
+    pub(crate) count: u32,
+}
+impl GrowingWalls {
+    fn new(every: Option<u32>) -> Self {
+        Self { every, due: 0, count: 0 }
+    }
+
+    pub(crate) fn enabled(&self) -> bool {
+        self.every.is_some()
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.due = 0;
+        self.count = 0;
+    }
+}
+
+// `--grow-walls <foods>` on the command line; without it no walls grow.
+fn grow_walls_from_args() -> Option<u32> {
+    parsed_arg("--grow-walls", |every: &u32| *every > 0)
+}
+
+// How many tiles can be reached from `start` without going through a wall.
+// Snakes and food don't count as blocking: they move, or get eaten.
+fn reachable(start: Position, walls: &HashSet<Position>, level: &Level, wrap: WrapAxes) -> usize {
+    let (w, h) = (level.width as i32, level.height as i32);
+    let mut seen = HashSet::new();
+    seen.insert(start);
+    let mut frontier = VecDeque::new();
+    frontier.push_back(start);
+    while let Some(pos) = frontier.pop_front() {
+        for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)].iter() {
+            let x = if wrap.x { (pos.x + dx).rem_euclid(w) } else { pos.x + dx };
+            let y = if wrap.y { (pos.y + dy).rem_euclid(h) } else { pos.y + dy };
+            let next = Position { x, y };
+            if x >= 0 && x < w && y >= 0 && y < h && !walls.contains(&next) && seen.insert(next) {
+                frontier.push_back(next);
+            }
+        }
+    }
+    seen.len()
+}
+
+// Runs after `eat_events_solver` (`FoodPlugin` is added first), so the foods
+// eaten on this tick are already counted.
+#[allow(clippy::too_many_arguments)]
+fn grow_walls(
+    commands: &mut Commands,
+    level: Res<Level>,
+    tiles: Res<ArenaTiles>,
+    wrap: Res<WrapAxes>,
+    materials: Res<Materials>,
+    stats: Res<RunStats>,
+    shrink: Res<ShrinkTimer>,
+    mut growing: ResMut<GrowingWalls>,
+    mut rng: ResMut<GameRng>,
+    occupied: Query<&Position, Without<Tile>>,
+    walls: Query<&Position, With<Wall>>,
+    heads: Query<&Position, With<SnakeHead>>,
+) {
+    let every = match growing.every {
+        Some(every) => every,
+        None => return,
+    };
+    if growing.due >= stats.foods / every {
+        return;
+    }
+    // If there's no room, it's skipped rather than put off, so walls don't
+    // all come at once when some frees up later.
+    growing.due += 1;
+    let heads: Vec<Position> = heads.iter().cloned().collect();
+    let start = match heads.first() {
+        Some(&start) => start,
+        None => return,
+    };
+    let occupied: HashSet<Position> = occupied.iter().cloned().collect();
+    let mut walled: HashSet<Position> = walls.iter().cloned().collect();
+    // Never right in front of a head, where it couldn't be dodged.
+    let next_to_head = |pos: &Position| {
+        heads
+            .iter()
+            .any(|head| (head.x - pos.x).abs() + (head.y - pos.y).abs() <= 1)
+    };
+    let mut candidates: Vec<Position> = tiles
+        .free(&occupied)
+        .filter(|pos| !shrink.shrunk(&level, *pos) && !next_to_head(pos))
+        .collect();
+    candidates.shuffle(&mut rng.0);
+    // Nor where it would cut any part of the arena off from the head: the
+    // only tile it may take away is its own.
+    let before = reachable(start, &walled, &level, *wrap);
+    let pos = candidates.into_iter().find(|pos| {
+        walled.insert(*pos);
+        let after = reachable(start, &walled, &level, *wrap);
+        walled.remove(pos);
+        after + 1 == before
+    });
+    if let Some(pos) = pos {
+        growing.count += 1;
+        commands
+            .spawn(SpriteBundle {
+                material: materials.wall_material.clone(),
+                transform: Transform::from_translation(Vec3::new(0., 0., 0.5)),
+                ..Default::default()
+            })
+            .with(Wall)
+            .with(Obstacle)
+            .with(pos)
+            .with(Size::square(1.));
+    }
+}
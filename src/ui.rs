@@ -0,0 +1,1149 @@
+use bevy::diagnostic::*;
+use bevy::prelude::*;
+use bevy::window::WindowResized;
+
+use crate::collision::RoundResult;
+use crate::food::{Combo, PendingColor};
+use crate::frenzy::FrenzyActive;
+use crate::objective::Objective;
+use crate::settings::Palette;
+use crate::snake::{snake_length, ControlsInverted, Player, SnakeSegment};
+use crate::stats::{LifetimeStats, RunStats};
+use crate::survival::GrowingWalls;
+use crate::{
+    ConfirmQuit, Countdown, Daily, Difficulty, GameState, GamepadLost, KeyBindings, Practice, Recorder, GAME_STATES,
+};
+
+// The HUD, and the menu, pause and game over screens.
+pub(crate) struct UiPlugin;
+impl Plugin for UiPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_plugin(FrameTimeDiagnosticsPlugin)
+            .add_startup_system(hud_setup.system())
+            .add_system(update_fps.system())
+            .add_resource(ElapsedTime::default())
+            .add_resource(ShowFps::default())
+            .add_resource(ShowHelp::default())
+            .add_system(update_help.system())
+            .add_system(update_difficulty_text.system())
+            .add_system(update_run_time.system())
+            .add_system(update_snake_length.system())
+            .add_system(update_practice_text.system())
+            .add_system(update_wall_count.system())
+            .add_system(update_daily_text.system())
+            .add_system(update_confusion_text.system())
+            .add_system(update_pair_text.system())
+            .add_system(update_frenzy_text.system())
+            .add_system(update_goal_text.system())
+            .add_system(update_quit_text.system())
+            .add_system(update_gamepad_lost_text.system())
+            .add_system(update_hud.system())
+            .add_system(scale_hud_text.system())
+            .add_resource(MenuSelection::default())
+            .init_resource::<ButtonMaterials>()
+            .add_event::<MenuActivated>()
+            .add_system(menu_navigation.system())
+            .add_system(menu_focus.system())
+            .on_state_enter(GAME_STATES, GameState::Menu, menu_setup.system())
+            .on_state_exit(GAME_STATES, GameState::Menu, despawn_all::<MenuUi>.system())
+            .on_state_exit(GAME_STATES, GameState::Menu, hide_help.system())
+            // The demo plays behind the same menu.
+            .on_state_enter(GAME_STATES, GameState::Demo, menu_setup.system())
+            .on_state_exit(GAME_STATES, GameState::Demo, despawn_all::<MenuUi>.system())
+            .on_state_enter(GAME_STATES, GameState::Lost, game_over_setup.system())
+            .on_state_exit(GAME_STATES, GameState::Lost, despawn_all::<GameOverUi>.system())
+            // Reaching the goal gets the same screen, with the time up top.
+            .on_state_enter(GAME_STATES, GameState::Won, game_over_setup.system())
+            .on_state_exit(GAME_STATES, GameState::Won, despawn_all::<GameOverUi>.system())
+            .on_state_enter(GAME_STATES, GameState::Countdown, countdown_setup.system())
+            .on_state_update(GAME_STATES, GameState::Countdown, update_countdown_text.system())
+            .on_state_exit(GAME_STATES, GameState::Countdown, despawn_all::<CountdownUi>.system())
+            .on_state_enter(GAME_STATES, GameState::Paused, pause_setup.system())
+            .on_state_exit(GAME_STATES, GameState::Paused, despawn_all::<PauseUi>.system());
+    }
+}
+
+// On every HUD text, which is sized after the window.
+struct HudText;
+struct FpsText;
+struct FoodText;
+struct DifficultyText;
+struct TimeText;
+struct SnakeLengthText;
+struct PracticeText;
+struct WallCountText;
+struct DailyText;
+struct ConfusionText;
+struct PairText;
+struct FrenzyText;
+struct GoalText;
+struct MenuUi;
+struct GameOverUi;
+struct CountdownUi;
+struct CountdownText;
+struct QuitText;
+struct GamepadLostText;
+struct PauseUi;
+struct HelpUi;
+
+// Whether the FPS counter is on screen. It starts hidden in release builds.
+pub(crate) struct ShowFps(pub(crate) bool);
+impl Default for ShowFps {
+    fn default() -> Self {
+        Self(cfg!(debug_assertions))
+    }
+}
+
+// Which of the buttons on screen has keyboard focus, counted from the top.
+// Screens reset it when they're set up.
+#[derive(Default)]
+pub(crate) struct MenuSelection(pub(crate) usize);
+
+// On each button of a screen's list of options, with its place in the list.
+pub(crate) struct MenuItem(pub(crate) usize);
+
+// Sent when the focused button is picked with Enter instead of clicked.
+pub(crate) struct MenuActivated(pub(crate) Entity);
+
+pub(crate) struct ButtonMaterials {
+    pub(crate) normal: Handle<ColorMaterial>,
+    focused: Handle<ColorMaterial>,
+}
+impl FromResources for ButtonMaterials {
+    fn from_resources(resources: &Resources) -> Self {
+        let mut materials = resources.get_mut::<Assets<ColorMaterial>>().unwrap();
+        Self {
+            normal: materials.add(Color::rgb(0.15, 0.15, 0.15).into()),
+            focused: materials.add(Color::rgb(0.25, 0.25, 0.25).into()),
+        }
+    }
+}
+
+// The controls screen, shown over the menu.
+#[derive(Default)]
+pub(crate) struct ShowHelp(pub(crate) bool);
+
+// How long the current run has been going, in seconds. It only runs while
+// the snake moves, so pauses and the game over screen don't count.
+#[derive(Default)]
+pub(crate) struct ElapsedTime(f32);
+
+// HUD font size as a fraction of the window's height, and its bounds.
+const HUD_FONT_SCALE: f32 = 0.03;
+const MIN_HUD_FONT_SIZE: f32 = 12.;
+const MAX_HUD_FONT_SIZE: f32 = 40.;
+
+fn hud_setup(commands: &mut Commands, asset_server: Res<AssetServer>) {
+    commands.spawn(TextBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    bottom: Val::Px(10.),
+                    right: Val::Px(10.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: "FPS:".to_string(),
+                font: asset_server.load("fonts/DejaVuSans.ttf"),
+                style: TextStyle {
+                    font_size: 20.0,
+                    color: Color::WHITE,
+                    ..Default::default()
+                },
+            },
+            ..Default::default()
+        })
+        .with(FpsText)
+        .with(HudText);
+    commands.spawn(TextBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(10.),
+                    left: Val::Px(10.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: "Food:".to_string(),
+                font: asset_server.load("fonts/DejaVuSans.ttf"),
+                style: TextStyle {
+                    font_size: 20.0,
+                    color: Color::WHITE,
+                    ..Default::default()
+                },
+            },
+            ..Default::default()
+        })
+        .with(FoodText)
+        .with(HudText);
+    commands.spawn(TextBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(10.),
+                    right: Val::Px(10.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: "Difficulty:".to_string(),
+                font: asset_server.load("fonts/DejaVuSans.ttf"),
+                style: TextStyle {
+                    font_size: 20.0,
+                    color: Color::WHITE,
+                    ..Default::default()
+                },
+            },
+            ..Default::default()
+        })
+        .with(DifficultyText)
+        .with(HudText);
+    commands.spawn(TextBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    bottom: Val::Px(10.),
+                    left: Val::Px(10.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: format_time(0.),
+                font: asset_server.load("fonts/DejaVuSans.ttf"),
+                style: TextStyle {
+                    font_size: 20.0,
+                    color: Color::WHITE,
+                    ..Default::default()
+                },
+            },
+            ..Default::default()
+        })
+        .with(TimeText)
+        .with(HudText);
+    commands.spawn(TextBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(10.),
+                    left: Val::Percent(40.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: String::new(),
+                font: asset_server.load("fonts/DejaVuSans.ttf"),
+                style: TextStyle {
+                    font_size: 20.0,
+                    color: Color::WHITE,
+                    ..Default::default()
+                },
+            },
+            ..Default::default()
+        })
+        .with(SnakeLengthText)
+        .with(HudText);
+    commands
+        .spawn(TextBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    bottom: Val::Px(10.),
+                    left: Val::Percent(45.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: String::new(),
+                font: asset_server.load("fonts/DejaVuSans.ttf"),
+                style: TextStyle {
+                    font_size: 20.0,
+                    color: Color::YELLOW,
+                    ..Default::default()
+                },
+            },
+            ..Default::default()
+        })
+        .with(PracticeText)
+        .with(HudText);
+    commands
+        .spawn(TextBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    bottom: Val::Px(10.),
+                    left: Val::Percent(25.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: String::new(),
+                font: asset_server.load("fonts/DejaVuSans.ttf"),
+                style: TextStyle {
+                    font_size: 20.0,
+                    color: Color::WHITE,
+                    ..Default::default()
+                },
+            },
+            ..Default::default()
+        })
+        .with(WallCountText)
+        .with(HudText);
+    commands
+        .spawn(TextBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(40.),
+                    left: Val::Px(10.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: String::new(),
+                font: asset_server.load("fonts/DejaVuSans.ttf"),
+                style: TextStyle {
+                    font_size: 20.0,
+                    color: Color::WHITE,
+                    ..Default::default()
+                },
+            },
+            ..Default::default()
+        })
+        .with(DailyText)
+        .with(HudText);
+    commands
+        .spawn(TextBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(40.),
+                    left: Val::Percent(40.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: String::new(),
+                font: asset_server.load("fonts/DejaVuSans.ttf"),
+                style: TextStyle {
+                    font_size: 20.0,
+                    color: Color::rgb(1.0, 0.5, 0.1),
+                    ..Default::default()
+                },
+            },
+            ..Default::default()
+        })
+        .with(ConfusionText)
+        .with(HudText);
+    commands
+        .spawn(TextBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(70.),
+                    left: Val::Px(10.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: String::new(),
+                font: asset_server.load("fonts/DejaVuSans.ttf"),
+                style: TextStyle {
+                    font_size: 20.0,
+                    color: Color::WHITE,
+                    ..Default::default()
+                },
+            },
+            ..Default::default()
+        })
+        .with(PairText)
+        .with(HudText);
+    commands
+        .spawn(TextBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(70.),
+                    left: Val::Percent(40.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: String::new(),
+                font: asset_server.load("fonts/DejaVuSans.ttf"),
+                style: TextStyle {
+                    font_size: 20.0,
+                    color: Color::rgb(1.0, 0.85, 0.1),
+                    ..Default::default()
+                },
+            },
+            ..Default::default()
+        })
+        .with(FrenzyText)
+        .with(HudText);
+    commands
+        .spawn(TextBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(40.),
+                    right: Val::Px(10.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text {
+                value: String::new(),
+                font: asset_server.load("fonts/DejaVuSans.ttf"),
+                style: TextStyle {
+                    font_size: 20.0,
+                    color: Color::WHITE,
+                    ..Default::default()
+                },
+            },
+            ..Default::default()
+        })
+        .with(GoalText)
+        .with(HudText);
+}
+
+fn menu_setup(
+    commands: &mut Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: bevy::prelude::Size::new(Val::Percent(100.), Val::Percent(100.)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: materials.add(Color::NONE.into()),
+            ..Default::default()
+        })
+        .with(MenuUi)
+        .with_children(|parent| {
+            parent.spawn(TextBundle {
+                text: Text {
+                    value: "Idle Snake".to_string(),
+                    font: asset_server.load("fonts/DejaVuSans.ttf"),
+                    style: TextStyle {
+                        font_size: 60.0,
+                        color: Color::WHITE,
+                        ..Default::default()
+                    },
+                },
+                ..Default::default()
+            });
+            parent.spawn(TextBundle {
+                text: Text {
+                    value: "Press Space to Play, 2 for Two Players, 3 to Play the Computer, R to Watch the Last Run".to_string(),
+                    font: asset_server.load("fonts/DejaVuSans.ttf"),
+                    style: TextStyle {
+                        font_size: 20.0,
+                        color: Color::WHITE,
+                        ..Default::default()
+                    },
+                },
+                ..Default::default()
+            });
+            parent.spawn(TextBundle {
+                text: Text {
+                    value: "4 for the Daily Challenge, Tab to Change Difficulty, P for Practice, O for Settings, L for the Leaderboard, H for Controls".to_string(),
+                    font: asset_server.load("fonts/DejaVuSans.ttf"),
+                    style: TextStyle {
+                        font_size: 20.0,
+                        color: Color::WHITE,
+                        ..Default::default()
+                    },
+                },
+                ..Default::default()
+            });
+        });
+}
+
+fn hide_help(mut show_help: ResMut<ShowHelp>) {
+    show_help.0 = false;
+}
+
+fn key_names(keys: &[KeyCode]) -> String {
+    keys.iter().map(|key| format!("{:?}", key)).collect::<Vec<_>>().join(" or ")
+}
+
+// Read from the bindings, so it stays right whatever the keys are.
+fn help_lines(bindings: &KeyBindings) -> Vec<String> {
+    let mut lines = vec![format!(
+        "Move: {}, {}, {}, {}",
+        key_names(&bindings.solo.up),
+        key_names(&bindings.solo.left),
+        key_names(&bindings.solo.down),
+        key_names(&bindings.solo.right)
+    )];
+    for (index, keys) in bindings.versus.iter().enumerate() {
+        lines.push(format!(
+            "Player {} in Two Players: {}, {}, {}, {}",
+            index + 1,
+            key_names(&keys.up),
+            key_names(&keys.left),
+            key_names(&keys.down),
+            key_names(&keys.right)
+        ));
+    }
+    let actions = [
+        ("Play / Pause", bindings.pause),
+        ("Boost (hold)", bindings.boost),
+        ("Two Players", bindings.two_players),
+        ("Play the Computer", bindings.versus_ai),
+        ("Daily Challenge", bindings.daily),
+        ("Watch the Last Run / Restart", bindings.replay),
+        ("Change Difficulty", bindings.difficulty),
+        ("Practice Mode", bindings.practice),
+        ("Settings", bindings.settings),
+        ("Leaderboard", bindings.leaderboard),
+        ("Mute", bindings.mute),
+        ("Show FPS", bindings.fps),
+        ("Show the Frame Time Graph", bindings.frame_graph),
+        ("Log the Board Layout", bindings.layout),
+        ("Show Tile Coordinates", bindings.grid_coords),
+        ("Show the Minimap", bindings.minimap),
+        ("Step One Tick (paused)", bindings.step),
+        ("Save the Game", bindings.save),
+        ("Load the Saved Game", bindings.load),
+        ("Back / Quit", bindings.back),
+        ("Close This", bindings.help),
+    ];
+    lines.extend(actions.iter().map(|(action, key)| format!("{}: {:?}", action, key)));
+    lines
+}
+
+fn update_help(
+    commands: &mut Commands,
+    show_help: ChangedRes<ShowHelp>,
+    bindings: Res<KeyBindings>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+    shown: Query<Entity, With<HelpUi>>,
+) {
+    for entity in shown.iter() {
+        commands.despawn_recursive(entity);
+    }
+    if !show_help.0 {
+        return;
+    }
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: bevy::prelude::Size::new(Val::Percent(100.), Val::Percent(100.)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: materials.add(Color::rgba(0., 0., 0., 0.85).into()),
+            ..Default::default()
+        })
+        .with(HelpUi)
+        .with_children(|parent| {
+            for line in help_lines(&bindings) {
+                parent.spawn(TextBundle {
+                    text: Text {
+                        value: line,
+                        font: asset_server.load("fonts/DejaVuSans.ttf"),
+                        style: TextStyle {
+                            font_size: 18.0,
+                            color: Color::WHITE,
+                            ..Default::default()
+                        },
+                    },
+                    ..Default::default()
+                });
+            }
+        });
+}
+
+#[allow(clippy::too_many_arguments)]
+fn game_over_setup(
+    commands: &mut Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+    objective: Res<Objective>,
+    result: Res<RoundResult>,
+    recorder: Res<Recorder>,
+    elapsed: Res<ElapsedTime>,
+    stats: Res<RunStats>,
+    lifetime: Res<LifetimeStats>,
+    players: Query<&Player>,
+) {
+    let mut players: Vec<&Player> = players.iter().collect();
+    players.sort_by_key(|player| player.index);
+    let title = match (players.as_slice(), objective.reached_by) {
+        // Only set when the run was won.
+        ([_], Some(_)) => format!("Goal Reached in {}", format_time(elapsed.0)),
+        (_, Some(index)) => format!(
+            "{} Reaches the Goal in {}",
+            recorder.mode.player_name(index),
+            format_time(elapsed.0)
+        ),
+        ([player], _) => format!("Game Over — Food: {}", player.food),
+        ([one, two], _) => {
+            let winner = match result.losers.as_slice() {
+                [0] => format!("{} Wins", recorder.mode.player_name(1)),
+                [1] => format!("{} Wins", recorder.mode.player_name(0)),
+                _ => "Draw".to_string(),
+            };
+            format!("{} — Food: {} / {}", winner, one.food, two.food)
+        }
+        _ => "Game Over".to_string(),
+    };
+    // UI nodes are drawn by the UI camera after the sprite pass, so the
+    // overlay always ends up on top of the board.
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: bevy::prelude::Size::new(Val::Percent(100.), Val::Percent(100.)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: materials.add(Color::rgba(0., 0., 0., 0.6).into()),
+            ..Default::default()
+        })
+        .with(GameOverUi)
+        .with_children(|parent| {
+            parent.spawn(TextBundle {
+                text: Text {
+                    value: title,
+                    font: asset_server.load("fonts/DejaVuSans.ttf"),
+                    style: TextStyle {
+                        font_size: 40.0,
+                        color: Color::WHITE,
+                        ..Default::default()
+                    },
+                },
+                ..Default::default()
+            });
+            let tiles_per_food = stats
+                .tiles_per_food()
+                .map_or_else(|| "-".to_string(), |tiles| format!("{:.1}", tiles));
+            // How each snake that crashed went, named when there are two.
+            let mut lines: Vec<String> = result
+                .reasons
+                .iter()
+                .map(|(index, reason)| match players.len() {
+                    1 => reason.description().to_string(),
+                    _ => format!("{}: {}", recorder.mode.player_name(*index), reason.description()),
+                })
+                .collect();
+            lines.extend(vec![
+                format!("Time: {}", format_time(elapsed.0)),
+                format!(
+                    "Ticks: {}  Food Eaten: {}  Best Combo: x{}  Tiles per Food: {}",
+                    stats.ticks, stats.foods, stats.max_combo.max(1), tiles_per_food
+                ),
+                format!(
+                    "All Runs: {}  Food Eaten: {}  Best Combo: x{}",
+                    lifetime.runs, lifetime.foods, lifetime.max_combo.max(1)
+                ),
+            ]);
+            for line in lines.iter() {
+                parent.spawn(TextBundle {
+                    text: Text {
+                        value: line.clone(),
+                        font: asset_server.load("fonts/DejaVuSans.ttf"),
+                        style: TextStyle {
+                            font_size: 20.0,
+                            color: Color::WHITE,
+                            ..Default::default()
+                        },
+                    },
+                    ..Default::default()
+                });
+            }
+            parent.spawn(TextBundle {
+                text: Text {
+                    value: "Press Enter to Retry, Esc for the Menu".to_string(),
+                    font: asset_server.load("fonts/DejaVuSans.ttf"),
+                    style: TextStyle {
+                        font_size: 20.0,
+                        color: Color::WHITE,
+                        ..Default::default()
+                    },
+                },
+                ..Default::default()
+            });
+        });
+}
+
+fn pause_setup(
+    commands: &mut Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+    bindings: Res<KeyBindings>,
+) {
+    let lines = [
+        format!("Resume ({})", key_names(&[bindings.pause])),
+        format!("Restart ({})", key_names(&[bindings.replay])),
+        format!("Quit to Menu ({})", key_names(&[bindings.back])),
+    ];
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: bevy::prelude::Size::new(Val::Percent(100.), Val::Percent(100.)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: materials.add(Color::rgba(0., 0., 0., 0.6).into()),
+            ..Default::default()
+        })
+        .with(PauseUi)
+        .with_children(|parent| {
+            parent.spawn(TextBundle {
+                text: Text {
+                    value: "Paused".to_string(),
+                    font: asset_server.load("fonts/DejaVuSans.ttf"),
+                    style: TextStyle {
+                        font_size: 60.0,
+                        color: Color::WHITE,
+                        ..Default::default()
+                    },
+                },
+                ..Default::default()
+            });
+            for line in &lines {
+                parent.spawn(TextBundle {
+                    text: Text {
+                        value: line.clone(),
+                        font: asset_server.load("fonts/DejaVuSans.ttf"),
+                        style: TextStyle {
+                            font_size: 24.0,
+                            color: Color::WHITE,
+                            ..Default::default()
+                        },
+                    },
+                    ..Default::default()
+                });
+            }
+            parent
+                .spawn(TextBundle {
+                    text: Text {
+                        value: String::new(),
+                        font: asset_server.load("fonts/DejaVuSans.ttf"),
+                        style: TextStyle {
+                            font_size: 36.0,
+                            color: Color::rgb(1., 0.8, 0.2),
+                            ..Default::default()
+                        },
+                    },
+                    ..Default::default()
+                })
+                .with(QuitText);
+            parent
+                .spawn(TextBundle {
+                    text: Text {
+                        value: String::new(),
+                        font: asset_server.load("fonts/DejaVuSans.ttf"),
+                        style: TextStyle {
+                            font_size: 24.0,
+                            color: Color::rgb(1., 0.8, 0.2),
+                            ..Default::default()
+                        },
+                    },
+                    ..Default::default()
+                })
+                .with(GamepadLostText);
+        });
+}
+
+fn countdown_setup(
+    commands: &mut Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    asset_server: Res<AssetServer>,
+    countdown: Res<Countdown>,
+) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: bevy::prelude::Size::new(Val::Percent(100.), Val::Percent(100.)),
+                position_type: PositionType::Absolute,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: materials.add(Color::NONE.into()),
+            ..Default::default()
+        })
+        .with(CountdownUi)
+        .with_children(|parent| {
+            parent
+                .spawn(TextBundle {
+                    text: Text {
+                        value: countdown.label().to_string(),
+                        font: asset_server.load("fonts/DejaVuSans.ttf"),
+                        style: TextStyle {
+                            font_size: 120.0,
+                            color: Color::WHITE,
+                            ..Default::default()
+                        },
+                    },
+                    ..Default::default()
+                })
+                .with(CountdownText);
+        });
+}
+
+fn update_countdown_text(countdown: Res<Countdown>, mut texts: Query<&mut Text, With<CountdownText>>) {
+    for mut text in texts.iter_mut() {
+        text.value = countdown.label().to_string();
+    }
+}
+
+// Up and Down move the focus, wrapping around at the ends, and Enter picks
+// the focused button. Hovering a button with the mouse focuses it too, so
+// there's only ever one highlighted.
+fn menu_navigation(
+    keys: Res<Input<KeyCode>>,
+    mut selection: ResMut<MenuSelection>,
+    mut activated_events: ResMut<Events<MenuActivated>>,
+    items: Query<(Entity, &MenuItem)>,
+    hovered: Query<(&MenuItem, &Interaction), Mutated<Interaction>>,
+) {
+    let count = items.iter().count();
+    if count == 0 {
+        return;
+    }
+    for (item, interaction) in hovered.iter() {
+        if *interaction != Interaction::None {
+            selection.0 = item.0;
+        }
+    }
+    selection.0 %= count;
+    if keys.just_pressed(KeyCode::Up) {
+        selection.0 = (selection.0 + count - 1) % count;
+    }
+    if keys.just_pressed(KeyCode::Down) {
+        selection.0 = (selection.0 + 1) % count;
+    }
+    if keys.just_pressed(KeyCode::Return) {
+        if let Some((entity, _)) = items.iter().find(|(_, item)| item.0 == selection.0) {
+            activated_events.send(MenuActivated(entity));
+        }
+    }
+}
+
+fn menu_focus(
+    selection: Res<MenuSelection>,
+    button_materials: Res<ButtonMaterials>,
+    mut items: Query<(&MenuItem, &mut Handle<ColorMaterial>)>,
+) {
+    for (item, mut material) in items.iter_mut() {
+        *material = if item.0 == selection.0 {
+            button_materials.focused.clone()
+        } else {
+            button_materials.normal.clone()
+        };
+    }
+}
+
+// Only on a resize, or when new HUD text shows up.
+fn scale_hud_text(
+    windows: Res<Windows>,
+    resize_events: Res<Events<WindowResized>>,
+    mut resize_reader: Local<EventReader<WindowResized>>,
+    added: Query<Entity, Added<HudText>>,
+    mut texts: Query<&mut Text, With<HudText>>,
+) {
+    let resized = resize_reader.iter(&resize_events).count() > 0;
+    if !resized && added.iter().next().is_none() {
+        return;
+    }
+    let window = windows.get_primary().unwrap();
+    let font_size = (window.height() * HUD_FONT_SCALE)
+        .max(MIN_HUD_FONT_SIZE)
+        .min(MAX_HUD_FONT_SIZE);
+    for mut text in texts.iter_mut() {
+        text.style.font_size = font_size;
+    }
+}
+
+pub(crate) fn despawn_all<T: Component>(commands: &mut Commands, q: Query<Entity, With<T>>) {
+    for entity in q.iter() {
+        commands.despawn_recursive(entity);
+    }
+}
+
+// Only rewritten once the average has moved by `FPS_STEP`, so the text
+// doesn't flicker in the last digits every frame.
+const FPS_STEP: f64 = 0.5;
+
+fn update_fps(
+    diagnostics: Res<Diagnostics>,
+    show_fps: Res<ShowFps>,
+    mut shown: Local<Option<f64>>,
+    mut fps_text_q: Query<&mut Text, With<FpsText>>,
+) {
+    let mut text = fps_text_q.iter_mut().next().unwrap();
+    if !show_fps.0 {
+        if shown.take().is_some() {
+            text.value.clear();
+        }
+        return;
+    }
+    let average = match diagnostics.get(FrameTimeDiagnosticsPlugin::FPS).and_then(|fps| fps.average()) {
+        Some(average) => average,
+        None => return,
+    };
+    if shown.map_or(true, |shown| (average - shown).abs() >= FPS_STEP) {
+        *shown = Some(average);
+        text.value = format!("FPS: {:.2}", average);
+    }
+}
+fn update_difficulty_text(
+    gamestate: Res<State<GameState>>,
+    difficulty: Res<Difficulty>,
+    recorder: Res<Recorder>,
+    mut difficulty_text_q: Query<&mut Text, With<DifficultyText>>,
+) {
+    // A run keeps the difficulty it started with, even if the selection
+    // changes on the game over screen.
+    let difficulty = match gamestate.current() {
+        GameState::Menu | GameState::Lost | GameState::Won => *difficulty,
+        _ => recorder.difficulty,
+    };
+    let mut difficulty_text = difficulty_text_q.iter_mut().next().unwrap();
+    difficulty_text.value = format!("Difficulty: {}", difficulty.name());
+}
+
+fn update_run_time(
+    gamestate: Res<State<GameState>>,
+    time: Res<Time>,
+    mut elapsed: ResMut<ElapsedTime>,
+    mut time_text_q: Query<&mut Text, With<TimeText>>,
+) {
+    if matches!(gamestate.current(), GameState::Playing | GameState::Replay) {
+        elapsed.0 += time.delta_seconds();
+    }
+    let mut time_text = time_text_q.iter_mut().next().unwrap();
+    time_text.value = format_time(elapsed.0);
+}
+
+// mm:ss
+fn format_time(seconds: f32) -> String {
+    let seconds = seconds as u32;
+    format!("{:02}:{:02}", seconds / 60, seconds % 60)
+}
+
+// Checked every frame, but only rewritten when a score or the combo
+// actually changes.
+#[allow(clippy::type_complexity)]
+fn update_hud(
+    players: Query<&Player>,
+    combo: Res<Combo>,
+    mut shown: Local<Option<(Vec<(usize, u32)>, u32)>>,
+    mut food_text_q: Query<&mut Text, With<FoodText>>,
+) {
+    let mut scores: Vec<(usize, u32)> = players.iter().map(|player| (player.index, player.food)).collect();
+    scores.sort_unstable();
+    if shown.as_ref() == Some(&(scores.clone(), combo.multiplier)) {
+        return;
+    }
+    let mut food_text = food_text_q.iter_mut().next().unwrap();
+    food_text.value = match scores.as_slice() {
+        [(_, food)] => format!("Food: {}", food),
+        _ => scores
+            .iter()
+            .map(|(index, food)| format!("P{}: {}", index + 1, food))
+            .collect::<Vec<_>>()
+            .join("  "),
+    };
+    if combo.multiplier > 1 {
+        food_text.value += &format!("  Combo x{}", combo.multiplier);
+    }
+    *shown = Some((scores, combo.multiplier));
+}
+
+// Every frame rather than every tick: segments added or removed on a tick
+// only show up once that tick's commands have run.
+fn update_snake_length(
+    players: Query<(Entity, &Player)>,
+    segments: Query<&SnakeSegment>,
+    mut length_text_q: Query<&mut Text, With<SnakeLengthText>>,
+) {
+    let mut players: Vec<(Entity, &Player)> = players.iter().collect();
+    players.sort_by_key(|(_, player)| player.index);
+    let lengths: Vec<(usize, usize)> = players
+        .iter()
+        .map(|(head, player)| (player.index, snake_length(*head, &segments)))
+        .collect();
+    let mut length_text = length_text_q.iter_mut().next().unwrap();
+    length_text.value = match lengths.as_slice() {
+        [] => String::new(),
+        [(_, length)] => format!("Length: {}", length),
+        _ => lengths
+            .iter()
+            .map(|(index, length)| format!("P{} Length: {}", index + 1, length))
+            .collect::<Vec<_>>()
+            .join("  "),
+    };
+}
+
+fn update_practice_text(practice: ChangedRes<Practice>, mut practice_text_q: Query<&mut Text, With<PracticeText>>) {
+    for mut text in practice_text_q.iter_mut() {
+        text.value = if practice.0 { "PRACTICE".to_string() } else { String::new() };
+    }
+}
+
+// Only shown when walls grow at all.
+fn update_wall_count(growing: ChangedRes<GrowingWalls>, mut wall_count_text_q: Query<&mut Text, With<WallCountText>>) {
+    for mut text in wall_count_text_q.iter_mut() {
+        text.value = if growing.enabled() {
+            format!("Walls: {}", growing.count)
+        } else {
+            String::new()
+        };
+    }
+}
+
+fn update_daily_text(
+    daily: Res<Daily>,
+    lifetime: Res<LifetimeStats>,
+    mut daily_text_q: Query<&mut Text, With<DailyText>>,
+) {
+    let value = match daily.0 {
+        Some(day) => match lifetime.daily_best.get(&day) {
+            Some(best) => format!("Daily #{} — Best: {}", day, best),
+            None => format!("Daily #{}", day),
+        },
+        None => String::new(),
+    };
+    for mut text in daily_text_q.iter_mut() {
+        if text.value != value {
+            text.value = value.clone();
+        }
+    }
+}
+
+// Flashes for as long as someone's controls are swapped around.
+fn update_confusion_text(
+    time: Res<Time>,
+    inverted: Res<ControlsInverted>,
+    mut confusion_text_q: Query<&mut Text, With<ConfusionText>>,
+) {
+    let shown = inverted.owner.is_some() && (time.seconds_since_startup() * 4.) as u64 % 2 == 0;
+    let value = if shown { "⚠ Controls Inverted" } else { "" };
+    for mut text in confusion_text_q.iter_mut() {
+        if text.value != value {
+            text.value = value.to_string();
+        }
+    }
+}
+
+// The longest snake's length against the goal, when there is one.
+fn update_goal_text(
+    objective: Res<Objective>,
+    players: Query<Entity, With<Player>>,
+    segments: Query<&SnakeSegment>,
+    mut goal_text_q: Query<&mut Text, With<GoalText>>,
+) {
+    let value = match objective.target_len {
+        Some(target) => {
+            let longest = players.iter().map(|head| snake_length(head, &segments)).max().unwrap_or(0);
+            format!("Goal: {}/{}", longest, target)
+        }
+        None => String::new(),
+    };
+    for mut text in goal_text_q.iter_mut() {
+        if text.value != value {
+            text.value = value.clone();
+        }
+    }
+}
+
+fn update_frenzy_text(frenzy: Res<FrenzyActive>, mut frenzy_text_q: Query<&mut Text, With<FrenzyText>>) {
+    let value = if frenzy.on() { "Food Frenzy!" } else { "" };
+    for mut text in frenzy_text_q.iter_mut() {
+        if text.value != value {
+            text.value = value.to_string();
+        }
+    }
+}
+
+// The color each player needs next to finish a pair, in that color.
+fn update_pair_text(
+    pending: ChangedRes<PendingColor>,
+    palette: Res<Palette>,
+    mut pair_text_q: Query<&mut Text, With<PairText>>,
+) {
+    let pairs = palette.colors().pairs;
+    let waiting: Vec<(usize, usize)> = pending
+        .0
+        .iter()
+        .enumerate()
+        .filter_map(|(player, color)| color.map(|color| (player, color)))
+        .collect();
+    let value = match waiting.as_slice() {
+        [] => String::new(),
+        [(_, color)] => format!("Match: {}", pairs[*color].0),
+        _ => waiting
+            .iter()
+            .map(|(player, color)| format!("P{} Match: {}", player + 1, pairs[*color].0))
+            .collect::<Vec<_>>()
+            .join("  "),
+    };
+    for mut text in pair_text_q.iter_mut() {
+        text.value = value.clone();
+        if let Some((_, color)) = waiting.first() {
+            text.style.color = pairs[*color].1;
+        }
+    }
+}
+
+fn update_gamepad_lost_text(lost: Res<GamepadLost>, mut lost_text_q: Query<&mut Text, With<GamepadLostText>>) {
+    let value = if lost.0 { "Controller Disconnected" } else { "" };
+    for mut text in lost_text_q.iter_mut() {
+        if text.value != value {
+            text.value = value.to_string();
+        }
+    }
+}
+
+fn update_quit_text(confirm_quit: Res<ConfirmQuit>, mut quit_text_q: Query<&mut Text, With<QuitText>>) {
+    let value = if confirm_quit.0 { "Quit the Game? (Y/N)" } else { "" };
+    for mut text in quit_text_q.iter_mut() {
+        if text.value != value {
+            text.value = value.to_string();
+        }
+    }
+}